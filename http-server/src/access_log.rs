@@ -0,0 +1,61 @@
+//! Wrapper module for the [`AccessLog`] fairing
+
+use log::info;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::response::Body;
+use rocket::{Data, Request, Response};
+use std::time::Instant;
+
+use crate::util::client_ip;
+
+/// The log target used for access log lines, so that they can be distinguished from everything
+/// else
+static ACCESS_LOG_TARGET: &str = "access";
+
+/// Fairing that logs a line for every response: method, path, status, size, client IP, and the
+/// time taken to produce the response
+///
+/// Only attached if `access_log` is enabled in the config.
+pub struct AccessLog;
+
+/// Marker stored in each request's local cache so that we can measure how long it took
+struct RequestStart(Instant);
+
+impl Fairing for AccessLog {
+    fn info(&self) -> Info {
+        Info {
+            name: "Access Log",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        request.local_cache(|| RequestStart(Instant::now()));
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        let elapsed = request
+            .local_cache(|| RequestStart(Instant::now()))
+            .0
+            .elapsed();
+
+        // Streamed bodies don't have a known size until they're written out, so we just mark them
+        // with a dash (like most other access logs).
+        let size = match response.body() {
+            Some(Body::Sized(_, size)) => size.to_string(),
+            Some(Body::Chunked(_, _)) => "-".to_owned(),
+            None => "0".to_owned(),
+        };
+
+        info!(
+            target: ACCESS_LOG_TARGET,
+            "{} {} {} {}B ({}) in {:.1}ms",
+            request.method(),
+            request.uri(),
+            response.status().code,
+            size,
+            client_ip(request).as_deref().unwrap_or("unknown ip"),
+            elapsed.as_secs_f64() * 1000.0,
+        );
+    }
+}
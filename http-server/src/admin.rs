@@ -0,0 +1,123 @@
+//! Routes for administering the running server, all mounted under "/admin"
+//!
+//! Every route here requires the [`AdminAuth`] guard, which checks the request's bearer token
+//! against the `admin_token` in the config. If no token is configured, the routes act as if they
+//! don't exist.
+
+use log::{error, info};
+use rocket::http::Status;
+use rocket::request::{self, FromRequest};
+use rocket::response::status::Custom;
+use rocket::{post, Outcome, Request, State};
+use rocket_contrib::json::Json;
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::photos::{self, PhotoMeta};
+
+/// Request guard that only succeeds if the request has the correct `Authorization` header
+pub struct AdminAuth(());
+
+impl<'a, 'r> FromRequest<'a, 'r> for AdminAuth {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        let expected = match request.guard::<State<Config>>() {
+            Outcome::Success(config) => config.admin_token.clone(),
+            _ => return Outcome::Failure((Status::InternalServerError, ())),
+        };
+
+        let expected = match expected {
+            Some(t) => t,
+            None => return Outcome::Failure((Status::NotFound, ())),
+        };
+
+        let given = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|h| h.strip_prefix("Bearer "));
+
+        match given {
+            Some(t) if constant_time_eq(t.as_bytes(), expected.as_bytes()) => {
+                Outcome::Success(AdminAuth(()))
+            }
+            _ => Outcome::Failure((Status::Unauthorized, ())),
+        }
+    }
+}
+
+/// The body of an error response from one of the routes that otherwise returns JSON
+#[derive(Serialize)]
+pub struct ErrorResponse {
+    error: String,
+}
+
+impl ErrorResponse {
+    fn new(status: Status, error: String) -> Custom<Json<Self>> {
+        Custom(status, Json(ErrorResponse { error }))
+    }
+}
+
+/// Compares the two byte strings without exiting early, so that the time taken doesn't leak how
+/// much of the token was correct
+fn constant_time_eq(x: &[u8], y: &[u8]) -> bool {
+    if x.len() != y.len() {
+        return false;
+    }
+
+    x.iter().zip(y).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Triggers an update of the space-separated list of components in the body, exactly as if it
+/// were written to the update pipe
+///
+/// This is mostly useful for development (where there might not be a named pipe), but it works
+/// just the same in production.
+///
+/// The update runs on the request's thread, so the response isn't sent until it's finished -- which
+/// can take a while for the photos. That way, the status reflects whether it succeeded. Unknown
+/// components are rejected up front, without updating anything.
+#[post("/update", data = "<components>")]
+pub fn update(_auth: AdminAuth, components: String) -> Result<&'static str, Custom<String>> {
+    info!("received update request {:?} over HTTP", components);
+
+    if let Some(c) = crate::unknown_update_component(&components) {
+        let msg = format!("unrecognized update component {:?}\n", c);
+        return Err(Custom(Status::BadRequest, msg));
+    }
+
+    match crate::update_components(&components) {
+        0 => Ok("update complete\n"),
+        n => Err(Custom(
+            Status::InternalServerError,
+            format!("{} component(s) failed to update; see the logs\n", n),
+        )),
+    }
+}
+
+/// Re-processes a single photo from disk, returning its new metadata
+///
+/// This is much quicker than a full update of the photos, for iterating on a photo's description
+/// or alt text. See `photos::reprocess` for the changes it can't handle.
+#[post("/photos/reprocess/<name>")]
+pub fn reprocess_photo(
+    _auth: AdminAuth,
+    name: String,
+) -> Result<Json<PhotoMeta>, Custom<Json<ErrorResponse>>> {
+    info!("received request to reprocess photo {:?}", name);
+
+    match photos::reprocess(&name) {
+        Ok(Some(meta)) => Ok(Json(meta)),
+        Ok(None) => Err(ErrorResponse::new(
+            Status::NotFound,
+            format!("no photo named {:?}", name),
+        )),
+        Err(e) => {
+            error!("{:#}", e);
+            Err(ErrorResponse::new(
+                Status::InternalServerError,
+                format!("{:#}", e),
+            ))
+        }
+    }
+}
@@ -5,20 +5,36 @@
 
 use anyhow::{anyhow, bail, Context, Result};
 use arc_swap::ArcSwap;
-use chrono::{offset::FixedOffset, DateTime};
+use chrono::{offset::FixedOffset, DateTime, Datelike, Duration, Utc};
 use glob::glob;
 use lazy_static::lazy_static;
-use rocket::get;
+use log::{error, warn};
+use rayon::prelude::*;
+use regex::Regex;
+use rocket::http::uri::Uri;
+use rocket::http::{ContentType, Header, Status};
+use rocket::response::content::Html;
+use rocket::response::{self, Content, Responder, Response};
+use rocket::{get, post, uri, Request};
+use rocket_contrib::json::Json;
 use rocket_contrib::templates::Template;
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::process::exit;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
-use crate::util::{format_datetime, is_uri_idempotent, markdown_to_html, FormatLevel};
+use crate::config::BlogConfig;
+use crate::photos;
+use crate::static_assets;
+use crate::util::{
+    content_hash, etag, find_more_marker, format_datetime, format_relative_datetime, http_date,
+    is_absolute_url, is_uri_idempotent, json_ld::BlogPosting, markdown_to_html,
+    markdown_to_html_with_images, markdown_to_plain_text, render_og_image, slugify, FormatLevel,
+    IfModifiedSince, IfNoneMatch, MarkdownKind, MaybeRedirect, SearchIndex, SITE_URL,
+};
 
 /// Helper macro so that mounting the routes will work correctly at the crate root
 macro_rules! blog_routes {
@@ -26,8 +42,15 @@ macro_rules! blog_routes {
         rocket::routes![
             crate::blog::index,
             crate::blog::planned_posts,
+            crate::blog::archive,
             crate::blog::post,
+            crate::blog::print_post,
+            crate::blog::og_image,
             crate::blog::tag,
+            crate::blog::author,
+            crate::blog::api_posts,
+            crate::blog::api_tags,
+            crate::blog::search,
         ]
     }};
 }
@@ -36,45 +59,83 @@ macro_rules! blog_routes {
 static INDEX_TEMPLATE_NAME: &str = "blog/index";
 /// Name of the template used for displaying upcoming posts
 static PLANNED_POSTS_TEMPLATE_NAME: &str = "blog/planned";
+/// Name of the template used for the posts grouped by year and month (at "/blog/archive")
+static ARCHIVE_TEMPLATE_NAME: &str = "blog/archive";
 /// Name of the template used for individual blog posts (at "/blog/<post_name>")
 static POST_TEMPLATE_NAME: &str = "blog/post";
+/// Name of the template used for the print-friendly versions of blog posts (at
+/// "/blog/<post_name>/print")
+static PRINT_POST_TEMPLATE_NAME: &str = "blog/post-print";
 /// Name of the template used for displaying the values in a tag (at "/blog/tag/<tag_name>")
 static TAGS_TEMPLATE_NAME: &str = "blog/tag";
+/// Name of the template used for displaying the posts by an author (at "/blog/author/<slug>")
+static AUTHOR_TEMPLATE_NAME: &str = "blog/author";
 
-/// Directory that the blog posts are stored in, relative to the source root
-static BLOG_POSTS_DIRECTORY: &str = "content/blog-posts";
 /// Glob to match the markdown document responsible for each post
 static BLOG_GLOB: &str = "*.md";
-/// The file in `BLOG_POSTS_DIRECTORY` that houses information about planned posts
+/// The file in the blog posts directory that houses information about planned posts
 static PLANNED_POSTS_META_FILE: &str = "planned-posts.json";
 
 /// Minimum number of markdown bytes to include in a post sneak peek
 const MIN_SNEAK_PEEK_AMOUNT: usize = 100;
 
+/// Maximum number of results returned by a single search
+const MAX_SEARCH_RESULTS: usize = 20;
+
+/// Number of related posts to show at the end of each post
+const NUM_RELATED_POSTS: usize = 3;
+
+/// The number of days after publishing during which a post also shows how long ago it was published
+const RECENT_POST_DAYS: i64 = 30;
+
+// Weights of the different parts of a post in search results. Matching the title is worth a lot
+// more than matching the body.
+const SEARCH_TITLE_WEIGHT: u32 = 10;
+const SEARCH_TAG_WEIGHT: u32 = 5;
+const SEARCH_DESCRIPTION_WEIGHT: u32 = 3;
+const SEARCH_BODY_WEIGHT: u32 = 1;
+
 lazy_static! {
+    /// Configuration for the blog, set by `initialize`
+    static ref CONFIG: ArcSwap<BlogConfig> = ArcSwap::from(Arc::new(BlogConfig::default()));
+
     /// Global state of the blog information
-    static ref STATE: ArcSwap<BlogState> = match BlogState::new() {
+    static ref STATE: ArcSwap<BlogState> = match BlogState::new(&CONFIG.load()) {
         Ok(s) => ArcSwap::from(Arc::new(s)),
         Err(e) => {
-            eprintln!("failed to create `BlogState`: {:#}", e);
+            error!("failed to create `BlogState`: {:#}", e);
             exit(1)
         }
     };
+
+    /// Cache of the rendered Open Graph images, keyed by the content hash of the post
+    static ref OG_IMAGE_CACHE: Mutex<HashMap<String, Arc<[u8]>>> = Mutex::new(HashMap::new());
 }
 
-/// Collects all of the necessary information about the state of the blog, causing any failures to
-/// happen immediately
+/// Sets the configuration for the blog and -- if `load_now` -- collects all of the necessary
+/// information about the state of the blog, causing any failures to happen immediately
 ///
-/// Any failures encountered will result in an immediate exit.
-pub fn initialize() {
-    lazy_static::initialize(&STATE);
+/// Any failures encountered will result in an immediate exit. Otherwise, the state is only
+/// collected when it's first needed, so that startup is quick.
+pub fn initialize(config: &BlogConfig, load_now: bool) {
+    CONFIG.store(Arc::new(config.clone()));
+
+    if load_now {
+        lazy_static::initialize(&STATE);
+    }
 }
 
 /// Re-makes the `BlogState` to incorporate any recent file changes
 pub fn update() -> Result<()> {
     // Blog stuff is relatively cheap; we can afford to just recalculate the entire state whenever
     // there's a change.
-    let new_state = BlogState::new()?;
+    let new_state = BlogState::new(&CONFIG.load())?;
+
+    // Drop any cached images for posts that have changed (or no longer exist)
+    OG_IMAGE_CACHE
+        .lock()
+        .unwrap()
+        .retain(|hash, _| new_state.files.values().any(|p| &p.content_hash == hash));
 
     STATE.store(Arc::new(new_state));
 
@@ -93,102 +154,569 @@ pub fn planned_posts() -> Template {
     Template::render(PLANNED_POSTS_TEMPLATE_NAME, ctx)
 }
 
+/// Every post, grouped by the year and month it was published
+#[get("/archive")]
+pub fn archive() -> Template {
+    let ctx = STATE.load().archive_context();
+    Template::render(ARCHIVE_TEMPLATE_NAME, ctx)
+}
+
 #[get("/<post_name>")]
-pub fn post(post_name: Cow<str>) -> Option<Template> {
+pub fn post(
+    post_name: Cow<str>,
+    if_none_match: IfNoneMatch,
+    if_modified_since: IfModifiedSince,
+) -> Option<MaybeRedirect<PostPage>> {
+    post_page(&post_name, false, if_none_match, if_modified_since)
+}
+
+/// The post without any of the surrounding navigation or code highlighting, for printing or saving
+/// as a PDF
+#[get("/<post_name>/print")]
+pub fn print_post(
+    post_name: Cow<str>,
+    if_none_match: IfNoneMatch,
+    if_modified_since: IfModifiedSince,
+) -> Option<MaybeRedirect<PostPage>> {
+    post_page(&post_name, true, if_none_match, if_modified_since)
+}
+
+/// Shared implementation of the `post` and `print_post` routes
+fn post_page(
+    post_name: &str,
+    print: bool,
+    if_none_match: IfNoneMatch,
+    if_modified_since: IfModifiedSince,
+) -> Option<MaybeRedirect<PostPage>> {
     assert!(!post_name.is_empty());
 
-    let ctx = STATE.load().post_context(&*post_name)?;
-    Some(Template::render(POST_TEMPLATE_NAME, ctx))
+    let state = STATE.load();
+
+    // Old names for posts permanently redirect to the current one
+    if let Some(canonical) = state.aliases.get(Path::new(post_name)) {
+        let canonical = canonical.to_string_lossy();
+        let new_url = if print {
+            uri!("/blog", print_post: canonical)
+        } else {
+            uri!("/blog", post: canonical)
+        };
+        return Some(MaybeRedirect::Redirect {
+            new_url,
+            is_permanent: true,
+        });
+    }
+
+    let post = state.post_context(post_name)?;
+
+    // Relative times are only interesting for recent posts, and they depend on the current time,
+    // so they're added here instead of when the post is loaded.
+    let published_relative = relative_if_recent(post.meta.published_datetime, Utc::now());
+
+    // The related posts come from the rest of the blog, so they can change without this post
+    // changing: the page is as new as the most recently updated post, and the list is part of the
+    // ETag.
+    let related = state
+        .related
+        .get(&post.meta.path)
+        .cloned()
+        .unwrap_or_default();
+    let page_updated = (state.by_updated_time.first())
+        .map_or(post.meta.updated_unix_time, |p| p.meta.updated_unix_time)
+        .max(post.meta.updated_unix_time);
+    let base_hash = content_hash(format!("{}{:?}", post.content_hash, related).as_bytes());
+
+    // The relative time changes the page without the post itself changing, so it has to be part
+    // of the ETag. For the same reason, there's no sensible `Last-Modified` while it's shown.
+    let hash = match &published_relative {
+        None => base_hash,
+        Some(rel) => content_hash(format!("{}{}", base_hash, rel).as_bytes()),
+    };
+    let last_modified = match published_relative {
+        None => Some(page_updated),
+        Some(_) => None,
+    };
+
+    // `If-Modified-Since` is only used if there's no `If-None-Match`, as required by RFC 7232
+    let not_modified = if if_none_match.is_present() {
+        if_none_match.matches(&hash)
+    } else {
+        last_modified.is_some_and(|t| if_modified_since.is_unmodified(t))
+    };
+
+    let template = (!not_modified).then(|| {
+        let ctx = PostPageContext {
+            json_ld: post.json_ld.clone(),
+            has_og_image: CONFIG.load().og_image_font.is_some(),
+            post,
+            published_relative,
+            related,
+        };
+        let name = if print {
+            PRINT_POST_TEMPLATE_NAME
+        } else {
+            POST_TEMPLATE_NAME
+        };
+        Template::render(name, ctx)
+    });
+
+    Some(MaybeRedirect::Dont(PostPage {
+        template,
+        hash,
+        last_modified,
+    }))
+}
+
+/// Formats the post's publication time relative to `now`, if it was published within the last
+/// `RECENT_POST_DAYS` days
+fn relative_if_recent(published: DateTime<FixedOffset>, now: DateTime<Utc>) -> Option<String> {
+    (now.signed_duration_since(published) < Duration::days(RECENT_POST_DAYS))
+        .then(|| format_relative_datetime(published, now))
+}
+
+/// A post's page, with the validators for conditional requests
+pub struct PostPage {
+    /// The rendered page, or `None` if the client already has the current version -- which
+    /// produces a `304 Not Modified`
+    template: Option<Template>,
+    /// Hash of the page's content, for the `ETag`
+    hash: String,
+    /// Unix timestamp for the `Last-Modified` header, if there should be one
+    last_modified: Option<i64>,
+}
+
+impl<'r> Responder<'r> for PostPage {
+    fn respond_to(self, req: &Request) -> response::Result<'r> {
+        let mut resp = match self.template {
+            Some(t) => t.respond_to(req)?,
+            None => Response::build().status(Status::NotModified).finalize(),
+        };
+
+        resp.set_header(Header::new("ETag", etag(&self.hash)));
+        if let Some(t) = self.last_modified {
+            resp.set_header(Header::new("Last-Modified", http_date(t)));
+        }
+
+        Ok(resp)
+    }
 }
 
+/// Serves the Open Graph preview image for the post, rendering it if it isn't already cached
+#[get("/<post_name>/og-image.png")]
+pub fn og_image(post_name: Cow<str>) -> Result<Content<Vec<u8>>, Status> {
+    let post = STATE
+        .load()
+        .post_context(&*post_name)
+        .ok_or(Status::NotFound)?;
+
+    if let Some(png) = OG_IMAGE_CACHE.lock().unwrap().get(&post.content_hash) {
+        return Ok(Content(ContentType::PNG, png.to_vec()));
+    }
+
+    let font_path = CONFIG
+        .load()
+        .og_image_font
+        .clone()
+        .ok_or(Status::NotFound)?;
+
+    // We don't hold the lock while rendering, so the same image might occasionally be rendered
+    // twice. That's fine; the result is the same either way.
+    let png =
+        render_og_image(&font_path, &post.meta.title, &post.plain_description).map_err(|e| {
+            error!(
+                "failed to render OG image for post {:?}: {:#}",
+                post_name, e
+            );
+            Status::InternalServerError
+        })?;
+
+    OG_IMAGE_CACHE
+        .lock()
+        .unwrap()
+        .insert(post.content_hash.clone(), Arc::from(png.as_slice()));
+
+    Ok(Content(ContentType::PNG, png))
+}
+
+/// Lists the posts with a tag, given its normalized form (see `Tag::key`)
+///
+/// Any other form of the tag -- like "Rust" instead of "rust" -- redirects to the normalized one.
 #[get("/tag/<tag>")]
-pub fn tag(tag: String) -> Option<Template> {
-    let ctx = STATE.load().tag_context(&tag)?;
-    Some(Template::render(TAGS_TEMPLATE_NAME, ctx))
+pub fn tag(tag: String) -> Option<MaybeRedirect<Template>> {
+    let key = normalize_tag(&tag);
+    let ctx = STATE.load().tag_context(&key)?;
+
+    if key != tag {
+        return Some(MaybeRedirect::Redirect {
+            new_url: uri!("/blog", tag: key),
+            is_permanent: true,
+        });
+    }
+
+    Some(MaybeRedirect::Dont(Template::render(
+        TAGS_TEMPLATE_NAME,
+        ctx,
+    )))
+}
+
+/// Lists the posts by an author, given the slug of their name (see `PostMeta::author_slug`)
+#[get("/author/<author>")]
+pub fn author(author: String) -> Option<Template> {
+    let ctx = STATE.load().author_context(&author)?;
+    Some(Template::render(AUTHOR_TEMPLATE_NAME, ctx))
+}
+
+/// JSON listing of all of the (non-hidden) posts, newest first
+///
+/// Results can be filtered to a single tag with `?tag=<tag>` and capped with `?limit=N`. The full
+/// HTML of each post is only included if `?full=true` is given.
+#[get("/api/posts?<tag>&<limit>&<full>")]
+pub fn api_posts(
+    tag: Option<String>,
+    limit: Option<usize>,
+    full: Option<bool>,
+) -> Option<Json<Vec<ApiPost>>> {
+    let state = STATE.load();
+
+    let posts = match &tag {
+        Some(t) => state.tags.get(&normalize_tag(t))?,
+        None => &state.by_time,
+    };
+
+    let full = full.unwrap_or(false);
+    let list = posts
+        .values()
+        .rev()
+        .take(limit.unwrap_or(usize::MAX))
+        .map(|p| ApiPost::new(p, full))
+        .collect();
+
+    Some(Json(list))
+}
+
+/// JSON listing of every tag with the number of posts that have it, most common first
+#[get("/api/tags")]
+pub fn api_tags() -> Json<Vec<TagCount>> {
+    Json(STATE.load().tag_counts())
+}
+
+/// Full-text search over the titles, descriptions, tags, and bodies of all of the posts
+///
+/// Returns a JSON array of the results, best match first. An empty (or missing) query gives no
+/// results.
+#[get("/search?<q>")]
+pub fn search(q: Option<String>) -> Json<Vec<SearchResultInfo>> {
+    let state = STATE.load();
+    let query = q.unwrap_or_default();
+
+    let results = state
+        .search_index
+        .search(&query, MAX_SEARCH_RESULTS)
+        .into_iter()
+        .filter_map(|r| {
+            let post = state.files.get(r.key)?;
+            Some(SearchResultInfo {
+                path: post.meta.path.clone(),
+                title: post.meta.title.clone(),
+                snippet: r.snippet,
+            })
+        })
+        .collect();
+
+    Json(results)
+}
+
+/// Renders the markdown in the request body exactly as it would be in a post, returning the HTML
+/// fragment
+///
+/// This is only for previewing posts while writing them, so it isn't in `blog_routes!`; it's only
+/// mounted in debug builds.
+#[post("/preview", data = "<markdown>")]
+pub fn preview(markdown: String) -> Html<String> {
+    Html(markdown_to_html_with_images(
+        &markdown,
+        MarkdownKind::Document,
+        &photos::markdown_image,
+    ))
 }
 
-pub fn recent_posts_context() -> Vec<Arc<PostContext>> {
-    STATE.load().recent_posts_context()
+/// Returns the `limit` most recently published posts, newest first
+pub fn recent_posts_context(limit: usize) -> Vec<Arc<PostContext>> {
+    STATE.load().recent_posts_context(limit)
+}
+
+/// Returns the number of blog posts currently loaded
+pub fn num_posts() -> usize {
+    STATE.load().files.len()
+}
+
+/// Returns the names of every tag, sorted by number of posts, then alphabetically
+pub fn tag_names() -> Vec<String> {
+    (STATE.load().tags_sorted.iter())
+        .map(|(tag, _)| tag.name.clone())
+        .collect()
+}
+
+/// Returns when the blog posts were last loaded from disk
+pub fn generated_at() -> DateTime<Utc> {
+    STATE.load().generated_at
+}
+
+/// Returns whether there's a post at the path -- either its current one, or an old name for it
+pub fn post_exists(path: &str) -> bool {
+    let state = STATE.load();
+    let path = Path::new(path);
+    state.files.contains_key(path) || state.aliases.contains_key(path)
+}
+
+/// Returns the rendered HTML of every post's description and body, each labelled with where it's
+/// from, for `crate::links`
+pub fn rendered_html() -> Vec<(String, String)> {
+    STATE
+        .load()
+        .files
+        .values()
+        .map(|post| {
+            let html = format!("{}\n{}", post.meta.description, post.html_body_content);
+            (format!("blog post {:?}", post.meta.path), html)
+        })
+        .collect()
 }
 
 impl BlogState {
     /// Creates the `BlogState`, returning any error if applicable
-    fn new() -> Result<Self> {
-        let planned_posts = PlannedPostsInfo::read()
+    fn new(config: &BlogConfig) -> Result<Self> {
+        let planned_posts = PlannedPostsInfo::read(&config.posts_dir)
             .map(Arc::new)
             .context("couldn't read planned posts")?;
 
         let mut files = HashMap::new();
+        // The source file for each post path, so that we can report conflicts
+        let mut source_files: HashMap<PathBuf, PathBuf> = HashMap::new();
 
         let mut by_time = BTreeMap::new();
         let mut tags: HashMap<String, BTreeMap<_, _>> = HashMap::new();
+        let mut authors: HashMap<String, BTreeMap<_, _>> = HashMap::new();
 
         // Each blog post exists as a separate markdown file in the blogs directory
-        let glob_pat = format!("{}/{}", BLOG_POSTS_DIRECTORY, BLOG_GLOB);
-        for glob_result in glob(&glob_pat).expect("failed to read glob pattern") {
-            let file_path = glob_result.context("failed to get glob item for blog posts")?;
+        let glob_pat = format!("{}/{}", config.posts_dir.display(), BLOG_GLOB);
+        let file_paths = glob(&glob_pat)
+            .expect("failed to read glob pattern")
+            .map(|r| r.context("failed to get glob item for blog posts"))
+            .collect::<Result<Vec<_>>>()?;
 
-            let file_name: PathBuf = file_path
-                .file_prefix()
-                .expect("expected glob result to have file name")
-                .into();
+        // Parsing a post can require a bunch of round-trips to the highlight server, so we
+        // parse them all in parallel. Everything after this is cheap, and done in order so that
+        // conflicts are reported consistently.
+        let parsed_posts = file_paths
+            .into_par_iter()
+            .map(|file_path| {
+                let file_name: PathBuf = file_path
+                    .file_prefix()
+                    .expect("expected glob result to have file name")
+                    .into();
 
-            if !is_uri_idempotent(&file_name.to_string_lossy()) {
-                bail!(
-                    "bad entry file name {:?}: must URI encode to the same value",
-                    file_path.file_name().unwrap()
-                );
-            }
+                let info = fs::read_to_string(&file_path)
+                    .context("could not read to string")
+                    .and_then(|c| PostContext::from_file_content(config, &file_name, &c))
+                    .with_context(|| format!("could not parse file {:?}", file_path))?;
 
-            let info: Arc<_> = fs::read_to_string(&file_path)
-                .context("could not read to string")
-                .and_then(|c| PostContext::from_file_content(&file_name, &c))
-                .with_context(|| format!("could not parse file {:?}", file_name))?
-                .into();
+                Ok((file_path, Arc::new(info)))
+            })
+            .collect::<Result<Vec<_>>>()?;
 
+        for (file_path, info) in parsed_posts {
             if info.meta.is_hidden {
                 continue;
             }
 
+            // The post's path comes from either its slug or the file name
+            let post_path = &info.meta.path;
+            let path_str = post_path.to_string_lossy();
+            if path_str.is_empty() || !is_uri_idempotent(&path_str) {
+                bail!(
+                    "bad path {:?} for file {:?}: must be non-empty and URI encode to itself",
+                    post_path,
+                    file_path
+                );
+            }
+
+            if let Some(existing) = source_files.insert(post_path.clone(), file_path.clone()) {
+                bail!(
+                    "files {:?} and {:?} have the same path {:?}",
+                    existing,
+                    file_path,
+                    post_path
+                );
+            }
+
             // Add info to the blog state
             let time = info.meta.published_unix_time;
 
             by_time.insert(time, info.clone());
             for t in &info.meta.tags {
-                tags.entry(t.to_owned())
+                tags.entry(t.key.clone())
                     .or_default()
                     .insert(time, info.clone());
             }
+            authors
+                .entry(info.meta.author_slug.clone())
+                .or_default()
+                .insert(time, info.clone());
+
+            files.insert(info.meta.path.clone(), info);
+        }
+
+        // Aliases are checked after all of the posts have been collected so that we can detect
+        // conflicts with any of them.
+        let mut aliases = HashMap::new();
+        for info in files.values() {
+            for alias in &info.meta.aliases {
+                let alias_str = alias.to_string_lossy();
+                if alias_str.is_empty() || !is_uri_idempotent(&alias_str) {
+                    bail!(
+                        "bad alias {:?} for post {:?}: must be non-empty and URI encode to itself",
+                        alias,
+                        info.meta.path
+                    );
+                } else if files.contains_key(alias) {
+                    bail!(
+                        "alias {:?} for post {:?} conflicts with an existing post",
+                        alias,
+                        info.meta.path
+                    );
+                }
 
-            files.insert(file_name, info);
+                if let Some(other) = aliases.insert(alias.clone(), info.meta.path.clone()) {
+                    bail!(
+                        "alias {:?} is used by both posts {:?} and {:?}",
+                        alias,
+                        other,
+                        info.meta.path
+                    );
+                }
+            }
         }
 
         let mut tags_sorted = tags
             .iter()
-            .map(|(name, set)| (name.clone(), set.clone()))
+            .map(|(key, set)| (Self::tag_with_key(key, set), set.clone()))
             .collect::<Vec<_>>();
 
-        tags_sorted.sort_by(|(x_name, x_set), (y_name, y_set)| {
+        tags_sorted.sort_by(|(x_tag, x_set), (y_tag, y_set)| {
             x_set
                 .len()
                 .cmp(&y_set.len())
-                .then_with(|| x_name.cmp(&y_name))
+                .then_with(|| x_tag.key.cmp(&y_tag.key))
         });
 
+        // Related posts depend on all of the others, so they can only be found once everything's
+        // been loaded
+        let related = (files.values())
+            .map(|info| {
+                let posts = Self::related_posts(info, &tags, &by_time);
+                (info.meta.path.clone(), posts)
+            })
+            .collect();
+
+        // Ties are broken by the publish time, so that the order is stable
+        let mut by_updated_time: Vec<_> = by_time.values().cloned().rev().collect();
+        by_updated_time.sort_by_key(|p| std::cmp::Reverse(p.meta.updated_unix_time));
+
+        let mut search_index = SearchIndex::new();
+        for info in files.values() {
+            let tags = (info.meta.tags.iter())
+                .map(|t| t.name.as_str())
+                .collect::<Vec<_>>()
+                .join(" ");
+            let description =
+                markdown_to_plain_text(&info.plain_description, MarkdownKind::Snippet);
+            let fields = [
+                (info.meta.title.as_str(), SEARCH_TITLE_WEIGHT),
+                (tags.as_str(), SEARCH_TAG_WEIGHT),
+                (description.as_str(), SEARCH_DESCRIPTION_WEIGHT),
+                (info.plain_text_body.as_str(), SEARCH_BODY_WEIGHT),
+            ];
+
+            search_index.add(
+                info.meta.path.clone(),
+                &fields,
+                info.plain_text_body.clone(),
+            );
+        }
+
         Ok(BlogState {
             files,
+            aliases,
+            search_index,
             tags,
             tags_sorted,
+            authors,
             by_time,
+            by_updated_time,
+            related,
             planned_posts,
+            generated_at: Utc::now(),
         })
     }
+
+    /// Returns the tag with the key, as it's written by the most recent of its posts
+    ///
+    /// Different posts can write the same tag differently (e.g. "Rust" and "rust"), so this gives
+    /// a single name to display for it.
+    fn tag_with_key(key: &str, posts: &BTreeMap<i64, Arc<PostContext>>) -> Tag {
+        (posts.values().next_back())
+            .and_then(|p| p.meta.tags.iter().find(|t| t.key == key))
+            .cloned()
+            .expect("tag should be from one of its posts")
+    }
+
+    /// Returns the posts that share the most tags with `post`, with ties broken by recency
+    ///
+    /// If there aren't enough posts with any tags in common (or `post` doesn't have any tags), the
+    /// rest are filled in with the most recent other posts.
+    fn related_posts(
+        post: &PostContext,
+        tags: &HashMap<String, BTreeMap<i64, Arc<PostContext>>>,
+        by_time: &BTreeMap<i64, Arc<PostContext>>,
+    ) -> Vec<PostSummary> {
+        // Published time -> (number of shared tags, post)
+        let mut shared: HashMap<i64, (usize, &Arc<PostContext>)> = HashMap::new();
+        for t in &post.meta.tags {
+            for (time, other) in &tags[&t.key] {
+                if other.meta.path != post.meta.path {
+                    shared.entry(*time).or_insert((0, other)).0 += 1;
+                }
+            }
+        }
+
+        let mut ranked: Vec<_> = shared.into_iter().collect();
+        ranked.sort_by_key(|(time, (count, _))| std::cmp::Reverse((*count, *time)));
+
+        let most_shared = ranked.into_iter().map(|(_, (_, p))| p);
+        let most_recent = by_time
+            .values()
+            .rev()
+            .filter(|p| p.meta.path != post.meta.path);
+
+        let mut related: Vec<PostSummary> = Vec::new();
+        for p in most_shared.chain(most_recent) {
+            if related.len() == NUM_RELATED_POSTS {
+                break;
+            } else if related.iter().any(|r| r.path == p.meta.path) {
+                continue;
+            }
+
+            related.push(PostSummary::new(p));
+        }
+
+        related
+    }
 }
 
 impl PlannedPostsInfo {
     /// Reads the information on planned posts from the JSON file in the posts directory
-    fn read() -> Result<Self> {
-        let file_path = Path::new(BLOG_POSTS_DIRECTORY).join(PLANNED_POSTS_META_FILE);
+    fn read(posts_dir: &Path) -> Result<Self> {
+        let file_path = posts_dir.join(PLANNED_POSTS_META_FILE);
 
         let file_content = fs::read_to_string(&file_path)
             .with_context(|| format!("could not file {:?} to string", file_path))?;
@@ -197,10 +725,10 @@ impl PlannedPostsInfo {
             .with_context(|| format!("failed to parse `Vec<PlannedPost>` in file {:?}", file_path))
             .map(|mut planned: Self| {
                 // Most of the fields are meant to be markdown; we need to process them:
-                planned.intro = markdown_to_html(&planned.intro);
+                planned.intro = markdown_to_html(&planned.intro, MarkdownKind::Snippet);
 
                 for p in planned.posts.iter_mut() {
-                    p.description = markdown_to_html(&p.description);
+                    p.description = markdown_to_html(&p.description, MarkdownKind::Snippet);
                 }
 
                 planned
@@ -209,7 +737,36 @@ impl PlannedPostsInfo {
 }
 
 impl PostContext {
-    fn from_file_content(path: &Path, content: &str) -> Result<Self> {
+    /// Returns the path of the post, relative to "/blog"
+    pub fn path(&self) -> &Path {
+        &self.meta.path
+    }
+
+    /// Returns the title of the post
+    pub fn title(&self) -> &str {
+        &self.meta.title
+    }
+
+    /// Returns the post's description, as HTML
+    pub fn description(&self) -> &str {
+        &self.meta.description
+    }
+
+    /// Returns the time at which the post was first published
+    pub fn published_datetime(&self) -> DateTime<FixedOffset> {
+        self.meta.published_datetime
+    }
+
+    /// Returns whether the post is hidden, and so shouldn't be listed anywhere
+    pub fn is_hidden(&self) -> bool {
+        self.meta.is_hidden
+    }
+
+    /// Parses the post from the content of its file
+    ///
+    /// `file_name` is used as the post's path only if the header doesn't provide a `slug`. Any
+    /// includes in the body are expanded relative to the posts directory (see [`expand_includes`]).
+    fn from_file_content(config: &BlogConfig, file_name: &Path, content: &str) -> Result<Self> {
         // Split the string into the header & body:
         //
         // The header exists until the first line that equals '+++'. So we can just directly split
@@ -218,16 +775,40 @@ impl PostContext {
             .split_once("\n+++\n")
             .ok_or_else(|| anyhow!("file must include '\\n+++\\n' to split header & body"))?;
 
+        let body = expand_includes(&config.posts_dir, body, &mut Vec::new())?;
+        let body = body.as_str();
+
         // We just parse the top of the file as TOML
         #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
         struct ParsedMeta {
             title: String,
+            slug: Option<String>,
+            #[serde(default)]
+            aliases: Vec<String>,
             tab_title: Option<String>,
             description: String,
             first_published: ParsedDateTime,
             updated: Vec<ParsedDateTime>,
             tags: Vec<String>,
+            author: Option<String>,
             is_hidden: bool,
+            #[serde(default)]
+            extra_css: Vec<String>,
+            #[serde(default)]
+            extra_js: Vec<String>,
+            sneak_peek: Option<ParsedSneakPeek>,
+            canonical_url: Option<String>,
+            cover_image: Option<String>,
+        }
+
+        /// Override for the sneak peek: either the number of paragraphs from the body to use, or
+        /// the markdown to use instead
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum ParsedSneakPeek {
+            Paragraphs(usize),
+            Excerpt(String),
         }
 
         #[derive(Deserialize)]
@@ -235,68 +816,260 @@ impl PostContext {
         struct ParsedDateTime(DateTime<FixedOffset>);
 
         impl TryFrom<String> for ParsedDateTime {
-            type Error = chrono::ParseError;
+            type Error = String;
 
-            fn try_from(s: String) -> chrono::ParseResult<Self> {
-                DateTime::parse_from_rfc2822(&s).map(ParsedDateTime)
+            fn try_from(s: String) -> Result<Self, String> {
+                DateTime::parse_from_rfc2822(&s)
+                    .map(ParsedDateTime)
+                    .map_err(|e| format!("invalid RFC 2822 date {:?} ({})", s, e))
             }
         }
 
-        let parsed: ParsedMeta = toml::from_str(header).context("failed to parse header")?;
+        // The header starts at the top of the file, so toml's line numbers are the same as the
+        // file's.
+        let parsed: ParsedMeta = toml::from_str(header).map_err(|e| {
+            let msg = match e.line_col() {
+                Some((line, col)) => format!(
+                    "failed to parse header at line {}, column {}",
+                    line + 1,
+                    col + 1
+                ),
+                None => "failed to parse header".to_owned(),
+            };
+            anyhow::Error::new(e).context(msg)
+        })?;
 
-        // Figure out how much to show as a sneak peek for the blog post. We *could* do this
-        // semantically by the parsed markdown, but directly going off of the byte sizes is just
-        // easier.
-        //
-        // Essentially what we're doing is getting enough paragraphs of input so that there's at
-        // least MIN_SNEAK_PEEK_AMOUNT bytes of raw markdown represented.
-        let sneak_peek_amount = body
-            // Double newline signifies a new paragraph -- usually.
+        // Byte offsets of the end of each paragraph in the body. Double newline signifies a new
+        // paragraph -- usually.
+        let mut paragraph_ends = body
             .matches("\n\n")
-            .map(|m| m.as_ptr() as usize - body.as_ptr() as usize)
-            .find(|a| a >= &MIN_SNEAK_PEEK_AMOUNT)
-            .unwrap_or_else(|| body.len());
+            .map(|m| m.as_ptr() as usize - body.as_ptr() as usize);
+
+        // Figure out how much to show as a sneak peek for the blog post. In order of preference,
+        // that's from the header, up to the marker in the body, or just a heuristic.
+        let sneak_peek_markdown = match &parsed.sneak_peek {
+            Some(ParsedSneakPeek::Excerpt(md)) => md.as_str(),
+            Some(ParsedSneakPeek::Paragraphs(0)) => {
+                bail!("header field `sneak_peek` must be at least one paragraph")
+            }
+            Some(ParsedSneakPeek::Paragraphs(n)) => {
+                &body[..paragraph_ends.nth(n - 1).unwrap_or(body.len())]
+            }
+            None => match find_more_marker(body, MarkdownKind::Document) {
+                Some(i) => &body[..i],
+                // We *could* do this semantically by the parsed markdown, but directly going off
+                // of the byte sizes is just easier.
+                //
+                // Essentially what we're doing is getting enough paragraphs of input so that
+                // there's at least MIN_SNEAK_PEEK_AMOUNT bytes of raw markdown represented.
+                None => {
+                    &body[..paragraph_ends
+                        .find(|a| a >= &MIN_SNEAK_PEEK_AMOUNT)
+                        .unwrap_or(body.len())]
+                }
+            },
+        };
+
+        for path in parsed.extra_css.iter().chain(&parsed.extra_js) {
+            static_assets::validate_path(path)
+                .context("invalid extra stylesheet or script in header")?;
+        }
+
+        if let Some(url) = &parsed.canonical_url {
+            if !is_absolute_url(url) {
+                bail!(
+                    "header field `canonical_url` must be an absolute URL, found {:?}",
+                    url
+                );
+            }
+        }
+
+        let author = parsed
+            .author
+            .unwrap_or_else(|| config.default_author.clone());
+        let author_slug = slugify(&author);
+        if author_slug.is_empty() {
+            bail!(
+                "author {:?} must have at least one ASCII letter or digit",
+                author
+            );
+        }
+
+        // A missing cover shouldn't stop the post from loading -- especially because the photos
+        // might only be missing locally.
+        let cover_image = parsed.cover_image.as_deref().and_then(|name| {
+            let cover = photos::cover_image(name);
+            if cover.is_none() && photos::is_loaded() {
+                warn!(
+                    "cover image {:?} for file {:?} isn't a photo; ignoring it",
+                    name, file_name
+                );
+            }
+            cover
+        });
+
+        let mut tags: Vec<Tag> = Vec::new();
+        for name in &parsed.tags {
+            let tag = Tag::new(name)?;
+            // Tags that only differ by case are the same tag
+            if !tags.iter().any(|t| t.key == tag.key) {
+                tags.push(tag);
+            }
+        }
 
         let tab_title = parsed.tab_title.unwrap_or_else(|| parsed.title.clone());
         let meta = PostMeta {
-            path: path.to_owned(),
+            path: parsed
+                .slug
+                .map(PathBuf::from)
+                .unwrap_or_else(|| file_name.to_owned()),
+            aliases: parsed.aliases.into_iter().map(PathBuf::from).collect(),
             title: parsed.title,
             tab_title,
-            sneak_peek: markdown_to_html(&body[..sneak_peek_amount]),
-            description: markdown_to_html(&parsed.description),
+            sneak_peek: markdown_to_html_with_images(
+                sneak_peek_markdown,
+                MarkdownKind::Excerpt,
+                &photos::markdown_image,
+            ),
+            description: markdown_to_html(&parsed.description, MarkdownKind::Snippet),
             first_published: format_datetime(parsed.first_published.0, FormatLevel::Date),
             updated: parsed
                 .updated
-                .into_iter()
+                .iter()
                 .map(|d| format_datetime(d.0, FormatLevel::Date))
                 .collect(),
-            tags: parsed.tags,
+            tags,
+            author,
+            author_slug,
             is_hidden: parsed.is_hidden,
+            extra_css: parsed.extra_css,
+            extra_js: parsed.extra_js,
+            canonical_url: parsed.canonical_url,
+            cover_image,
+            published_datetime: parsed.first_published.0,
             published_unix_time: parsed.first_published.0.timestamp(),
+            updated_unix_time: parsed
+                .updated
+                .iter()
+                .map(|d| d.0.timestamp())
+                .max()
+                .unwrap_or_else(|| parsed.first_published.0.timestamp()),
         };
 
+        let keywords: Vec<_> = meta.tags.iter().map(|t| t.name.clone()).collect();
+        let json_ld = BlogPosting {
+            headline: &meta.title,
+            description: &markdown_to_plain_text(&parsed.description, MarkdownKind::Snippet),
+            url: match &meta.canonical_url {
+                Some(url) => url.clone(),
+                None => format!("{}/blog/{}", SITE_URL, meta.path.display()),
+            },
+            date_published: meta.published_datetime,
+            date_modified: parsed.updated.iter().map(|d| d.0).max(),
+            keywords: &keywords,
+            author: &meta.author,
+        }
+        .to_script();
+
         Ok(PostContext {
             meta,
-            html_body_content: markdown_to_html(body),
+            html_body_content: markdown_to_html_with_images(
+                body,
+                MarkdownKind::Document,
+                &photos::markdown_image,
+            ),
+            plain_text_body: markdown_to_plain_text(body, MarkdownKind::Document),
+            plain_description: parsed.description,
+            // The included files are part of the post too, so changes to them need to change the
+            // hash. Without any includes, this is the same as the original file.
+            content_hash: content_hash(format!("{}\n+++\n{}", header, body).as_bytes()),
+            json_ld,
         })
     }
 }
 
+/// Replaces every `{{include:<path>}}` in the markdown with the contents of the file at `path`,
+/// relative to `posts_dir`
+///
+/// The included files shouldn't match `BLOG_GLOB`, or they'll also be parsed as posts; putting
+/// them in a subdirectory is easiest. Included files can include others in turn; `stack` is the
+/// chain of files currently being included, so that cycles are an error instead of recursing
+/// forever. Paths must stay within the posts directory, so they can't be absolute or have any `..`
+/// components. Includes inside code blocks are expanded all the same.
+fn expand_includes(posts_dir: &Path, markdown: &str, stack: &mut Vec<PathBuf>) -> Result<String> {
+    lazy_static! {
+        static ref INCLUDE: Regex = Regex::new(r"\{\{include:([^}]*)\}\}").unwrap();
+    }
+
+    let mut expanded = String::with_capacity(markdown.len());
+    let mut last_end = 0;
+
+    for cap in INCLUDE.captures_iter(markdown) {
+        let whole = cap.get(0).unwrap();
+        let path = Path::new(cap[1].trim());
+
+        let within_dir = path.components().all(|c| matches!(c, Component::Normal(_)));
+        if path.as_os_str().is_empty() || !within_dir {
+            bail!(
+                "bad include path {:?}: must be relative, without '.' or '..'",
+                path
+            );
+        }
+
+        if stack.iter().any(|p| p == path) {
+            bail!(
+                "include cycle: {:?} includes itself (via {:?})",
+                path,
+                stack
+            );
+        }
+
+        let file_path = posts_dir.join(path);
+        let content = fs::read_to_string(&file_path)
+            .with_context(|| format!("failed to read included file {:?}", file_path))?;
+
+        stack.push(path.to_owned());
+        let included = expand_includes(posts_dir, &content, stack)
+            .with_context(|| format!("failed to expand includes in {:?}", path))?;
+        stack.pop();
+
+        expanded.push_str(&markdown[last_end..whole.start()]);
+        expanded.push_str(&included);
+        last_end = whole.end();
+    }
+
+    expanded.push_str(&markdown[last_end..]);
+    Ok(expanded)
+}
+
 /// The total stored state of the blog, a single instance of which is stored in `STATE`
 #[derive(Debug)]
 struct BlogState {
     /// Mapping of file / directory names
     files: HashMap<PathBuf, Arc<PostContext>>,
-    /// All of the tags and the posts
+    /// Mapping of old post names to the current path of the post
+    aliases: HashMap<PathBuf, PathBuf>,
+    /// Index of the content of each post, keyed by path
+    search_index: SearchIndex<PathBuf>,
+    /// All of the tags and the posts, keyed by `Tag::key`
     tags: HashMap<String, BTreeMap<i64, Arc<PostContext>>>,
     /// A version of `tags`, but sorted by: number of posts, then alphabetically
-    tags_sorted: Vec<(String, BTreeMap<i64, Arc<PostContext>>)>,
+    tags_sorted: Vec<(Tag, BTreeMap<i64, Arc<PostContext>>)>,
+    /// Author slug -> their posts, like `tags`
+    authors: HashMap<String, BTreeMap<i64, Arc<PostContext>>>,
 
     /// Entry names, sorted by their publishing timestamp
     by_time: BTreeMap<i64, Arc<PostContext>>,
+    /// All of the posts, sorted by their latest update (most recent first)
+    by_updated_time: Vec<Arc<PostContext>>,
+    /// Post path -> the other posts to suggest at the end of it
+    related: HashMap<PathBuf, Vec<PostSummary>>,
 
     /// Information about planned posts
     planned_posts: Arc<PlannedPostsInfo>,
+
+    /// When this state was created, from the files on disk
+    generated_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -318,12 +1091,26 @@ pub struct PostContext {
     meta: PostMeta,
     /// The body of the blog post, as HTML
     html_body_content: String,
+    /// The body of the blog post, as plain text
+    #[serde(skip)]
+    plain_text_body: String,
+    /// The original markdown of the post's description, for places where we can't use HTML
+    #[serde(skip)]
+    plain_description: String,
+    /// Hash of the post's entire source file
+    #[serde(skip)]
+    content_hash: String,
+    /// The post's structured data, for the `<script>` tag on its page
+    #[serde(skip)]
+    json_ld: String,
 }
 
 #[derive(Debug, Clone, Serialize)]
 struct PostMeta {
     /// The path to the post
     path: PathBuf,
+    /// Previous paths of the post, which redirect to the current one
+    aliases: Vec<PathBuf>,
     /// The name of the blog post displayed at the top of the page
     title: String,
     /// The name used for titling the tab. Defaults to `title` if not given
@@ -336,38 +1123,259 @@ struct PostMeta {
     first_published: String,
     /// All of the times at which the post was updated
     updated: Vec<String>,
-    /// Tags associated with the post
-    tags: Vec<String>,
+    /// Tags associated with the post, without any duplicates
+    tags: Vec<Tag>,
+    /// Name of whoever wrote the post, defaulting to `BlogConfig::default_author`
+    author: String,
+    /// URL-safe version of `author`, for linking to the list of their posts
+    author_slug: String,
     /// True if this post should be hidden (i.e. completely skipped, for now)
     is_hidden: bool,
+    /// Additional stylesheets needed by the post, as paths within the static directory
+    extra_css: Vec<String>,
+    /// Additional scripts needed by the post, as paths within the static directory
+    extra_js: Vec<String>,
+    /// Where the original version of the post is, if it was first published somewhere else. If
+    /// not given, the post on this site is the canonical one.
+    canonical_url: Option<String>,
+    /// The photo to display at the top of the post, if it has one. Given in the header by the
+    /// photo's file name.
+    cover_image: Option<photos::CoverImage>,
+    /// The time at which the post was first published, for formatting relative to the current time
+    #[serde(skip)]
+    published_datetime: DateTime<FixedOffset>,
     /// The "first published" timestamp, represented as seconds since the Unix epoch. Stored for
     /// sorting.
     published_unix_time: i64,
+    /// The timestamp of the latest update, or `published_unix_time` if there haven't been any.
+    /// Stored for sorting.
+    updated_unix_time: i64,
+}
+
+/// The context for rendering a single post's page
+#[derive(Debug, Clone, Serialize)]
+struct PostPageContext {
+    #[serde(flatten)]
+    post: Arc<PostContext>,
+    /// How long ago the post was published (e.g. "3 days ago"), if it was published recently
+    #[serde(skip_serializing_if = "Option::is_none")]
+    published_relative: Option<String>,
+    /// Contents of the page's `<script type="application/ld+json">`
+    json_ld: String,
+    /// Whether the post's Open Graph image can be rendered -- i.e. whether `og_image_font` is set
+    has_og_image: bool,
+    /// Other posts to suggest at the end of this one; see `BlogState::related_posts`
+    related: Vec<PostSummary>,
+}
+
+/// Summary of a post, for linking to it without all of its content
+#[derive(Debug, Clone, Serialize)]
+struct PostSummary {
+    path: PathBuf,
+    title: String,
+    /// Pretty-printed date at which the post was first published
+    first_published: String,
+}
+
+impl PostSummary {
+    fn new(post: &PostContext) -> Self {
+        PostSummary {
+            path: post.meta.path.clone(),
+            title: post.meta.title.clone(),
+            first_published: post.meta.first_published.clone(),
+        }
+    }
+}
+
+/// The context for the archive page: every post, by year then month, most recent first. Months
+/// without any posts are left out.
+#[derive(Serialize)]
+struct ArchiveContext {
+    years: Vec<ArchiveYear>,
+}
+
+#[derive(Serialize)]
+struct ArchiveYear {
+    year: i32,
+    months: Vec<ArchiveMonth>,
+}
+
+#[derive(Serialize)]
+struct ArchiveMonth {
+    /// The month's full name; e.g. "March"
+    name: String,
+    posts: Vec<PostSummary>,
+}
+
+/// A tag on a post, as written in its header and normalized for use in URLs
+#[derive(Debug, Clone, Serialize)]
+struct Tag {
+    /// The tag as it's displayed, with any surrounding whitespace removed
+    name: String,
+    /// Normalized version of `name` that identifies the tag (see [`normalize_tag`]), so that e.g.
+    /// "Rust" and "rust" are the same tag
+    key: String,
+    /// Percent-encoded version of `key`, for use in URLs
+    slug: String,
+}
+
+impl Tag {
+    fn new(name: &str) -> Result<Self> {
+        let name = name.trim();
+        if name.is_empty() {
+            bail!("tags must not be empty");
+        }
+
+        let key = normalize_tag(name);
+        Ok(Tag {
+            name: name.to_owned(),
+            slug: Uri::percent_encode(&key).into_owned(),
+            key,
+        })
+    }
+}
+
+/// Returns the form of the tag that identifies it: trimmed and lowercase
+///
+/// Everything else is kept as-is, so that e.g. "C++" and "C#" are still different tags.
+fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase()
+}
+
+/// The information about a single post provided by `api_posts`
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiPost {
+    path: PathBuf,
+    title: String,
+    /// Description of the post, as HTML
+    description: String,
+    tags: Vec<String>,
+    first_published: String,
+    updated: Vec<String>,
+    /// The body of the post, as HTML. Only present if explicitly requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    html_body_content: Option<String>,
+}
+
+impl ApiPost {
+    fn new(post: &PostContext, full: bool) -> Self {
+        let meta = &post.meta;
+
+        ApiPost {
+            path: meta.path.clone(),
+            title: meta.title.clone(),
+            description: meta.description.clone(),
+            tags: meta.tags.iter().map(|t| t.name.clone()).collect(),
+            first_published: meta.first_published.clone(),
+            updated: meta.updated.clone(),
+            html_body_content: full.then(|| post.html_body_content.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TagCount {
+    name: String,
+    slug: String,
+    count: usize,
+}
+
+/// A single result provided by `search`
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchResultInfo {
+    path: PathBuf,
+    title: String,
+    /// Plain text from the body of the post around the first match
+    snippet: String,
 }
 
 #[derive(Debug, Clone, Serialize)]
 struct IndexContext {
     posts: Vec<Arc<PostContext>>,
-    tags: Vec<String>,
+    /// Each tag with the number of posts it has, for displaying tags by weight
+    tag_counts: Vec<TagCount>,
+    /// All of the posts, ordered by the time they were last updated (or published), most recent
+    /// first
+    recently_updated: Vec<Arc<PostContext>>,
+    tags: Vec<Tag>,
 }
 
 #[derive(Debug, Clone, Serialize)]
 struct TagContext {
+    /// The display name of the tag
     tag: String,
     posts: Vec<Arc<PostContext>>,
 }
 
+#[derive(Debug, Clone, Serialize)]
+struct AuthorContext {
+    /// The author's name, as given by their posts
+    author: String,
+    posts: Vec<Arc<PostContext>>,
+}
+
 impl BlogState {
     fn index_context(&self) -> IndexContext {
         IndexContext {
             tags: self
                 .tags_sorted
                 .iter()
-                .map(|(name, _)| name)
+                .map(|(tag, _)| tag)
                 .cloned()
                 .collect(),
             posts: self.by_time.iter().map(|(_, i)| i).cloned().rev().collect(),
+            tag_counts: self.tag_counts(),
+            recently_updated: self.by_updated_time.clone(),
+        }
+    }
+
+    /// Returns all of the tags and their post counts, sorted by count (descending), then by name
+    fn tag_counts(&self) -> Vec<TagCount> {
+        let mut counts: Vec<_> = self
+            .tags_sorted
+            .iter()
+            .map(|(tag, posts)| TagCount {
+                name: tag.name.clone(),
+                slug: tag.slug.clone(),
+                count: posts.len(),
+            })
+            .collect();
+
+        counts.sort_by(|x, y| y.count.cmp(&x.count).then_with(|| x.name.cmp(&y.name)));
+        counts
+    }
+
+    fn archive_context(&self) -> ArchiveContext {
+        let mut years: Vec<ArchiveYear> = Vec::new();
+
+        // Each post is bucketed by the date where it was published, so that its month matches the
+        // date displayed with it
+        for post in self.by_time.values().rev() {
+            let published = post.meta.published_datetime;
+
+            match years.last_mut() {
+                Some(y) if y.year == published.year() => (),
+                _ => years.push(ArchiveYear {
+                    year: published.year(),
+                    months: Vec::new(),
+                }),
+            }
+            let months = &mut years.last_mut().unwrap().months;
+
+            let name = published.format("%B").to_string();
+            match months.last_mut() {
+                Some(m) if m.name == name => (),
+                _ => months.push(ArchiveMonth {
+                    name,
+                    posts: Vec::new(),
+                }),
+            }
+            let month = months.last_mut().unwrap();
+
+            month.posts.push(PostSummary::new(post));
         }
+
+        ArchiveContext { years }
     }
 
     fn planned_posts_context(&self) -> Arc<PlannedPostsInfo> {
@@ -378,14 +1386,204 @@ impl BlogState {
         self.files.get(name.as_ref()).cloned()
     }
 
-    fn tag_context(&self, name: &str) -> Option<TagContext> {
+    fn tag_context(&self, key: &str) -> Option<TagContext> {
+        let posts = self.tags.get(key)?;
         Some(TagContext {
-            tag: name.to_owned(),
-            posts: self.tags.get(name)?.values().cloned().rev().collect(),
+            tag: Self::tag_with_key(key, posts).name,
+            posts: posts.values().cloned().rev().collect(),
+        })
+    }
+
+    fn author_context(&self, slug: &str) -> Option<AuthorContext> {
+        let posts: Vec<_> = self.authors.get(slug)?.values().cloned().rev().collect();
+        Some(AuthorContext {
+            // Names that only differ by punctuation or case share a slug, so we just pick one
+            author: posts[0].meta.author.clone(),
+            posts,
         })
     }
 
-    fn recent_posts_context(&self) -> Vec<Arc<PostContext>> {
-        self.by_time.values().cloned().rev().collect()
+    fn recent_posts_context(&self, limit: usize) -> Vec<Arc<PostContext>> {
+        self.by_time.values().rev().take(limit).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses a post whose header is the given lines, followed by a short body
+    fn parse_with_header(header: &[&str]) -> Result<PostContext> {
+        let content = format!("{}\n+++\nThe body of the post.\n", header.join("\n"));
+        PostContext::from_file_content(&BlogConfig::default(), Path::new("test-post"), &content)
+    }
+
+    static VALID_HEADER: &[&str] = &[
+        r#"title = "A test post""#,
+        r#"description = "Just for testing""#,
+        r#"first_published = "Sat, 01 Jan 2022 12:00:00 -0800""#,
+        r#"updated = []"#,
+        r#"tags = []"#,
+        r#"is_hidden = false"#,
+    ];
+
+    #[test]
+    fn valid_header() {
+        let post = parse_with_header(VALID_HEADER).unwrap();
+        assert_eq!(post.meta.title, "A test post");
+        assert_eq!(post.meta.path, Path::new("test-post"));
+    }
+
+    #[test]
+    fn malformed_header_reports_location() {
+        let mut header = VALID_HEADER.to_vec();
+        header[2] = "first_published = ";
+
+        let err = format!("{:#}", parse_with_header(&header).unwrap_err());
+        assert!(err.contains("at line 3"), "unexpected error: {}", err);
+    }
+
+    #[test]
+    fn unknown_header_key() {
+        let mut header = VALID_HEADER.to_vec();
+        header.push(r#"colour = "blue""#);
+
+        let err = format!("{:#}", parse_with_header(&header).unwrap_err());
+        assert!(
+            err.contains("unknown field `colour`"),
+            "unexpected error: {}",
+            err
+        );
+    }
+
+    #[test]
+    fn relative_time_only_for_recent_posts() {
+        let published = DateTime::parse_from_rfc3339("2022-01-01T12:00:00-08:00").unwrap();
+        let after = |d: Duration| (published + d).with_timezone(&Utc);
+
+        let just_before = Duration::days(RECENT_POST_DAYS) - Duration::seconds(1);
+        assert_eq!(
+            relative_if_recent(published, after(just_before)).as_deref(),
+            Some("29 days ago")
+        );
+        assert_eq!(
+            relative_if_recent(published, after(Duration::days(RECENT_POST_DAYS))),
+            None
+        );
+    }
+
+    #[test]
+    fn missing_header_separator() {
+        let content = VALID_HEADER.join("\n");
+        let result = PostContext::from_file_content(
+            &BlogConfig::default(),
+            Path::new("test-post"),
+            &content,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn tags_normalized() {
+        let rust = Tag::new(" Rust ").unwrap();
+        assert_eq!((rust.name.as_str(), rust.key.as_str()), ("Rust", "rust"));
+        assert_eq!(rust.slug, "rust");
+
+        let mut header = VALID_HEADER.to_vec();
+        header[4] = r#"tags = ["Rust", "rust ", "C++", "C#", "C", "Café"]"#;
+        let post = parse_with_header(&header).unwrap();
+
+        let keys: Vec<_> = post.meta.tags.iter().map(|t| t.key.as_str()).collect();
+        assert_eq!(keys, ["rust", "c++", "c#", "c", "café"]);
+        let slugs: Vec<_> = post.meta.tags.iter().map(|t| t.slug.as_str()).collect();
+        assert_eq!(slugs, ["rust", "c%2B%2B", "c%23", "c", "caf%C3%A9"]);
+    }
+
+    #[test]
+    fn empty_tag() {
+        let mut header = VALID_HEADER.to_vec();
+        header[4] = r#"tags = ["  "]"#;
+        assert!(parse_with_header(&header).is_err());
+    }
+
+    /// Writes the files into a fresh directory for the test, returning its path
+    fn include_dir(test_name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("includes-{}-{}", test_name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (path, content) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    fn expand(dir: &Path, markdown: &str) -> Result<String> {
+        expand_includes(dir, markdown, &mut Vec::new())
+    }
+
+    #[test]
+    fn nested_includes() {
+        let dir = include_dir(
+            "nested",
+            &[
+                ("includes/outer.md", "outer [{{include:includes/inner.md}}]"),
+                ("includes/inner.md", "inner"),
+            ],
+        );
+
+        let expanded = expand(&dir, "a {{include: includes/outer.md }} b").unwrap();
+        assert_eq!(expanded, "a outer [inner] b");
+        // The same file can be included more than once, as long as it's not within itself
+        let expanded = expand(
+            &dir,
+            "{{include:includes/inner.md}}{{include:includes/inner.md}}",
+        );
+        assert_eq!(expanded.unwrap(), "innerinner");
+    }
+
+    #[test]
+    fn include_outside_posts_dir() {
+        let dir = include_dir("outside", &[("secret.md", "secret"), ("posts/ok.md", "ok")]);
+        let posts = dir.join("posts");
+
+        for markdown in [
+            "{{include:../secret.md}}",
+            "{{include:./ok.md}}",
+            "{{include:}}",
+        ] {
+            let err = format!("{:#}", expand(&posts, markdown).unwrap_err());
+            assert!(
+                err.contains("bad include path"),
+                "unexpected error: {}",
+                err
+            );
+        }
+
+        let absolute = format!("{{{{include:{}}}}}", dir.join("secret.md").display());
+        let err = format!("{:#}", expand(&posts, &absolute).unwrap_err());
+        assert!(
+            err.contains("bad include path"),
+            "unexpected error: {}",
+            err
+        );
+    }
+
+    #[test]
+    fn include_cycle() {
+        let dir = include_dir(
+            "cycle",
+            &[
+                ("a.md", "A {{include:b.md}}"),
+                ("b.md", "B {{include:a.md}}"),
+                ("self.md", "{{include:self.md}}"),
+            ],
+        );
+
+        for markdown in ["{{include:a.md}}", "{{include:self.md}}"] {
+            let err = format!("{:#}", expand(&dir, markdown).unwrap_err());
+            assert!(err.contains("include cycle"), "unexpected error: {}", err);
+        }
     }
 }
@@ -0,0 +1,69 @@
+//! Wrapper module for the [`CacheControl`] fairing
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Header, Status};
+use rocket::{Request, Response};
+use std::path::Path;
+
+use crate::config::CacheConfig;
+use crate::static_assets;
+
+/// Policy for static assets that are requested with their current content hash in the URL (either
+/// in the name or as a `rev` query parameter), so that any change to the file results in a
+/// different URL.
+///
+/// This matches the policy used for images.
+pub static IMMUTABLE_POLICY: &str = "max-age=2592000, immutable";
+
+/// Response fairing that sets a default `Cache-Control` header on HTML pages and CSS/JS assets
+///
+/// Responses that already have a `Cache-Control` header (like images) are left untouched, as are
+/// unsuccessful responses.
+pub struct CacheControl {
+    html_policy: String,
+    asset_policy: String,
+}
+
+impl CacheControl {
+    pub fn new(config: &CacheConfig) -> Self {
+        CacheControl {
+            html_policy: format!("max-age={}", config.html_max_age_secs),
+            asset_policy: format!("max-age={}", config.static_max_age_secs),
+        }
+    }
+}
+
+impl Fairing for CacheControl {
+    fn info(&self) -> Info {
+        Info {
+            name: "Cache-Control",
+            kind: Kind::Response,
+        }
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        if response.status() != Status::Ok || response.headers().contains("Cache-Control") {
+            return;
+        }
+
+        let ct = match response.content_type() {
+            Some(ct) => ct,
+            None => return,
+        };
+
+        let policy = if ct.is_html() {
+            self.html_policy.as_str()
+        } else if ct.is_css() || ct.is_javascript() {
+            // An old (or made up) `rev` would otherwise pin whatever's served now to that URL
+            let path = Path::new(request.uri().path().trim_start_matches('/'));
+            match request.get_query_value::<String>("rev") {
+                Some(Ok(rev)) if static_assets::is_current_rev(path, &rev) => IMMUTABLE_POLICY,
+                _ => self.asset_policy.as_str(),
+            }
+        } else {
+            return;
+        };
+
+        response.set_header(Header::new("Cache-Control", policy.to_owned()));
+    }
+}
@@ -0,0 +1,46 @@
+//! Error catchers, so that error pages are rendered with the same templates as the rest of the
+//! site
+//!
+//! The main export is the `catchers_list` function, which gives every catcher to register.
+
+use rocket::{catch, catchers, Catcher, Request};
+use rocket_contrib::templates::Template;
+use serde::Serialize;
+
+/// Name of the template used for 404 pages
+static NOT_FOUND_TEMPLATE_NAME: &str = "error/404";
+/// Name of the template used for 500 pages
+static INTERNAL_ERROR_TEMPLATE_NAME: &str = "error/500";
+
+/// Returns all of the catchers, to be registered with Rocket
+pub fn catchers_list() -> Vec<Catcher> {
+    catchers![not_found, internal_error]
+}
+
+/// Template context for the error pages
+#[derive(Serialize)]
+struct ErrorContext {
+    /// The URI that the request was for
+    uri: String,
+}
+
+impl ErrorContext {
+    fn new(req: &Request) -> Self {
+        ErrorContext {
+            uri: req.uri().to_string(),
+        }
+    }
+}
+
+// Rocket sets the status code of the response to match the catcher, so we don't need to do it
+// ourselves here.
+
+#[catch(404)]
+fn not_found(req: &Request) -> Template {
+    Template::render(NOT_FOUND_TEMPLATE_NAME, ErrorContext::new(req))
+}
+
+#[catch(500)]
+fn internal_error(req: &Request) -> Template {
+    Template::render(INTERNAL_ERROR_TEMPLATE_NAME, ErrorContext::new(req))
+}
@@ -0,0 +1,182 @@
+//! Wrapper module for the [`Compress`] fairing
+
+use log::error;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{ContentType, Header};
+use rocket::{Request, Response};
+use std::io::{self, Cursor, Write};
+
+/// Response fairing that compresses text-like responses with brotli or gzip, depending on what the
+/// client supports
+///
+/// Images are never compressed; the WEBP and JPEG data we serve is already compressed, so doing it
+/// again would just be wasted CPU.
+pub struct Compress;
+
+/// Minimum size of a response body, in bytes, before we bother compressing it
+///
+/// Below this, the savings aren't worth the extra work (or can even make the response larger).
+const MIN_COMPRESS_SIZE: usize = 1024;
+
+/// Brotli quality level, from 0 to 11. Higher levels are *much* slower for only slightly better
+/// compression, and we're compressing on every request.
+const BROTLI_QUALITY: u32 = 5;
+/// Base-2 logarithm of the brotli window size
+const BROTLI_LG_WINDOW_SIZE: u32 = 22;
+
+/// The (top, sub) media types that we'll compress
+static COMPRESSIBLE_TYPES: &[(&str, &str)] = &[
+    ("text", "html"),
+    ("text", "plain"),
+    ("text", "css"),
+    ("text", "javascript"),
+    ("text", "xml"),
+    ("application", "javascript"),
+    ("application", "json"),
+    ("application", "xml"),
+    ("application", "atom+xml"),
+];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Fairing for Compress {
+    fn info(&self) -> Info {
+        Info {
+            name: "Compress",
+            kind: Kind::Response,
+        }
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        if response.headers().contains("Content-Encoding") {
+            return;
+        }
+
+        match response.content_type() {
+            Some(ct) if is_compressible(&ct) => (),
+            _ => return,
+        }
+
+        // Regardless of whether we compress this particular response, caches need to know that
+        // the content can vary with the encoding.
+        response.set_header(Header::new("Vary", "Accept-Encoding"));
+
+        let encoding = match request
+            .headers()
+            .get_one("Accept-Encoding")
+            .and_then(preferred_encoding)
+        {
+            Some(e) => e,
+            None => return,
+        };
+
+        let body = match response.body_bytes() {
+            Some(b) => b,
+            None => return,
+        };
+
+        if body.len() < MIN_COMPRESS_SIZE {
+            response.set_sized_body(Cursor::new(body));
+            return;
+        }
+
+        match compress(&body, encoding) {
+            Ok(compressed) => {
+                let name = match encoding {
+                    Encoding::Brotli => "br",
+                    Encoding::Gzip => "gzip",
+                };
+
+                response.set_header(Header::new("Content-Encoding", name));
+                weaken_etag(response);
+                response.set_sized_body(Cursor::new(compressed));
+            }
+            Err(e) => {
+                error!("failed to compress response for {}: {}", request.uri(), e);
+                response.set_sized_body(Cursor::new(body));
+            }
+        }
+    }
+}
+
+fn is_compressible(content_type: &ContentType) -> bool {
+    let media_type = content_type.media_type();
+
+    COMPRESSIBLE_TYPES
+        .iter()
+        .any(|&(top, sub)| media_type.top() == top && media_type.sub() == sub)
+}
+
+/// Marks the response's `ETag` as weak, if it has a strong one
+///
+/// The tag is from the uncompressed body, so it can't be a strong validator for the compressed one:
+/// they aren't byte-for-byte identical. Our `If-None-Match` handling uses weak comparison, so
+/// clients that send back the weakened tag still get 304s.
+fn weaken_etag(response: &mut Response) {
+    let strong = match response.headers().get_one("ETag") {
+        Some(tag) if !tag.starts_with("W/") => tag.to_owned(),
+        _ => return,
+    };
+
+    response.set_header(Header::new("ETag", format!("W/{}", strong)));
+}
+
+/// Picks the best encoding we support out of the value of an `Accept-Encoding` header
+///
+/// We always prefer brotli over gzip if both are available, regardless of the weights the client
+/// gives; the only weight we care about is "q=0", which explicitly disallows an encoding.
+fn preferred_encoding(accept_encoding: &str) -> Option<Encoding> {
+    let mut best = None;
+
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';').map(str::trim);
+        let name = parts.next().unwrap_or("");
+
+        let disallowed = parts.any(|p| match p.strip_prefix("q=") {
+            Some(q) => q.parse::<f32>().map(|q| q == 0.0).unwrap_or(false),
+            None => false,
+        });
+
+        if disallowed {
+            continue;
+        }
+
+        match name {
+            "br" => return Some(Encoding::Brotli),
+            "gzip" => best = Some(Encoding::Gzip),
+            _ => (),
+        }
+    }
+
+    best
+}
+
+fn compress(data: &[u8], encoding: Encoding) -> io::Result<Vec<u8>> {
+    match encoding {
+        Encoding::Brotli => {
+            let mut out = Vec::new();
+            {
+                let mut writer = brotli::CompressorWriter::new(
+                    &mut out,
+                    4096,
+                    BROTLI_QUALITY,
+                    BROTLI_LG_WINDOW_SIZE,
+                );
+                writer.write_all(data)?;
+                // Remaining output is flushed when the writer is dropped
+            }
+            Ok(out)
+        }
+        Encoding::Gzip => {
+            use flate2::write::GzEncoder;
+
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+    }
+}
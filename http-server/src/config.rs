@@ -0,0 +1,346 @@
+//! Wrapper module for [`Config`], the runtime configuration of the server
+//!
+//! The configuration is read once at startup from `CONFIG_PATH`. Every field has a default (the
+//! values that used to be hardcoded), so the file only needs to contain the values that differ --
+//! and it doesn't need to exist at all.
+
+use anyhow::{bail, Context, Result};
+use chrono_tz::Tz;
+use log::LevelFilter;
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Path of the configuration file, relative to the working directory
+static CONFIG_PATH: &str = "config.toml";
+
+/// Configuration for the entire server
+///
+/// The `blog` and `photos` sections are passed to their respective modules' `initialize`
+/// functions.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Minimum level of log messages to display; one of "off", "error", "warn", "info", "debug",
+    /// or "trace"
+    pub log_level: LevelFilter,
+    /// Whether to log a line for every response. Typically disabled in development.
+    pub access_log: bool,
+    /// Directory used to store static content at the site root
+    pub static_dir: PathBuf,
+    /// Path of the named pipe to listen to for updates to the site content
+    pub update_pipe_path: PathBuf,
+    /// Number of seconds to wait before retrying if we can't open the updates pipe
+    pub update_retry_wait_secs: u64,
+    /// Bearer token required to use the `/admin` routes. If not set, they're disabled entirely.
+    pub admin_token: Option<String>,
+    /// Whether broken links between posts, photos, and albums should prevent the server from
+    /// starting. Otherwise, they're just logged. In debug builds, links are only checked if this is
+    /// set, because checking them means loading everything at startup.
+    pub broken_links_fatal: bool,
+    /// Path to a small stylesheet to inline in the site root's `<head>`, so that the page can be
+    /// rendered before the full stylesheet arrives. The file is only read once, at startup.
+    pub index_critical_css: Option<PathBuf>,
+    /// Number of recent blog posts to show at the site root
+    pub index_num_posts: usize,
+    /// Number of photos to show at the site root, from `PhotosConfig::preview_album`
+    pub index_num_photos: usize,
+
+    pub cache: CacheConfig,
+    pub rate_limit: RateLimitConfig,
+    pub security_headers: SecurityHeadersConfig,
+    pub blog: BlogConfig,
+    pub photos: PhotosConfig,
+}
+
+/// Configuration for the default `Cache-Control` headers, set by `crate::cache_control`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Number of seconds that HTML pages may be cached for
+    pub html_max_age_secs: u64,
+    /// Number of seconds that CSS and JS files may be cached for, if they aren't requested with a
+    /// `rev` query parameter
+    pub static_max_age_secs: u64,
+}
+
+/// Configuration for the security-related headers on HTML pages, set by `crate::security_headers`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecurityHeadersConfig {
+    /// Value of the `Content-Security-Policy` header. This needs to allow everything that the
+    /// templates load from other sites, like the map tiles. If empty, the header isn't set.
+    pub content_security_policy: String,
+    /// Value of the `Referrer-Policy` header
+    pub referrer_policy: String,
+    /// `max-age` for the `Strict-Transport-Security` header, which is only set if the reverse proxy
+    /// tells us that the request was made over HTTPS
+    pub hsts_max_age_secs: u64,
+}
+
+/// Configuration for the per-IP limits on expensive requests, enforced by `crate::rate_limit`
+///
+/// Each client gets a single "token bucket" shared between all of the limited routes: every
+/// limited request uses up a token, and tokens are refilled at a constant rate, up to `burst`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Whether to limit requests at all
+    pub enabled: bool,
+    /// Maximum number of limited requests that a client can make in quick succession
+    pub burst: u32,
+    /// Number of limited requests per minute that a client can make once they've used up the
+    /// `burst`
+    pub per_minute: u32,
+    /// Which requests are limited
+    pub routes: Vec<RateLimitedRoute>,
+    /// Addresses of the reverse proxies in front of the server. Requests from these are limited by
+    /// the client address that the proxy added to `X-Forwarded-For`; everything else is limited by
+    /// the address of the connection itself.
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+/// A set of requests that are subject to the rate limit
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitedRoute {
+    /// Glob pattern matching the request path; e.g. "/photos/img-file/*" for everything with that
+    /// prefix
+    pub path: String,
+    /// Query parameter that the request must also have (as "key=value"), if any
+    #[serde(default)]
+    pub query: Option<String>,
+}
+
+/// Configuration specific to `crate::blog`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BlogConfig {
+    /// Directory that the blog posts are stored in
+    pub posts_dir: PathBuf,
+    /// Path to the TrueType font used to draw the Open Graph preview images for posts. If not set,
+    /// the preview images are disabled.
+    pub og_image_font: Option<PathBuf>,
+    /// Author of every post that doesn't give its own in its header
+    pub default_author: String,
+}
+
+/// Configuration specific to `crate::photos`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PhotosConfig {
+    /// Directory that images (+ album lists, metadata) are stored in
+    pub imgs_dir: PathBuf,
+    /// Approximate desired pixel count of the smaller versions of images
+    pub small_img_aprox_pixelcount: u64,
+    /// WEBP quality to encode the small images with
+    pub small_img_quality: f32,
+    /// Target maximum size of each small image, in bytes. If set, images that are larger than this
+    /// at `small_img_quality` are re-encoded at lower qualities until they fit (or we hit the
+    /// lowest quality we're willing to use). Otherwise, every image uses exactly
+    /// `small_img_quality`.
+    pub small_img_max_bytes: Option<u64>,
+    /// Maximum pixel count of a source image to fully decode when making its smaller version.
+    /// Anything larger is decoded at a reduced scale, so that a single huge image can't use up all
+    /// of the memory. Images that are still too large at the smallest scale (or can't be decoded
+    /// at a reduced scale at all) are skipped, with a warning.
+    pub max_source_pixelcount: u64,
+    /// Whether to make square crops of the album cover images for the album listings. Otherwise,
+    /// the listings use the cover photo's small image, cropped by the browser.
+    pub crop_album_covers: bool,
+    /// Path to the TrueType font used to draw the watermark on full-size images in albums with
+    /// `watermark` set. Required if there are any such albums.
+    pub watermark_font: Option<PathBuf>,
+    /// Text of the watermark
+    pub watermark_text: String,
+    /// Center of the initial view for the global map, as `[latitude, longitude]`, if there aren't
+    /// enough photos with locations to fit the view to them
+    pub fallback_map_center: [f64; 2],
+    /// Zoom level of the initial view for the global map, with the same conditions as
+    /// `fallback_map_center`
+    pub fallback_map_zoom: u8,
+    /// Timezone to display the times that photos were taken in, by its IANA name -- like
+    /// "America/Los_Angeles". If not set, each photo's time is displayed with the offset it was
+    /// taken at. Either way, photos are still sorted by the actual time they were taken. The date
+    /// albums and "on this day" pages use the same dates that are displayed.
+    ///
+    /// Each photo gets the timezone's offset at the time it was taken, so daylight saving time is
+    /// accounted for.
+    pub display_timezone: Option<Tz>,
+    /// Path of the auto-generated album that holds every photo. It's reserved, so there can't be an
+    /// album with this path in the albums info files, except to provide its settings.
+    pub all_album_path: String,
+    /// Display name of the album at `all_album_path`
+    pub all_album_name: String,
+    /// Description of the album at `all_album_path`
+    pub all_album_description: String,
+    /// Path of the album with favorite photos, which are displayed slightly differently. If it
+    /// doesn't exist, the photos index shows the most recent photos instead.
+    pub favorites_album: String,
+    /// Path of the album to show photos from at the site root. If it doesn't exist, the most
+    /// recent photos are shown instead.
+    pub preview_album: String,
+    /// Names of photos that legitimately don't need any alt text, and so shouldn't be reported as
+    /// missing it
+    pub alt_text_exempt: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            log_level: LevelFilter::Info,
+            access_log: true,
+            static_dir: "static".into(),
+            update_pipe_path: "updated".into(),
+            update_retry_wait_secs: 300, // 5 minutes
+            admin_token: None,
+            broken_links_fatal: false,
+            index_critical_css: None,
+            index_num_posts: 3,
+            index_num_photos: 5,
+            cache: CacheConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            security_headers: SecurityHeadersConfig::default(),
+            blog: BlogConfig::default(),
+            photos: PhotosConfig::default(),
+        }
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            html_max_age_secs: 300,     // 5 minutes
+            static_max_age_secs: 86400, // 1 day
+        }
+    }
+}
+
+impl Default for SecurityHeadersConfig {
+    fn default() -> Self {
+        SecurityHeadersConfig {
+            // The templates have a few inline scripts and styles, so those have to be allowed.
+            // Everything else is from: leaflet (unpkg, plus the OpenStreetMap tiles), OpenSeadragon
+            // (jsdelivr), and Google Fonts.
+            content_security_policy: [
+                "default-src 'self'",
+                "script-src 'self' 'unsafe-inline' https://unpkg.com https://cdn.jsdelivr.net",
+                "style-src 'self' 'unsafe-inline' https://unpkg.com https://fonts.googleapis.com",
+                "font-src 'self' https://fonts.gstatic.com",
+                "img-src 'self' data: https://tile.openstreetmap.org https://unpkg.com \
+                    https://cdn.jsdelivr.net",
+                "frame-ancestors 'none'",
+            ]
+            .join("; "),
+            referrer_policy: "strict-origin-when-cross-origin".to_owned(),
+            hsts_max_age_secs: 31536000, // 1 year
+        }
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        let route = |path: &str, query: Option<&str>| RateLimitedRoute {
+            path: path.to_owned(),
+            query: query.map(str::to_owned),
+        };
+
+        RateLimitConfig {
+            enabled: true,
+            burst: 60,
+            per_minute: 30,
+            // Only the routes that serve full-size files or generate images; everything needed for
+            // normal page loads is left alone
+            routes: vec![
+                route("/photos/img-file/*", Some("size=full")),
+                route("/photos/media/*", None),
+                route("/photos/album/*/download.zip", None),
+                route("/blog/*/og-image.png", None),
+            ],
+            // Caddy runs on the same machine
+            trusted_proxies: vec![Ipv4Addr::LOCALHOST.into(), Ipv6Addr::LOCALHOST.into()],
+        }
+    }
+}
+
+impl Default for BlogConfig {
+    fn default() -> Self {
+        BlogConfig {
+            posts_dir: "content/blog-posts".into(),
+            og_image_font: None,
+            default_author: "Max Sharnoff".to_owned(),
+        }
+    }
+}
+
+impl Default for PhotosConfig {
+    fn default() -> Self {
+        PhotosConfig {
+            imgs_dir: "content/photos".into(),
+            small_img_aprox_pixelcount: 480_000, // ≈ 800x600
+            small_img_quality: 80.0,
+            small_img_max_bytes: None,
+            max_source_pixelcount: 100_000_000, // 100 megapixels
+            crop_album_covers: true,
+            watermark_font: None,
+            watermark_text: "© sharnoff.io".to_owned(),
+            fallback_map_center: [37.839, -122.396], // SF Bay Area
+            fallback_map_zoom: 11,
+            display_timezone: None,
+            all_album_path: "all".to_owned(),
+            all_album_name: "All photos".to_owned(),
+            all_album_description: "All of my photos on this site, each and every one".to_owned(),
+            favorites_album: "favorites".to_owned(),
+            preview_album: "favorites".to_owned(),
+            alt_text_exempt: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration from `CONFIG_PATH`, falling back to the defaults if the file
+    /// doesn't exist
+    pub fn load() -> Result<Self> {
+        Self::load_from(Path::new(CONFIG_PATH))
+    }
+
+    fn load_from(path: &Path) -> Result<Self> {
+        let content = match fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read config file {:?}", path))
+            }
+        };
+
+        toml::from_str(&content).with_context(|| format!("failed to parse config file {:?}", path))
+    }
+
+    /// Checks that all of the content directories exist
+    ///
+    /// Each module would otherwise fail in its own way (or not at all, for an empty glob), so this
+    /// gives a single clear error at startup.
+    pub fn check_dirs(&self) -> Result<()> {
+        let dirs = [
+            ("static directory", &self.static_dir),
+            ("blog posts directory", &self.blog.posts_dir),
+            ("photos directory", &self.photos.imgs_dir),
+        ];
+
+        for (name, dir) in dirs {
+            if !dir.is_dir() {
+                bail!("{} {:?} doesn't exist or isn't a directory", name, dir);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Time to wait if we can't open the updates pipe
+    pub fn update_retry_wait(&self) -> Duration {
+        Duration::from_secs(self.update_retry_wait_secs)
+    }
+}
@@ -0,0 +1,98 @@
+//! Checking for broken links between the different parts of the site
+//!
+//! Blog posts and album & photo descriptions can link to posts, photos, and albums -- which can
+//! silently break when things are renamed. This scans all of their rendered HTML for those links
+//! and reports any that point to something that doesn't exist. Links to other sites are ignored.
+//!
+//! Because it needs the state of both `blog` and `photos`, this is run separately, after both of
+//! them have been initialized (or updated).
+
+use lazy_static::lazy_static;
+use log::{info, warn};
+use regex::Regex;
+use std::fmt::{self, Display, Formatter};
+
+use crate::util::local_path;
+use crate::{blog, photos};
+
+/// Single-segment paths under "/blog" that are other pages, not posts
+static BLOG_NON_POST_PATHS: &[&str] = &["archive", "planned", "search"];
+
+/// A link to something on the site that doesn't exist
+pub struct BrokenLink {
+    /// Description of where the link was found; e.g. `blog post "hello-world"`
+    source: String,
+    /// The link itself, as written
+    href: String,
+}
+
+impl Display for BrokenLink {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "broken link in {}: {:?}", self.source, self.href)
+    }
+}
+
+/// Finds all of the broken internal links, logging a warning for each one
+///
+/// Returns the number of broken links found.
+pub fn check() -> usize {
+    let broken = find_broken();
+    for link in &broken {
+        warn!("{}", link);
+    }
+
+    if broken.is_empty() {
+        info!("no broken internal links found");
+    }
+
+    broken.len()
+}
+
+fn find_broken() -> Vec<BrokenLink> {
+    lazy_static! {
+        /// Matcher for the destination of every link in some HTML
+        ///
+        /// The HTML we're checking was all produced by `markdown_to_html`, which always uses
+        /// double quotes, so there's no need for a full parser.
+        static ref HREF: Regex = Regex::new(r#"<a [^>]*href="([^"]*)""#).unwrap();
+    }
+
+    blog::rendered_html()
+        .into_iter()
+        .chain(photos::rendered_html())
+        .flat_map(|(source, html)| {
+            HREF.captures_iter(&html)
+                .map(|c| c[1].to_owned())
+                .filter(|href| !link_exists(href))
+                .map(|href| BrokenLink {
+                    source: source.clone(),
+                    href,
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Returns whether the link refers to something that exists, treating anything that we don't
+/// check as existing
+fn link_exists(href: &str) -> bool {
+    let path = match local_path(href) {
+        Some(p) => p,
+        None => return true,
+    };
+
+    // Queries and fragments don't change what's being linked to
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    let segments: Vec<_> = path
+        .trim_start_matches('/')
+        .trim_end_matches('/')
+        .split('/')
+        .collect();
+
+    match segments.as_slice() {
+        ["blog", post] if !BLOG_NON_POST_PATHS.contains(post) => blog::post_exists(post),
+        ["photos", "view", name] => photos::photo_exists(name),
+        ["photos", "album", name] => photos::album_exists(name),
+        _ => true,
+    }
+}
@@ -1,9 +1,13 @@
 //! Wrapper module for the [`Log404`] fairing
 
+use log::warn;
 use rocket::fairing::{Fairing, Info, Kind};
 use rocket::http::Status;
 use rocket::{Request, Response};
 
+use crate::logger::NOT_FOUND_TARGET;
+use crate::util::client_ip;
+
 pub struct Log404;
 
 impl Fairing for Log404 {
@@ -19,25 +23,18 @@ impl Fairing for Log404 {
             return;
         }
 
-        let headers = request.headers();
-
-        let ip = headers
-            .get_one("X-Forwarded-For") // Set by caddy
-            .map(str::to_owned)
-            .or_else(|| Some(headers.get_one("X-Client-IP")?.to_string())) // Set by other proxies
-            .or_else(|| Some(request.client_ip()?.to_string()));
-
+        let ip = client_ip(request);
         let referer = request.headers().get_one("Referer");
         let uri = request.uri();
 
-        let yellow = "\x1b[33m";
-        let reset = "\x1b[0m";
+        // The logger handles the colorized "404:" prefix for this target
+        let target = NOT_FOUND_TARGET;
 
         match (referer, ip) {
-            (None, None) => eprintln!("{yellow}404:{reset} {uri}"),
-            (Some(r), None) => eprintln!("{yellow}404:{reset} [{r} =>]  {uri}"),
-            (None, Some(ip)) => eprintln!("{yellow}404:{reset} {uri}  (by {ip})"),
-            (Some(r), Some(ip)) => eprintln!("{yellow}404:{reset} [{r} =>]  {uri}  (by {ip})"),
+            (None, None) => warn!(target: target, "{uri}"),
+            (Some(r), None) => warn!(target: target, "[{r} =>]  {uri}"),
+            (None, Some(ip)) => warn!(target: target, "{uri}  (by {ip})"),
+            (Some(r), Some(ip)) => warn!(target: target, "[{r} =>]  {uri}  (by {ip})"),
         }
     }
 }
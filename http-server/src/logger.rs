@@ -0,0 +1,65 @@
+//! Wrapper module for [`Logger`], the implementation of the `log` facade used by the server
+//!
+//! Every line is formatted as `<LEVEL> @ <timestamp> :: <message>`, with errors and warnings going
+//! to stderr and everything else to stdout. Records with the `NOT_FOUND_TARGET` target (emitted by
+//! `Log404`) are instead printed in their own colorized format, without the timestamp.
+//!
+//! This logger must be installed before Rocket is ignited; Rocket will otherwise install its own
+//! (and ours would then fail to initialize).
+
+use chrono::{SecondsFormat, Utc};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// The log target used by `Log404`, which gets its own special formatting
+pub static NOT_FOUND_TARGET: &str = "404";
+
+struct Logger {
+    level: LevelFilter,
+}
+
+/// Installs the logger as the global logger for the `log` crate, only displaying messages at or
+/// above the given level
+///
+/// This function may only be called once.
+pub fn init(level: LevelFilter) {
+    log::set_boxed_logger(Box::new(Logger { level })).expect("logger was already initialized");
+    log::set_max_level(level);
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        // Hyper is *very* noisy at the lower levels, so we only let its more severe messages
+        // through unless we're explicitly tracing.
+        let is_hyper = metadata.target().starts_with("hyper");
+        if is_hyper && self.level < LevelFilter::Trace {
+            return metadata.level() <= Level::Warn;
+        }
+
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        if record.target() == NOT_FOUND_TARGET {
+            let yellow = "\x1b[33m";
+            let reset = "\x1b[0m";
+            eprintln!("{yellow}404:{reset} {}", record.args());
+            return;
+        }
+
+        let time = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, false);
+        let level = record.level().as_str();
+
+        match record.level() {
+            Level::Error | Level::Warn => eprintln!("{} @ {} :: {}", level, time, record.args()),
+            _ => println!("{} @ {} :: {}", level, time, record.args()),
+        }
+    }
+
+    fn flush(&self) {
+        // Nothing to do; we don't buffer anything
+    }
+}
@@ -1,68 +1,285 @@
 #![feature(proc_macro_hygiene, decl_macro, path_file_prefix)]
 
+// Among other things: updates are read from a named pipe with `tail -f` (see `util::FifoFile`), and
+// graceful shutdown relies on POSIX signal masks and `sigwait` (see `shutdown`).
 #[cfg(not(target_os = "linux"))]
 compile_error!("this server makes assumptions that may only be true on Linux");
 
 use anyhow::{anyhow, Context};
-use chrono::{SecondsFormat, Utc};
-use rocket::response::NamedFile;
-use rocket::{get, http, routes};
+use arc_swap::ArcSwap;
+use chrono::{DateTime, FixedOffset};
+use log::{error, info};
+use rocket::response::{Content, NamedFile};
+use rocket::{get, http, routes, State};
 use rocket_contrib::templates::Template;
 use serde::Serialize;
+use std::env;
 use std::fs;
 use std::io::{self, BufRead, BufReader};
 use std::path::{Path, PathBuf};
 use std::process::exit;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
+mod access_log;
+mod admin;
 #[macro_use] // <- gives us `blog_routes!`
 mod blog;
+mod cache_control;
+mod catchers;
+mod compress;
+mod config;
+mod links;
 #[macro_use] // <- gives us `photos_routes!`
 mod photos;
 mod log_404;
+mod logger;
+mod metrics;
+mod rate_limit;
+mod security_headers;
+mod shutdown;
+mod static_assets;
+mod status;
 mod util;
 
-use util::FifoFile;
+use config::Config;
+use static_assets::StaticFile;
+use util::{FifoFile, IfModifiedSince, IfNoneMatch, SITE_URL};
 
 fn main() {
-    let rocket = rocket::ignite()
+    let args = parse_args();
+
+    let mut config = match Config::load() {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            exit(1);
+        }
+    };
+
+    args.override_config(&mut config);
+    // Any of the content directories missing would otherwise just result in an empty site
+    if let Err(e) = config.check_dirs() {
+        eprintln!("{:#}", e);
+        exit(1);
+    }
+
+    // The logger needs to be set up before Rocket is ignited; otherwise Rocket will install its own.
+    logger::init(config.log_level);
+
+    if let Err(e) = load_critical_css(&config) {
+        error!("{:#}", e);
+        exit(1);
+    }
+
+    if args.check_only {
+        load_content(&config, true);
+        info!("all content is valid");
+        return;
+    }
+
+    let rate_limit = match rate_limit::RateLimit::new(&config.rate_limit) {
+        Ok(r) => r,
+        Err(e) => {
+            error!("{:#}", e);
+            exit(1);
+        }
+    };
+
+    // Signal handling has to be set up before any other threads are spawned
+    shutdown::install(stop_listening_for_updates);
+
+    let mut rocket = rocket::ignite()
         .mount("/blog", blog_routes!())
         .mount("/photos", photos_routes!())
-        .mount("/", routes![index, static_asset])
-        .attach(Template::fairing())
-        .attach(log_404::Log404);
+        .mount(
+            "/",
+            routes![
+                index,
+                feed,
+                static_asset,
+                metrics::export,
+                status::status,
+                rate_limit::rate_limited
+            ],
+        )
+        .mount("/admin", routes![admin::update, admin::reprocess_photo])
+        .register(catchers::catchers_list())
+        .attach(Template::custom(|engines| {
+            static_assets::register_template_functions(&mut engines.tera)
+        }))
+        .attach(log_404::Log404)
+        .attach(metrics::Metrics)
+        .attach(compress::Compress)
+        .attach(cache_control::CacheControl::new(&config.cache))
+        .attach(rate_limit)
+        .attach(security_headers::SecurityHeaders::new(
+            &config.security_headers,
+        ))
+        .manage(config.clone());
+
+    if config.access_log {
+        rocket = rocket.attach(access_log::AccessLog);
+    }
 
-    if cfg!(not(debug_assertions)) {
-        blog::initialize();
-        photos::initialize();
+    // Rendering arbitrary markdown is only useful while writing posts, and the full dump of the
+    // loaded state is only useful while debugging locally, so neither exists in release builds
+    if cfg!(debug_assertions) {
+        rocket = rocket
+            .mount("/blog", routes![blog::preview])
+            .mount("/debug", routes![status::debug_state]);
     }
 
-    let updates_path_result = fs::canonicalize(UPDATE_PIPE_PATH)
-        .with_context(|| format!("failed to canonicalize updates path {:?}", UPDATE_PIPE_PATH));
+    // Must be attached last, so that it sees the final response body
+    rocket = rocket.attach(shutdown::TrackRequests);
+
+    // Like the rest of the startup checks, loading everything is skipped in debug builds so that
+    // startup is quick -- unless broken links are fatal, because they can only be found once
+    // everything is loaded
+    let load_now = cfg!(not(debug_assertions)) || config.broken_links_fatal;
+    load_content(&config, load_now);
+
+    let pipe_path = &config.update_pipe_path;
+    let updates_path_result = fs::canonicalize(pipe_path)
+        .with_context(|| format!("failed to canonicalize updates path {:?}", pipe_path));
 
     let updates_path = match updates_path_result {
         Ok(p) => p,
         Err(e) => {
-            eprintln!("{:#}", e);
+            error!("{:#}", e);
             exit(1);
         }
     };
 
-    thread::spawn(move || listen_for_updates(&updates_path));
+    let retry_wait = config.update_retry_wait();
+    thread::spawn(move || listen_for_updates(&updates_path, retry_wait));
 
     rocket.launch();
 }
 
-/// Name of the local directory used to store static content at the site root
-static STATIC_DIRNAME: &str = "static";
+/// Every update component that "all" expands to
+///
+/// "photos" already reloads the photo display settings, but "config" is still needed for the
+/// critical CSS.
+static ALL_UPDATE_COMPONENTS: &[&str] = &["static", "photos", "blog", "config"];
+
+/// Returns the first of the space-separated components in `request` that isn't a valid update
+/// component, if there is one
+fn unknown_update_component(request: &str) -> Option<&str> {
+    request
+        .split_whitespace()
+        .find(|c| !matches!(*c, "all" | "static" | "photos" | "blog" | "config"))
+}
+
+/// Command-line usage, printed for any unrecognized arguments
+static USAGE: &str =
+    "usage: http-server [--check] [--static-dir <path>] [--posts-dir <path>] [--photos-dir <path>]";
+
+/// The parsed command-line arguments
+///
+/// The directories override the ones in the config file, so that multiple instances of the site
+/// (e.g. a staging copy) can run from the same working directory.
+#[derive(Default)]
+struct Args {
+    /// Whether `--check` was given
+    ///
+    /// With `--check`, all of the content is loaded (exactly as it would be in a release build) to
+    /// check that it's valid, and then we exit without serving anything. Any problems produce a
+    /// nonzero exit code.
+    check_only: bool,
+    /// Override for `Config::static_dir`
+    static_dir: Option<PathBuf>,
+    /// Override for `BlogConfig::posts_dir`
+    posts_dir: Option<PathBuf>,
+    /// Override for `PhotosConfig::imgs_dir`
+    photos_dir: Option<PathBuf>,
+}
+
+impl Args {
+    /// Replaces the directories in `config` with any that were given on the command line
+    fn override_config(&self, config: &mut Config) {
+        if let Some(dir) = &self.static_dir {
+            config.static_dir = dir.clone();
+        }
+        if let Some(dir) = &self.posts_dir {
+            config.blog.posts_dir = dir.clone();
+        }
+        if let Some(dir) = &self.photos_dir {
+            config.photos.imgs_dir = dir.clone();
+        }
+    }
+}
+
+/// Parses the command-line arguments, exiting with the usage on anything that isn't recognized
+fn parse_args() -> Args {
+    let mut args = Args::default();
+
+    let mut iter = env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        let dir = match arg.as_str() {
+            "--check" => {
+                args.check_only = true;
+                continue;
+            }
+            "--static-dir" => &mut args.static_dir,
+            "--posts-dir" => &mut args.posts_dir,
+            "--photos-dir" => &mut args.photos_dir,
+            _ => {
+                eprintln!("unknown argument {:?}\n{}", arg, USAGE);
+                exit(2);
+            }
+        };
+
+        match iter.next() {
+            Some(path) => *dir = Some(path.into()),
+            None => {
+                eprintln!("missing path after {:?}\n{}", arg, USAGE);
+                exit(2);
+            }
+        }
+    }
+
+    args
+}
+
+/// Sets up the static assets, blog, and photos, exiting on any failure
+///
+/// If `load_now` is false, the blog posts and photos are only loaded once they're first needed,
+/// and links between them aren't checked.
+fn load_content(config: &Config, load_now: bool) {
+    static_assets::initialize(&config.static_dir);
+    // Blog posts can reference photos, so the photos need to be set up first
+    photos::initialize(&config.photos, load_now);
+    blog::initialize(&config.blog, load_now);
+
+    // Links between posts, photos, and albums can only be checked once everything is loaded
+    if load_now {
+        let broken = links::check();
+        if broken != 0 && config.broken_links_fatal {
+            error!("found {} broken link(s)", broken);
+            exit(1);
+        }
+    }
+}
+
 /// Name of the template used for the site root
 static INDEX_TEMPLATE_NAME: &str = "index";
-/// Filename of the pipe to listen to for updates to the site content
-static UPDATE_PIPE_PATH: &str = "updated";
-/// Time to wait if we can't open the updates pipe; 5 minutes.
-const UPDATE_RETRY_WAIT_DURATION: Duration = Duration::from_secs(300);
+/// Window of time after an update request in which any further requests are combined with it
+const UPDATE_DEBOUNCE_DURATION: Duration = Duration::from_secs(1);
+/// PID of the `tail` process currently reading from the update pipe, or zero if there isn't one
+static UPDATE_PIPE_PID: AtomicU32 = AtomicU32::new(0);
+
+lazy_static::lazy_static! {
+    /// Path of the critical CSS for the site root (`Config::index_critical_css`), set by
+    /// `load_critical_css`
+    static ref CRITICAL_CSS_PATH: ArcSwap<Option<PathBuf>> = ArcSwap::from(Arc::new(None));
+    /// Contents of the file at `CRITICAL_CSS_PATH`, if there is one. Reloaded by the "config"
+    /// update component.
+    static ref CRITICAL_CSS: ArcSwap<Option<String>> = ArcSwap::from(Arc::new(None));
+}
 
 /// Template context for the site root
 #[derive(Serialize)]
@@ -72,62 +289,229 @@ struct IndexContext {
 
     /// List of photo contexts, supplied by `crate::photos`
     photos: Vec<Arc<photos::PhotoInfo>>,
+    /// Album that the photos are viewed as part of, supplied by `crate::photos`
+    photos_album: String,
 
     flex_grid_settings: photos::FlexGridSettings,
+
+    /// Stylesheet to inline in the page, if there is one. When set, the full stylesheet is loaded
+    /// asynchronously instead.
+    critical_css: Arc<Option<String>>,
+}
+
+/// Reads the critical CSS for the site root, if it's configured
+fn load_critical_css(config: &Config) -> anyhow::Result<()> {
+    CRITICAL_CSS_PATH.store(Arc::new(config.index_critical_css.clone()));
+    reload_critical_css()
+}
+
+/// Re-reads the critical CSS from the path given to `load_critical_css`, to pick up any changes
+/// to the file
+fn reload_critical_css() -> anyhow::Result<()> {
+    let css = match &**CRITICAL_CSS_PATH.load() {
+        Some(path) => Some(
+            fs::read_to_string(path)
+                .with_context(|| format!("failed to read critical CSS file {:?}", path))?,
+        ),
+        None => None,
+    };
+
+    CRITICAL_CSS.store(Arc::new(css));
+    Ok(())
 }
 
 #[get("/")]
-fn index() -> Template {
+fn index(config: State<Config>) -> Template {
     let ctx = IndexContext {
-        posts: blog::recent_posts_context(),
-        photos: photos::recent_photos_context(),
+        posts: blog::recent_posts_context(config.index_num_posts),
+        photos: photos::recent_photos_context(config.index_num_photos),
+        photos_album: photos::all_album_path(),
         flex_grid_settings: photos::FlexGridSettings {
             ..Default::default()
         },
+        critical_css: CRITICAL_CSS.load_full(),
     };
 
     Template::render(INDEX_TEMPLATE_NAME, ctx)
 }
 
+/// Name of the template used for the combined feed
+static FEED_TEMPLATE_NAME: &str = "feed";
+/// Number of entries in the combined feed, if no limit is given
+const DEFAULT_FEED_LIMIT: usize = 20;
+/// Maximum number of entries in the combined feed, regardless of the requested limit
+const MAX_FEED_LIMIT: usize = 100;
+
+/// Template context for the combined feed
+#[derive(Serialize)]
+struct FeedContext {
+    site_url: &'static str,
+    /// The time of the most recent entry, in RFC 3339 format
+    updated: String,
+    entries: Vec<FeedEntry>,
+}
+
+/// A single blog post or photo in the combined feed
+#[derive(Serialize)]
+struct FeedEntry {
+    /// Either "post" or "photo", used as the entry's category
+    kind: &'static str,
+    title: String,
+    /// Path of the entry's page, relative to the site root
+    path: String,
+    /// The time at which the post was published or the photo was taken, in RFC 3339 format
+    published: String,
+    /// HTML summary of the entry, if there is one
+    summary: Option<String>,
+    /// Path of an image to display with the entry, relative to the site root
+    img_path: Option<String>,
+}
+
+/// Atom feed of the recent blog posts and photos together, most recent first
+///
+/// The photos are the same ones that are shown on the index page, alongside the most recent posts.
+/// The number of entries can be set with `limit`, up to `MAX_FEED_LIMIT`.
+#[get("/feed.xml?<limit>")]
+fn feed(limit: Option<usize>, config: State<Config>) -> Content<Template> {
+    let limit = limit.unwrap_or(DEFAULT_FEED_LIMIT).min(MAX_FEED_LIMIT);
+
+    let posts = blog::recent_posts_context(limit)
+        .into_iter()
+        .filter(|p| !p.is_hidden())
+        .map(|p| {
+            let entry = FeedEntry {
+                kind: "post",
+                title: p.title().to_owned(),
+                path: format!("/blog/{}", p.path().display()),
+                published: p.published_datetime().to_rfc3339(),
+                summary: Some(p.description().to_owned()),
+                img_path: None,
+            };
+            (p.published_datetime(), entry)
+        });
+
+    let photos = photos::recent_photos_context(config.index_num_photos)
+        .into_iter()
+        .map(|p| {
+            let entry = FeedEntry {
+                kind: "photo",
+                title: p.title().to_owned(),
+                path: format!("/photos/view/{}", p.file_name()),
+                published: p.taken_at().to_rfc3339(),
+                summary: p.description().map(String::from),
+                img_path: Some(p.small_img_path()),
+            };
+            (p.taken_at(), entry)
+        });
+
+    let mut entries: Vec<(DateTime<FixedOffset>, FeedEntry)> = posts.chain(photos).collect();
+    entries.sort_by(|(x, _), (y, _)| x.cmp(y).reverse());
+    entries.truncate(limit);
+
+    // With no entries, the feed was last updated whenever the content was loaded
+    let updated = match entries.first() {
+        Some((time, _)) => time.to_rfc3339(),
+        None => blog::generated_at()
+            .max(photos::generated_at())
+            .to_rfc3339(),
+    };
+
+    let ctx = FeedContext {
+        site_url: SITE_URL,
+        updated,
+        entries: entries.into_iter().map(|(_, e)| e).collect(),
+    };
+
+    let content_type = http::ContentType::new("application", "atom+xml");
+    Content(content_type, Template::render(FEED_TEMPLATE_NAME, ctx))
+}
+
 // Static assets are *accessed* as if they're in the root directory, but they're actually all
-// stored in the 'static' subdirectory. We have them over there just to keep things clean :)
+// stored in the 'static' subdirectory (or whatever's configured as `static_dir`). We have them over
+// there just to keep things clean :)
 //
 // Rocket incorrectly classifies the rank of this route, so we have to reduce its precedence a bit
 // extra (hence rank = 0)
+//
+// Assets can also be requested by their content-hashed names (see `static_assets`), in which case
+// they're allowed to be cached indefinitely (as long as the hash is the current one).
 #[get("/<file_path..>", rank = 0)]
-fn static_asset(file_path: PathBuf) -> Result<NamedFile, http::Status> {
+fn static_asset(
+    file_path: PathBuf,
+    config: State<Config>,
+    if_none_match: IfNoneMatch,
+    if_modified_since: IfModifiedSince,
+) -> Result<StaticFile, http::Status> {
+    let (file_path, immutable) =
+        static_assets::original_path(&file_path).unwrap_or((file_path, false));
+
     // Rocket's implementation of FromSegments for PathBuf ensures that we don't end up with paths
     // leading outside of the original directory -- i.e. it protects against path traversal
     // attacks.
     //
     //   per the Rocket docs: https://rocket.rs/v0.5-rc/guide/requests/#multiple-segments
-    NamedFile::open(Path::new(STATIC_DIRNAME).join(file_path)).map_err(|e| match e.kind() {
-        io::ErrorKind::NotFound => http::Status::NotFound,
-        _ => http::Status::InternalServerError,
+    let file = match NamedFile::open(config.static_dir.join(&file_path)) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(http::Status::NotFound),
+        Err(_) => return Err(http::Status::InternalServerError),
+    };
+
+    let hash = static_assets::asset_hash(&file_path);
+    let last_modified = (file.metadata().and_then(|m| m.modified()).ok())
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64);
+
+    // `If-Modified-Since` is only used if there's no `If-None-Match`, as required by RFC 7232
+    let not_modified = if if_none_match.is_present() {
+        hash.as_ref().is_some_and(|h| if_none_match.matches(h))
+    } else {
+        last_modified.is_some_and(|t| if_modified_since.is_unmodified(t))
+    };
+
+    Ok(StaticFile {
+        file: (!not_modified).then_some(file),
+        hash,
+        last_modified,
+        immutable,
     })
 }
 
-/// On each successful read of `UPDATE_PIPE_PATH`, calls the update functions for the relevant
+/// On each successful read of the update pipe, calls the update functions for the relevant
 /// components of the server
 ///
+/// Requests are passed to a separate thread (see [`process_update_requests`]), so that requests
+/// arriving in quick succession can be combined.
+///
 /// On a failed read, attempts to re-open the file. If the file cannot be opened, it will retry
-/// every `UPDATE_RETRY_WAIT_DURATION` and log an error each time it fails.
-fn listen_for_updates(canonical_path: &Path) -> ! {
-    // Helper function to format the current time
-    let get_time = || Utc::now().to_rfc3339_opts(SecondsFormat::Millis, false);
+/// every `retry_wait` and log an error each time it fails.
+///
+/// Returns once the server is shutting down (see [`stop_listening_for_updates`]). Any update that's
+/// already in progress is allowed to finish.
+fn listen_for_updates(canonical_path: &Path, retry_wait: Duration) {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || process_update_requests(rx));
 
-    loop {
+    while !shutdown::is_shutting_down() {
         // Try to get the file
         let file = loop {
             match FifoFile::open(canonical_path) {
                 Ok(f) => break f,
-                Err(e) => eprintln!("ERROR @ {} :: {}", get_time(), e),
+                Err(e) => error!("{}", e),
             }
 
             // Wait to retry.
-            thread::sleep(UPDATE_RETRY_WAIT_DURATION);
+            thread::sleep(retry_wait);
+            if shutdown::is_shutting_down() {
+                return;
+            }
         };
 
+        UPDATE_PIPE_PID.store(file.id(), Ordering::SeqCst);
+        // We might have started shutting down just before the PID was stored
+        if shutdown::is_shutting_down() {
+            return;
+        }
+
         let mut reader = BufReader::new(file);
 
         loop {
@@ -136,35 +520,169 @@ fn listen_for_updates(canonical_path: &Path) -> ! {
                 format!("failed to read from update pipe at {:?}", canonical_path)
             });
 
-            if let Err(e) = result {
-                eprintln!("ERROR @ {} :: {:#}", get_time(), e);
-                break; // Go back and try to re-open the file
+            if shutdown::is_shutting_down() {
+                return;
             }
 
-            println!("INFO @ {} :: received update request {:?}", get_time(), buf);
-
-            for component in buf.trim().split(' ') {
-                let func = match component {
-                    "photos" => photos::update,
-                    "blog" => blog::update,
-                    s => {
-                        let err = anyhow!("skipping unrecognized update component {:?}", s);
-                        eprintln!("ERROR @ {} :: {:#}", get_time(), err);
-                        continue;
-                    }
-                };
-
-                let result =
-                    func().with_context(|| format!("failed to update component {:?}", component));
-
-                if let Err(e) = result {
-                    eprintln!("ERROR @ {} :: {:#}", get_time(), e);
-                } else {
-                    println!("INFO @ {} :: updated component {:?}", get_time(), component);
+            match result {
+                Err(e) => {
+                    error!("{:#}", e);
+                    break; // Go back and try to re-open the file
                 }
+                // EOF; `tail` exited for some reason, so we need to re-open the file
+                Ok(0) => break,
+                Ok(_) => (),
             }
 
-            println!("INFO @ {} :: update complete", get_time());
+            info!("received update request {:?}", buf);
+            // The receiving thread never exits while we still hold the sender
+            let _ = tx.send(buf);
         }
     }
 }
+
+/// Stops [`listen_for_updates`], by killing the `tail` process that it's reading from
+///
+/// Called on shutdown, so that `tail` doesn't outlive us.
+fn stop_listening_for_updates() {
+    let pid = UPDATE_PIPE_PID.swap(0, Ordering::SeqCst);
+    if pid == 0 {
+        return;
+    }
+
+    // SAFETY: `kill` has no memory safety requirements. The worst case is that `tail` already
+    // exited and the PID was reused, which is vanishingly unlikely in the time we have.
+    if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } != 0 {
+        error!(
+            "failed to stop update pipe listener: {}",
+            io::Error::last_os_error()
+        );
+    }
+}
+
+/// Processes update requests from the pipe, combining all of the requests received within
+/// `UPDATE_DEBOUNCE_DURATION` of the first one
+///
+/// Each component is updated at most once per batch, so five "photos" lines in a second trigger a
+/// single rebuild. Requests that arrive while an update is in progress are batched for the next
+/// one.
+fn process_update_requests(rx: Receiver<String>) {
+    while let Ok(first) = rx.recv() {
+        // Components requested in this batch, in the order they were first requested
+        let mut components = Vec::new();
+        let mut add_request = |req: &str| {
+            for c in req.split_whitespace() {
+                if !components.iter().any(|existing| existing == c) {
+                    components.push(c.to_owned());
+                }
+            }
+        };
+
+        add_request(&first);
+
+        let deadline = Instant::now() + UPDATE_DEBOUNCE_DURATION;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match rx.recv_timeout(timeout) {
+                Ok(req) => add_request(&req),
+                Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        if components.is_empty() {
+            continue;
+        }
+
+        update_components(&components.join(" "));
+    }
+}
+
+/// Update function for the "config" component; see `update_components`
+fn update_config() -> anyhow::Result<()> {
+    photos::update_settings()?;
+    reload_critical_css()
+}
+
+/// Calls the update functions for each of the space-separated components in `request` (e.g.
+/// "photos blog")
+///
+/// The components are "static", "photos", "blog", and "config" -- which only reloads the photo
+/// display settings and the critical CSS for the site root, without re-processing the photos
+/// themselves. "all" is shorthand for every component.
+///
+/// Errors are logged as they occur. Returns the number of components that weren't successfully
+/// updated, including any that weren't recognized.
+///
+/// Only one set of updates runs at a time; concurrent callers will wait for the previous update to
+/// finish.
+fn update_components(request: &str) -> usize {
+    lazy_static::lazy_static! {
+        static ref UPDATE_LOCK: Mutex<()> = Mutex::new(());
+    }
+
+    // If a previous update panicked, there's no state behind the lock to be corrupted.
+    let _guard = UPDATE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let mut failed = 0;
+
+    let mut components: Vec<&str> = Vec::new();
+    for c in request.split_whitespace() {
+        let expanded = match c {
+            "all" => ALL_UPDATE_COMPONENTS.to_vec(),
+            _ => vec![c],
+        };
+
+        for c in expanded {
+            if !components.contains(&c) {
+                components.push(c);
+            }
+        }
+    }
+
+    // Blog posts include links to the current versions of photos, so they need to be regenerated
+    // whenever the photos change.
+    if components.contains(&"photos") && !components.contains(&"blog") {
+        components.push("blog");
+    }
+    // Blog posts can also reference static assets, which need to be available first
+    if let Some(i) = components.iter().position(|&c| c == "static") {
+        let c = components.remove(i);
+        components.insert(0, c);
+    }
+
+    for &component in &components {
+        let func = match component {
+            "photos" => photos::update,
+            "blog" => blog::update,
+            "static" => static_assets::update,
+            "config" => update_config,
+            s => {
+                let err = anyhow!("skipping unrecognized update component {:?}", s);
+                error!("{:#}", err);
+                failed += 1;
+                continue;
+            }
+        };
+
+        let result = func().with_context(|| format!("failed to update component {:?}", component));
+
+        if let Err(e) = result {
+            error!("{:#}", e);
+            failed += 1;
+        } else {
+            info!("updated component {:?}", component);
+        }
+    }
+
+    // Broken links are only ever fatal at startup; there's no sense in taking the site down over
+    // them after it's running.
+    if components.contains(&"photos") || components.contains(&"blog") {
+        links::check();
+    }
+
+    match failed {
+        0 => info!("update complete"),
+        n => error!("update finished with {} failed component(s)", n),
+    }
+    failed
+}
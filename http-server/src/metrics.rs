@@ -0,0 +1,232 @@
+//! Wrapper module for the [`Metrics`] fairing and the `/metrics` route that exposes what it
+//! collects
+//!
+//! Everything is exported in the Prometheus text format. Recording a request is just a handful of
+//! atomic increments (plus a read-lock on the map of routes), so this shouldn't have any
+//! noticeable effect on image serving.
+
+use lazy_static::lazy_static;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::get;
+use rocket::http::{ContentType, Status, StatusClass};
+use rocket::response::Content;
+use rocket::{Data, Request, Response};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::Instant;
+
+use crate::{blog, photos};
+
+/// Upper bounds of the latency histogram buckets, in seconds
+///
+/// The final `+Inf` bucket is implicit; it's the same as the total count.
+static LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Label used for requests that didn't match any route
+static UNMATCHED_ROUTE: &str = "<none>";
+
+lazy_static! {
+    /// Global storage for everything we've recorded
+    static ref METRICS: MetricsState = MetricsState::default();
+}
+
+pub struct Metrics;
+
+/// Marker stored in each request's local cache so that we can measure its latency
+struct RequestStart(Instant);
+
+#[derive(Default)]
+struct MetricsState {
+    /// Per-route information, keyed by the name of the route's handler
+    routes: RwLock<HashMap<&'static str, RouteMetrics>>,
+    /// Count of responses, by status class (1xx, 2xx, 3xx, 4xx, 5xx)
+    status_classes: [AtomicU64; 5],
+    /// Total number of 404 responses -- complements the logging from `Log404`
+    not_found: AtomicU64,
+}
+
+#[derive(Default)]
+struct RouteMetrics {
+    count: AtomicU64,
+    /// Sum of all request durations, in microseconds
+    latency_sum_micros: AtomicU64,
+    /// Non-cumulative counts for each bucket in `LATENCY_BUCKETS`; the extra entry at the end is
+    /// for anything above the largest bucket
+    latency_buckets: [AtomicU64; 12],
+}
+
+impl Fairing for Metrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "Metrics",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        request.local_cache(|| RequestStart(Instant::now()));
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        // If `on_request` didn't run for some reason, we'll just record a latency of zero.
+        let elapsed = request
+            .local_cache(|| RequestStart(Instant::now()))
+            .0
+            .elapsed();
+
+        let status = response.status();
+        let class_idx = match status.class() {
+            StatusClass::Informational => Some(0),
+            StatusClass::Success => Some(1),
+            StatusClass::Redirection => Some(2),
+            StatusClass::ClientError => Some(3),
+            StatusClass::ServerError => Some(4),
+            StatusClass::Unknown => None,
+        };
+
+        if let Some(i) = class_idx {
+            METRICS.status_classes[i].fetch_add(1, Ordering::Relaxed);
+        }
+
+        if status == Status::NotFound {
+            METRICS.not_found.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let route = request
+            .route()
+            .and_then(|r| r.name)
+            .unwrap_or(UNMATCHED_ROUTE);
+
+        // Fast path: the route has already been seen, so we only need the read lock.
+        let routes = METRICS.routes.read().unwrap();
+        if let Some(m) = routes.get(route) {
+            m.record(elapsed.as_secs_f64());
+            return;
+        }
+        drop(routes);
+
+        METRICS
+            .routes
+            .write()
+            .unwrap()
+            .entry(route)
+            .or_default()
+            .record(elapsed.as_secs_f64());
+    }
+}
+
+impl RouteMetrics {
+    fn record(&self, secs: f64) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.latency_sum_micros
+            .fetch_add((secs * 1_000_000.0) as u64, Ordering::Relaxed);
+
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|&b| secs <= b)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.latency_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[get("/metrics")]
+pub fn export() -> Content<String> {
+    let content_type = ContentType::with_params("text", "plain", ("version", "0.0.4"));
+    Content(content_type, METRICS.render())
+}
+
+impl MetricsState {
+    /// Produces the Prometheus text format for all of the metrics
+    fn render(&self) -> String {
+        // Writing to a `String` never fails, so we ignore all of the `fmt::Result`s below.
+        let mut out = String::new();
+
+        let _ = writeln!(
+            out,
+            "# HELP http_requests_total Total number of requests, by route"
+        );
+        let _ = writeln!(out, "# TYPE http_requests_total counter");
+        let routes = self.routes.read().unwrap();
+        for (name, m) in routes.iter() {
+            let count = m.count.load(Ordering::Relaxed);
+            let _ = writeln!(out, "http_requests_total{{route=\"{}\"}} {}", name, count);
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP http_request_duration_seconds Request latency, by route"
+        );
+        let _ = writeln!(out, "# TYPE http_request_duration_seconds histogram");
+        for (name, m) in routes.iter() {
+            let mut cumulative = 0;
+            for (i, bound) in LATENCY_BUCKETS.iter().enumerate() {
+                cumulative += m.latency_buckets[i].load(Ordering::Relaxed);
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{route=\"{}\",le=\"{}\"}} {}",
+                    name, bound, cumulative
+                );
+            }
+
+            cumulative += m.latency_buckets[LATENCY_BUCKETS.len()].load(Ordering::Relaxed);
+            let sum = m.latency_sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_bucket{{route=\"{}\",le=\"+Inf\"}} {}",
+                name, cumulative
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_sum{{route=\"{}\"}} {}",
+                name, sum
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_count{{route=\"{}\"}} {}",
+                name, cumulative
+            );
+        }
+        drop(routes);
+
+        let _ = writeln!(
+            out,
+            "# HELP http_responses_total Total number of responses, by status class"
+        );
+        let _ = writeln!(out, "# TYPE http_responses_total counter");
+        for (i, class) in ["1xx", "2xx", "3xx", "4xx", "5xx"].iter().enumerate() {
+            let count = self.status_classes[i].load(Ordering::Relaxed);
+            let _ = writeln!(out, "http_responses_total{{class=\"{}\"}} {}", class, count);
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP http_not_found_total Total number of 404 responses"
+        );
+        let _ = writeln!(out, "# TYPE http_not_found_total counter");
+        let _ = writeln!(
+            out,
+            "http_not_found_total {}",
+            self.not_found.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP blog_posts_loaded Number of blog posts currently loaded"
+        );
+        let _ = writeln!(out, "# TYPE blog_posts_loaded gauge");
+        let _ = writeln!(out, "blog_posts_loaded {}", blog::num_posts());
+
+        let _ = writeln!(
+            out,
+            "# HELP photos_loaded Number of photos currently loaded"
+        );
+        let _ = writeln!(out, "# TYPE photos_loaded gauge");
+        let _ = writeln!(out, "photos_loaded {}", photos::num_photos());
+
+        out
+    }
+}
@@ -5,28 +5,41 @@
 
 use anyhow::{anyhow, bail, Context, Result};
 use arc_swap::ArcSwap;
-use chrono::{Date, DateTime, FixedOffset, TimeZone};
+use chrono::{Date, DateTime, Datelike, FixedOffset, Local, NaiveDate, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
 use glob::glob;
 use lazy_static::lazy_static;
+use log::{debug, error, info, warn};
 use rayon::prelude::*;
-use rocket::response::{self, NamedFile, Responder};
+use rocket::response::{self, Body, Content, NamedFile, Responder};
 use rocket::{get, http, uri, Request};
+use rocket_contrib::json::Json;
 use rocket_contrib::templates::Template;
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
-use std::collections::hash_map::Entry;
-use std::collections::{BTreeSet, HashMap};
+use std::collections::hash_map::{Entry, RandomState};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::fmt::{self, Debug, Formatter};
 use std::fs;
-use std::io::{self, Cursor, Write};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
 use std::ops::Range;
 use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
 
+use crate::config::PhotosConfig;
 use crate::util::{
-    format_datetime, is_uri_idempotent, markdown_to_html, FormatLevel, MaybeRedirect,
+    clips, content_hash, etag, format_date_range, format_datetime, is_uri_idempotent, jpeg_meta,
+    json_ld::{GeoCoordinates, ImageObject, Place},
+    markdown_to_html, progress,
+    progress::ProgressMode,
+    slugify, sprite, tiles,
+    watermark::Watermark,
+    zip::{ZipEntry, ZipStream},
+    FormatLevel, IfNoneMatch, LocalImage, MarkdownKind, MaybeRedirect, SITE_URL,
 };
 
 /// Helper macro so that mounting the routes will work correctly at the crate root
@@ -37,8 +50,24 @@ macro_rules! photos_routes {
             crate::photos::albums,
             crate::photos::img_page,
             crate::photos::album_page,
+            crate::photos::album_download,
+            crate::photos::random,
+            crate::photos::orphans,
+            crate::photos::missing_alt_text,
             crate::photos::img,
+            crate::photos::media,
             crate::photos::map,
+            crate::photos::on_this_day,
+            crate::photos::near,
+            crate::photos::camera,
+            crate::photos::lens,
+            crate::photos::meta,
+            crate::photos::more,
+            crate::photos::api_albums,
+            crate::photos::tiles_info,
+            crate::photos::tile,
+            crate::photos::sprite_manifest,
+            crate::photos::sprite_sheet,
         ]
     }};
 }
@@ -55,17 +84,32 @@ static IMG_TEMPLATE_NAME: &str = "photos/photo";
 static ALBUM_TEMPLATE_NAME: &str = "photos/album";
 /// Name of the template used for the page containing a map of every image with a location
 static MAP_TEMPLATE_NAME: &str = "photos/map";
-
-/// Directory that images (+ album lists, metadata) are stored in
-static IMGS_DIRECTORY: &str = "content/photos";
-/// Pattern inside `IMGS_DIRECTORY` to match each individual photo
-static IMGS_GLOB: &str = "*.jpg";
-/// The extension used for "full" images, stored on disk
-static FULL_IMG_EXT: &str = "jpg";
-/// File name inside `IMGS_DIRECTORY` that the meta information about albums is stored at
+/// Name of the template used for photos taken on a particular day of the year, across all years
+static ON_THIS_DAY_TEMPLATE_NAME: &str = "photos/on-this-day";
+/// Name of the template used for the photos taken with a particular camera or lens
+static GEAR_TEMPLATE_NAME: &str = "photos/gear";
+/// Name of the template used for the photos taken near a particular location
+static NEAR_TEMPLATE_NAME: &str = "photos/near";
+
+/// Patterns inside the images directory to match each individual photo
+///
+/// Video clips and animations are matched by `CLIP_GLOBS` instead.
+static IMGS_GLOBS: &[&str] = &["*.jpg", "*.webp", "*.png", "*.tif", "*.tiff"];
+/// Patterns inside the images directory to match each video clip or animation, which are displayed
+/// alongside the photos
+static CLIP_GLOBS: &[&str] = &["*.mp4", "*.gif"];
+/// File name inside the images directory that the meta information about albums is stored at
 static ALBUMS_META_FILENAME: &str = "albums.json";
-/// File name inside `IMGS_DIRECTORY` in which the default configuration for `FlexGrid` is stored
+/// Pattern inside the images directory to match additional files with album information, which
+/// are merged with the contents of `ALBUMS_META_FILENAME`
+static ALBUMS_META_GLOB: &str = "albums/*.json";
+/// File name inside the images directory in which the default configuration for `FlexGrid` is stored
 static FLEXGRID_SETTINGS_FILENAME: &str = "default-flex-grid-config.json";
+/// Name of the (optional) file in the images directory with the display names for camera gear
+static GEAR_NAMES_FILENAME: &str = "gear-names.json";
+/// Directory inside the images directory with the (optional) metadata overrides for each photo,
+/// at `<file name>.json`. See `PhotoOverrides`.
+static OVERRIDES_DIR: &str = "overrides";
 
 /// The prefix on the first line of the description used to indicate it's providing the alt text of
 /// the image
@@ -74,26 +118,31 @@ static FLEXGRID_SETTINGS_FILENAME: &str = "default-flex-grid-config.json";
 /// be ignored on that line.
 static ALT_TEXT_PREFIX: &str = "alt:";
 
-/// Number of photos to show at the site root, as a preview
-const NUM_PREVIEW_PHOTOS: usize = 5;
-/// Album to display from to show at the site root
-static PREVIEW_ALBUM: &str = FAVORITES_ALBUM_NAME;
+/// Maximum number of recent photos to show in place of the favorites or preview album, if it's
+/// missing or empty
+const NUM_FALLBACK_PHOTOS: usize = 24;
 
-/// Path-name of the "album" that holds every photo.
-///
-/// This album is auto-generated, and must not be specified manually in the list of albums.
-static ALL_ALBUM_PATH: &str = "all";
-static ALL_ALBUM_NAME: &str = "All photos";
-static ALL_ALBUM_DESC: &str = "All of my photos on this site, each and every one";
-/// Name of the "favorites" album
+/// Amount to reduce the WEBP quality by on each attempt to fit a small image within
+/// `PhotosConfig::small_img_max_bytes`
+const SMALL_IMG_QUALITY_STEP: f32 = 10.0;
+/// Lowest WEBP quality that we'll reduce to when fitting a small image within
+/// `PhotosConfig::small_img_max_bytes`
+const MIN_SMALL_IMG_QUALITY: f32 = 50.0;
+
+/// Maximum width & height of the square album cover crops; see `PhotosConfig::crop_album_covers`
+const COVER_IMG_SIZE: u32 = 512;
+
+/// Maximum number of rendered zoom tiles to keep in memory
+const MAX_CACHED_TILES: usize = 1024;
+/// Maximum number of watermarked full-size images to keep in memory
 ///
-/// We use this to make the displayed content slightly different for photos that are a favorite.
-static FAVORITES_ALBUM_NAME: &str = "favorites";
+/// These are much larger than the tiles -- usually a few megabytes each -- so we keep far fewer.
+const MAX_CACHED_WATERMARKED: usize = 16;
 
-/// Approximate desired pixel count of the smaller versions of images
-const SMALL_IMG_APROX_PIXELCOUNT: u64 = 480_000; // ≈ 800x600
-/// WEBP quality to encode the small images with
-const SMALL_IMG_QUALITY: f32 = 80.0;
+/// Reserved path in the albums info files for the list of collections, instead of an album
+///
+/// See [`ParsedCollection`].
+static COLLECTIONS_PATH: &str = "collections";
 
 /// The value of the 'Cache-Control' header that we set for image requests
 ///
@@ -101,20 +150,39 @@ const SMALL_IMG_QUALITY: f32 = 80.0;
 /// practically matter.
 static PHOTO_CACHE_POLICY: &str = "max-age=2592000, immutable";
 
-/// Default map view for the "global" map -- the one containing every photo
-const GLOBAL_MAP_VIEW: MapView = MapView {
+/// Map view for a filtered map with no photos to show -- the whole world
+const WORLD_MAP_VIEW: MapView = MapView {
     centered_at: GPSCoords {
-        lat: 37.839,
-        lon: -122.396,
+        lat: 20.0,
+        lon: 0.0,
     },
-    zoom_level: 11,
+    zoom_level: 2,
 };
 
+/// Zoom level for the map on a photo's page, if none of its albums provide one
+const DEFAULT_PHOTO_MAP_ZOOM: u8 = 12;
+
+/// Maximum zoom level when fitting the map to a set of photos, so that a tight cluster of them
+/// still shows some surroundings
+const MAX_FITTED_MAP_ZOOM: u8 = 15;
+
+/// Radius around the location for the `near` route, if one isn't given
+const DEFAULT_NEAR_RADIUS_KM: f64 = 1.0;
+/// Maximum radius around the location for the `near` route; anything larger is reduced to this
+const MAX_NEAR_RADIUS_KM: f64 = 50.0;
+/// Number of photos returned by the `more` route, if a count isn't given
+const DEFAULT_MORE_COUNT: usize = 24;
+/// Maximum number of photos returned by the `more` route; any larger count is reduced to this
+const MAX_MORE_COUNT: usize = 100;
+/// Mean radius of the Earth, for great-circle distances
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
 /// Parameters for `FlexGrid` -- refer to 'static/js/flex-grid.js' for more
 ///
-/// A "default" set of values is parsed from 'content/photos/default-flex-grid-config.json', and is
-/// what's used in the implementation of [`Default`]. `FlexGridSettings::default` cannot be used
-/// before calling [`initialize`].
+/// A "default" set of values is parsed from 'default-flex-grid-config.json' in the images
+/// directory, and is what's used in the implementation of [`Default`]. `FlexGridSettings::default`
+/// cannot be used before calling [`initialize`], and always gives the most recently loaded values
+/// -- they're reloaded by [`update`] and [`update_settings`].
 ///
 /// All of the fields are renamed during (de-)serialization so that they match the naming of the
 /// Javascript constructor.
@@ -165,8 +233,8 @@ impl Default for FlexGridSettings {
 }
 
 impl FlexGridSettings {
-    fn load_default() -> Result<Self> {
-        let path = Path::new(IMGS_DIRECTORY).join(FLEXGRID_SETTINGS_FILENAME);
+    fn load_default(imgs_dir: &Path) -> Result<Self> {
+        let path = imgs_dir.join(FLEXGRID_SETTINGS_FILENAME);
         let file_content = fs::read_to_string(&path).with_context(|| {
             format!(
                 "failed to read default `FlexGrid` config from file {:?}",
@@ -174,22 +242,302 @@ impl FlexGridSettings {
             )
         })?;
 
-        serde_json::from_str(&file_content)
-            .with_context(|| format!("failed to parse `FlexGridSettings` in file {:?}", path))
+        let settings: Self = serde_json::from_str(&file_content)
+            .with_context(|| format!("failed to parse `FlexGridSettings` in file {:?}", path))?;
+        settings
+            .validate()
+            .with_context(|| format!("invalid `FlexGridSettings` in file {:?}", path))?;
+
+        Ok(settings)
+    }
+
+    /// Checks the constraints between the values that can't be expressed by their types alone
+    fn validate(&self) -> Result<()> {
+        if self.min_columns > self.max_columns {
+            bail!(
+                "minColumns ({}) is greater than maxColumns ({})",
+                self.min_columns,
+                self.max_columns
+            );
+        } else if self.column_width_range.start > self.column_width_range.end {
+            bail!(
+                "columnWidthRange {:?} is backwards",
+                self.column_width_range
+            );
+        } else if self.max_sequential_multi == 0 {
+            bail!("maxSequentialMulti must be greater than zero");
+        }
+
+        Ok(())
     }
 
     fn update_from_fs(&self) -> Result<Option<Self>> {
         let old = FlexGridSettings::default();
-        let new = Self::load_default()?;
+        let new = Self::load_default(&CONFIG.load().imgs_dir)?;
 
         // Only update if it's different from before
         Ok((new != old).then(|| new))
     }
 }
 
+/// Per-album overrides for some of the [`FlexGridSettings`]
+///
+/// Each field corresponds to the one in `FlexGridSettings` with the same name; any that aren't
+/// given fall back to the defaults. The overrides are applied when the album is displayed, so that
+/// changes to the defaults still apply to everything that isn't overridden.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FlexGridOverrides {
+    #[serde(rename = "minColumns")]
+    min_columns: Option<u64>,
+    #[serde(rename = "maxColumns")]
+    max_columns: Option<u64>,
+    #[serde(rename = "minColumnWidth")]
+    min_column_width: Option<u64>,
+    #[serde(rename = "columnWidthRange")]
+    column_width_range: Option<Range<u64>>,
+    padding: Option<u64>,
+    #[serde(rename = "maxColumnCrop")]
+    max_column_crop: Option<f64>,
+    #[serde(rename = "maxMultiCrop")]
+    max_multi_crop: Option<f64>,
+    #[serde(rename = "maxMultiColumnHeightMultiplier")]
+    max_multi_column_height_multiplier: Option<f64>,
+    #[serde(rename = "maxSequentialMulti")]
+    max_sequential_multi: Option<u64>,
+}
+
+impl FlexGridOverrides {
+    /// Returns `base` with the overrides applied
+    fn apply(&self, base: FlexGridSettings) -> FlexGridSettings {
+        FlexGridSettings {
+            min_columns: self.min_columns.unwrap_or(base.min_columns),
+            max_columns: self.max_columns.unwrap_or(base.max_columns),
+            min_column_width: self.min_column_width.unwrap_or(base.min_column_width),
+            column_width_range: (self.column_width_range.clone())
+                .unwrap_or(base.column_width_range),
+            padding: self.padding.unwrap_or(base.padding),
+            max_column_crop: self.max_column_crop.unwrap_or(base.max_column_crop),
+            max_multi_crop: self.max_multi_crop.unwrap_or(base.max_multi_crop),
+            max_multi_column_height_multiplier: self
+                .max_multi_column_height_multiplier
+                .unwrap_or(base.max_multi_column_height_multiplier),
+            max_sequential_multi: self
+                .max_sequential_multi
+                .unwrap_or(base.max_sequential_multi),
+        }
+    }
+}
+
+/// Display names for camera gear, loaded from `GEAR_NAMES_FILENAME`
+///
+/// The EXIF tags for the same manufacturer can vary quite a bit (e.g. "NIKON CORPORATION" vs
+/// "Nikon"), so this maps the raw strings to the names we'd like to show. Anything not in the
+/// table is displayed as-is.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct GearNames {
+    /// Camera and lens makes, from the `Make` and `LensMake` tags
+    makes: HashMap<String, String>,
+    /// Camera models, from the `Model` tag (after the make is stripped from it)
+    models: HashMap<String, String>,
+    /// Lens models, from the `LensModel` tag
+    lenses: HashMap<String, String>,
+}
+
+impl GearNames {
+    /// Loads the table from the images directory, or an empty one if there's no file for it
+    fn load(imgs_dir: &Path) -> Result<Self> {
+        let path = imgs_dir.join(GEAR_NAMES_FILENAME);
+        if !path.exists() {
+            return Ok(GearNames::default());
+        }
+
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read gear names from file {:?}", path))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("failed to parse gear names in file {:?}", path))
+    }
+
+    fn lookup(table: &HashMap<String, String>, raw: String) -> String {
+        table.get(&raw).cloned().unwrap_or(raw)
+    }
+
+    /// Normalizes the camera's `(make, model)`
+    fn camera(&self, (make, model): (String, String)) -> (String, String) {
+        let make = Self::lookup(&self.makes, make);
+        let mut model = Self::lookup(&self.models, model);
+
+        // The canonical make might still prefix the model, even if the original one didn't (e.g.
+        // "NIKON CORPORATION" with "NIKON Z 6"), so we also check that -- ignoring case.
+        let has_prefix = model
+            .get(..make.len())
+            .is_some_and(|p| p.eq_ignore_ascii_case(&make));
+        if has_prefix {
+            model = model[make.len()..].trim_start().to_owned();
+        }
+
+        (make, model)
+    }
+
+    /// Normalizes the lens's `(make, model)`
+    fn lens(&self, (make, model): (String, String)) -> (String, String) {
+        (
+            Self::lookup(&self.makes, make),
+            Self::lookup(&self.lenses, model),
+        )
+    }
+}
+
+/// Metadata for a single photo that takes precedence over what's embedded in it, loaded from
+/// `OVERRIDES_DIR`
+///
+/// This is mostly for scanned film, where the embedded metadata describes the scanner instead of
+/// the camera -- and there's nowhere standard to put the film stock. It's also an easier way to fix
+/// a typo in a caption than editing the photo itself. Anything not given here falls through to the
+/// embedded metadata.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PhotoOverrides {
+    title: Option<String>,
+    /// Markdown description of the photo. Unlike the embedded one, this never contains the alt
+    /// text; that's given by `alt` instead.
+    description: Option<String>,
+    alt: Option<String>,
+    /// `(make, model)` of the camera. Unlike the embedded metadata, this isn't changed by
+    /// `GearNames`. If the photo doesn't have any camera information, this adds it -- without any
+    /// of the exposure settings.
+    camera: Option<(String, String)>,
+    /// `(make, model)` of the lens, like `camera`. The camera must be known, either from the
+    /// embedded metadata or from `camera`.
+    lens: Option<(String, String)>,
+    /// Whether to remove the exposure settings (ISO, shutter speed, aperture, and focal length)
+    /// from the embedded metadata -- e.g. because they're the scanner's
+    #[serde(default)]
+    clear_exposure: bool,
+    /// The film stock the photo was taken on, e.g. "Kodak Portra 400"
+    film: Option<String>,
+    /// Markdown notes about the photo, displayed below its description
+    notes: Option<String>,
+}
+
+impl PhotoOverrides {
+    /// Loads the overrides for the photo, or `None` if there aren't any
+    fn load(imgs_dir: &Path, file_name: &str) -> Result<Option<Self>> {
+        let path = imgs_dir
+            .join(OVERRIDES_DIR)
+            .join(format!("{}.json", file_name));
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read overrides from file {:?}", path))?;
+        serde_json::from_str(&content)
+            .map(Some)
+            .with_context(|| format!("failed to parse overrides in file {:?}", path))
+    }
+
+    /// Replaces the parts of `exif_info` that are given by the overrides
+    fn apply(self, exif_info: &mut PhotoExifInfo) -> Result<()> {
+        if let Some(title) = self.title {
+            exif_info.title = title;
+        }
+        if let Some(desc) = self.description {
+            exif_info.description = Some(markdown_to_html(&desc, MarkdownKind::Snippet));
+        }
+        if let Some(alt) = self.alt {
+            exif_info.alt_text = Some(alt);
+        }
+
+        if let Some(id) = self.camera {
+            match &mut exif_info.camera {
+                Some(camera) => {
+                    camera.camera_slug = gear_slug(&id.0, &id.1);
+                    camera.id = id;
+                }
+                // Film scans often don't have any camera information at all
+                None => exif_info.camera = Some(CameraInfo::without_settings(id)),
+            }
+        }
+        if let Some(id) = self.lens {
+            let camera = (exif_info.camera.as_mut())
+                .ok_or_else(|| anyhow!("can't override the lens without a camera"))?;
+            camera.lens_slug = Some(gear_slug(&id.0, &id.1));
+            camera.lens_id = Some(id);
+        }
+        if self.clear_exposure {
+            if let Some(camera) = &mut exif_info.camera {
+                camera.iso = None;
+                camera.f_stop = None;
+                camera.focal_length = None;
+                camera.exposure_time = None;
+                camera.exposure_value = None;
+            }
+        }
+
+        if let Some(film) = self.film {
+            exif_info.film = Some(film);
+        }
+        if let Some(notes) = self.notes {
+            exif_info.notes = Some(markdown_to_html(&notes, MarkdownKind::Snippet));
+        }
+
+        Ok(())
+    }
+}
+
+/// Produces the URL-safe identifier for a piece of gear, from its make and model; e.g.
+/// "nikon-z-6" for ("Nikon", "Z 6")
+fn gear_slug(make: &str, model: &str) -> String {
+    slugify(&format!("{} {}", make, model))
+}
+
 /// Storage type for album information
 type AlbumsInformation = Vec<(String, ParsedAlbum)>;
 
+/// The contents of a single file of album information, before the entries are parsed
+///
+/// Either form is accepted. The list preserves the order of the albums in the file; the map is
+/// ordered by album path.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AlbumsInfoFile {
+    List(Vec<(String, serde_json::Value)>),
+    Map(BTreeMap<String, serde_json::Value>),
+}
+
+/// Parsed information about the "all photos" album
+///
+/// Unlike the other albums, this one is generated automatically; the albums info file can only
+/// provide a couple of settings for it, under the reserved album path given by
+/// `PhotosConfig::all_album_path`.
+#[derive(Default, Deserialize)]
+struct ParsedAllPhotosAlbum {
+    /// The path name of the image to represent the album
+    ///
+    /// If not given, the median photo by time is used.
+    cover_img: Option<String>,
+}
+
+/// Parsed information about a collection of albums, grouped together on the albums page
+///
+/// The collections are given as a list under the reserved path `COLLECTIONS_PATH` in the albums
+/// info files, in the order that they're displayed.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ParsedCollection {
+    /// The displayed name of the collection
+    name: String,
+    /// A markdown description of the collection
+    #[serde(default)]
+    description: String,
+    /// Paths of the albums in the collection, in the order that they're displayed
+    ///
+    /// Only albums from the albums info files can be included -- not automatic day albums.
+    albums: Vec<String>,
+}
+
 /// Parsed information about an individual album
 ///
 /// The version that we actually store replaces strings for each photo with the reference to the
@@ -207,14 +555,93 @@ struct ParsedAlbum {
     display: AlbumDisplayOrder,
     /// A markdown description of the album
     description: String,
+    /// Longer-form markdown notes about the album, displayed below its photos
+    ///
+    /// Unlike the description, these aren't shown in album listings -- so they're a good place for
+    /// things like a full writeup of a trip.
+    notes: Option<String>,
     /// The path name of the image to represent this album -- ideally unique, but not required to
     /// be.
-    cover_img: String,
+    ///
+    /// If not given, the first photo displayed in the album is used.
+    cover_img: Option<String>,
     /// Ordered listing of all of the photos. `photos[0]` is displayed first, `photos[1]` second,
     /// etc.
     photos: Vec<String>,
+    /// Override for `PhotosConfig::small_img_aprox_pixelcount` for the photos in this album
+    ///
+    /// Because photos can be in multiple albums, overrides are taken first from the photo's
+    /// location album, then its day album, then any other albums in alphabetical order.
+    small_img_aprox_pixelcount: Option<u64>,
+    /// Override for `PhotosConfig::small_img_quality` for the photos in this album, with the same
+    /// precedence as `small_img_aprox_pixelcount`
+    small_img_quality: Option<f32>,
+    /// Zoom level for the map on the page of each photo in this album, with the same precedence as
+    /// `small_img_aprox_pixelcount`
+    ///
+    /// Typically set on location albums: closer for tightly clustered city shots, wider for
+    /// landscapes.
+    map_zoom_level: Option<u8>,
+    /// Whether to draw a watermark on the full-size versions of the photos in this album
+    ///
+    /// If a photo is in *any* album with this set, it's watermarked. Its zoom tiles are disabled
+    /// too, because they'd otherwise provide a way around it.
+    #[serde(default)]
+    watermark: bool,
+    /// Whether the album is left out of the album listings, for staging it before it's public
+    ///
+    /// Hidden albums (and their photos) are otherwise processed as normal, and are still available
+    /// by direct link. Debug builds list them anyways, with a marker.
+    #[serde(default)]
+    hidden: bool,
+    /// Overrides for the default flex-grid settings when displaying this album; e.g. fewer, wider
+    /// columns for an album of panoramas
+    #[serde(default)]
+    flex_grid: FlexGridOverrides,
+    /// Whether to turn off downloading the entire album as a ZIP file (see `album_download`)
+    #[serde(default)]
+    disable_download: bool,
+}
+
+/// Cache of the smaller images, keyed by the hash of the full image, so that identical images are
+/// only processed once
+///
+/// Each entry is `None` only while the image is being processed (or if processing failed).
+type SmallerImgCache = Mutex<HashMap<String, Arc<Mutex<Option<InMemImg>>>>>;
+
+/// The settings used to create the smaller version of a photo
+#[derive(Debug, Copy, Clone)]
+struct SmallImgSettings {
+    aprox_pixelcount: u64,
+    quality: f32,
+    /// Copied from `PhotosConfig::small_img_max_bytes`
+    max_bytes: Option<u64>,
+    /// Copied from `PhotosConfig::max_source_pixelcount`
+    max_source_pixelcount: u64,
+}
+
+/// Error from making the smaller version of an image that's too large to decode, even at the
+/// smallest scale we can decode it at
+///
+/// Photos with this error are skipped instead of failing the entire load.
+#[derive(Debug)]
+struct SourceTooLarge {
+    width: u32,
+    height: u32,
+}
+
+impl fmt::Display for SourceTooLarge {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "source image is too large ({}x{})",
+            self.width, self.height
+        )
+    }
 }
 
+impl std::error::Error for SourceTooLarge {}
+
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
 enum ParsedAlbumKind {
     #[serde(rename = "location")]
@@ -233,48 +660,151 @@ enum AlbumDisplayOrder {
 }
 
 lazy_static! {
+    /// Cache of the album sprite sheets that have been built, keyed by `Album::sprite_key`
+    static ref SPRITE_CACHE: Mutex<HashMap<String, Arc<SpriteSheet>>> =
+        Mutex::new(HashMap::new());
+
+    /// Cache of the zoom tiles that have been rendered
+    static ref TILE_CACHE: Mutex<BoundedCache<TileKey>> =
+        Mutex::new(BoundedCache::new(MAX_CACHED_TILES));
+
+    /// Cache of the watermarked full images that have been rendered, keyed by
+    /// `PhotoInfo::full_img_rev`
+    static ref WATERMARKED_CACHE: Mutex<BoundedCache<String>> =
+        Mutex::new(BoundedCache::new(MAX_CACHED_WATERMARKED));
+
+    /// Watermarked images that are currently being rendered, keyed like `WATERMARKED_CACHE`. See
+    /// `watermarked_img`.
+    static ref WATERMARKING: Mutex<HashMap<String, WatermarkedSlot>> = Mutex::new(HashMap::new());
+
+    /// The watermark to draw, loaded from `PhotosConfig::watermark_font` on first use and again on
+    /// every `update`
+    static ref WATERMARK: ArcSwap<Result<Watermark, String>> =
+        ArcSwap::from(Arc::new(load_watermark(&CONFIG.load())));
+
+    /// The full image (by hash) that zoom tiles were most recently requested for, and its slot
+    /// for the decoded image. See `decoded_full_img`.
+    static ref LAST_DECODED_IMG: Mutex<Option<(String, DecodedImgSlot)>> = Mutex::new(None);
+
+    /// Configuration for the photos, set by `initialize`
+    static ref CONFIG: ArcSwap<PhotosConfig> = ArcSwap::from(Arc::new(PhotosConfig::default()));
+
     /// Full state of all of the photos
-    static ref STATE: ArcSwap<PhotosState> = match PhotosState::new() {
-        Ok(s) => ArcSwap::new(Arc::new(s)),
+    static ref STATE: ArcSwap<PhotosState> = match PhotosState::new(&CONFIG.load()) {
+        Ok(s) => {
+            STATE_LOADED.store(true, Ordering::Release);
+            ArcSwap::new(Arc::new(s))
+        }
         Err(e) => {
-            eprintln!("failed to create `PhotosState`: {:#}", e);
+            error!("failed to create `PhotosState`: {:#}", e);
             exit(1)
         }
     };
 
-    /// The default settings provided to a `FlexGrid`, loaded from
-    /// 'content/photos/default-flex-grid-config.json'
-    static ref DEFAULT_FLEXGRID_SETTINGS: ArcSwap<FlexGridSettings> = match FlexGridSettings::load_default()
-    {
-        Ok(s) => ArcSwap::from(Arc::new(s)),
-        Err(e) => {
-            eprintln!("failed to load default `FlexGridSettings`: {:#}", e);
-            exit(1)
+    /// The default settings provided to a `FlexGrid`, loaded from 'default-flex-grid-config.json'
+    /// in the images directory
+    static ref DEFAULT_FLEXGRID_SETTINGS: ArcSwap<FlexGridSettings> = {
+        match FlexGridSettings::load_default(&CONFIG.load().imgs_dir) {
+            Ok(s) => ArcSwap::from(Arc::new(s)),
+            Err(e) => {
+                error!("failed to load default `FlexGridSettings`: {:#}", e);
+                exit(1)
+            }
         }
     };
 }
 
-/// Collects all of the necessary information about the photos we have stored, causing any failures
-/// to happen immediately
+/// Whether `STATE` has been initialized; see `is_loaded`
+static STATE_LOADED: AtomicBool = AtomicBool::new(false);
+
+/// Returns whether the photos have been loaded yet
+///
+/// With lazy loading (i.e. in debug builds), the photos are only loaded when they're first needed.
+/// Blog posts only use the photos to improve how they're displayed, so they check this instead of
+/// forcing the photos to load.
+pub fn is_loaded() -> bool {
+    STATE_LOADED.load(Ordering::Acquire)
+}
+
+/// Sets the configuration for the photos and -- if `load_now` -- collects all of the necessary
+/// information about the photos we have stored, causing any failures to happen immediately
 ///
-/// Any failures encountered will result in an immediate exit.
-pub fn initialize() {
-    lazy_static::initialize(&DEFAULT_FLEXGRID_SETTINGS);
-    lazy_static::initialize(&STATE);
+/// Any failures encountered will result in an immediate exit. Otherwise, the state is only
+/// collected when it's first needed, so that startup is quick.
+///
+/// Once the state is collected, any photos that aren't in an album (other than their auto-generated
+/// date album) are logged, so that they can be filed.
+pub fn initialize(config: &PhotosConfig, load_now: bool) {
+    CONFIG.store(Arc::new(config.clone()));
+
+    if load_now {
+        lazy_static::initialize(&DEFAULT_FLEXGRID_SETTINGS);
+        lazy_static::initialize(&STATE);
+
+        let state = STATE.load();
+        if !state.orphans.is_empty() {
+            info!(
+                "{} photo(s) aren't in any album: {:?}",
+                state.orphans.len(),
+                state.orphans
+            );
+        }
+
+        let missing_alt = state.missing_alt_text(config);
+        if !missing_alt.is_empty() {
+            warn!(
+                "{} photo(s) don't have any alt text: {:?}",
+                missing_alt.len(),
+                missing_alt
+            );
+        }
+    }
 }
 
 /// Re-makes the `PhotosState` and/or default `FlexGridSettings` to incorporate any recent file
 /// changes
 pub fn update() -> Result<()> {
+    // The settings don't depend on the photos, so a problem with one of them shouldn't prevent the
+    // other from being updated
+    let settings_result = update_settings();
+
+    // The watermark is part of the revision of watermarked images, so it has to be reloaded before
+    // the photos are processed
+    WATERMARK.store(Arc::new(load_watermark(&CONFIG.load())));
+
     let new_state_opt = STATE
         .load()
         .update_from_fs()
         .context("could not update PhotosState")?;
 
     if let Some(new_state) = new_state_opt {
+        // Drop any sprite sheets for sets of photos that aren't an album anymore
+        let current_keys: HashSet<_> = new_state.albums.values().map(|a| a.sprite_key()).collect();
+        SPRITE_CACHE
+            .lock()
+            .unwrap()
+            .retain(|key, _| current_keys.contains(key));
+
+        // ... and any watermarked images that aren't watermarked (or don't exist, or have a
+        // different watermark) anymore
+        WATERMARKED_CACHE.lock().unwrap().retain(|rev| {
+            new_state
+                .images
+                .values()
+                .any(|i| i.watermarked && i.full_img_rev == *rev)
+        });
+
         STATE.store(Arc::new(new_state));
     }
 
+    settings_result
+}
+
+/// Reloads the default `FlexGridSettings`, if they've changed
+///
+/// This is much cheaper than a full `update`, for when only the settings have changed. If the new
+/// settings can't be loaded, the previous ones are kept.
+pub fn update_settings() -> Result<()> {
     let new_default_flexgrid_opt = DEFAULT_FLEXGRID_SETTINGS
         .load()
         .update_from_fs()
@@ -312,6 +842,7 @@ pub fn img_page(
     name: Cow<str>,
     album: Option<String>,
 ) -> Result<MaybeRedirect<Template>, http::Status> {
+    let album = album.filter(|a| !a.is_empty());
     let ctx = match with_state(|s| s.img_page_context(&name, album))? {
         MaybeRedirect::Dont(c) => c,
         MaybeRedirect::Redirect {
@@ -337,150 +868,1055 @@ pub fn album_page(name: Cow<str>) -> Option<Template> {
     Some(Template::render(ALBUM_TEMPLATE_NAME, ctx))
 }
 
-#[get("/map")]
-pub fn map() -> Template {
-    let ctx = with_state(|s| s.map_context());
-    Template::render(MAP_TEMPLATE_NAME, ctx)
+/// Downloads the original files for all of the photos in the album, as a ZIP file
+///
+/// Each file is named by the photo's title. Albums can opt out with `disable_download`, and the
+/// album of all photos is never available (it's just too big). Albums with any watermarked photos
+/// aren't available either, because that would give away the photos without the watermark.
+#[get("/album/<name>/download.zip")]
+pub fn album_download(name: Cow<str>) -> Result<AlbumZip, http::Status> {
+    let album =
+        with_state(|s| s.albums.get(name.as_ref()).cloned()).ok_or(http::Status::NotFound)?;
+    if !album.downloadable {
+        return Err(http::Status::NotFound);
+    }
+
+    // Titles aren't necessarily unique, so any duplicates get a number added
+    let mut used_names = HashSet::new();
+    let entries = album
+        .photos
+        .iter()
+        .map(|p| {
+            let stem = p.exif_info.title.replace(['/', '\\'], "_");
+            let ext = p.path.extension().and_then(|e| e.to_str()).unwrap_or("");
+
+            let mut name = format!("{}.{}", stem, ext);
+            let mut n = 1;
+            while !used_names.insert(name.clone()) {
+                n += 1;
+                name = format!("{} ({}).{}", stem, n, ext);
+            }
+
+            ZipEntry {
+                name,
+                path: p.path.clone(),
+                modified: p.exif_info.actual_datetime.naive_local(),
+            }
+        })
+        .collect();
+
+    match ZipStream::new(entries) {
+        Ok(zip) => Ok(AlbumZip {
+            file_name: format!("{}.zip", album.path),
+            zip,
+        }),
+        Err(e) => {
+            error!("failed to make ZIP for album {:?}: {:#}", album.path, e);
+            Err(http::Status::InternalServerError)
+        }
+    }
 }
 
-pub fn recent_photos_context() -> Vec<Arc<PhotoInfo>> {
-    STATE
-        .load()
-        .albums
-        .get(PREVIEW_ALBUM)
-        .map(|a| a.photos.iter().cloned().take(NUM_PREVIEW_PHOTOS).collect())
-        .unwrap_or_default()
+/// Redirects to the page for a random photo -- either from all of them, or from the album, if one is
+/// given
+#[get("/random?<album>")]
+pub fn random(album: Option<String>) -> Result<MaybeRedirect<()>, http::Status> {
+    let album = album.filter(|a| !a.is_empty());
+    let name = with_state(|s| s.random_img(album.as_deref()))?;
+
+    // Without an album, we leave out the query entirely -- an empty one would be treated as an
+    // album that doesn't exist
+    let new_url = match album {
+        Some(a) => uri!("/photos", img_page: Cow::Owned(name), a),
+        None => uri!("/photos", img_page: Cow::Owned(name), _),
+    };
+
+    Ok(MaybeRedirect::Redirect {
+        new_url,
+        is_permanent: false,
+    })
 }
 
-// We include hashes in the image URLs so that they can be cached forever -- any updates to the
-// image will change the hash, so it'll be a different URL.
-//
-// Per MDN:
-//
-//     A modern best practice for static resources is to include version/hashes in their URLs,
-//     while never modifying the resources — but instead, when necessary, updating the resources
-//     with newer versions that have new version-numbers/hashes, so that their URLs are different.
-//     That’s called the cache-busting pattern.
-//
-// https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Cache-Control#immutable
-#[get("/img-file/<name>?<size>&<rev>")]
-pub fn img(
-    name: Cow<str>,
-    size: Option<String>,
-    rev: Option<String>,
-) -> Result<MaybeRedirect<ImageSource>, http::Status> {
-    let size = size.unwrap_or_default();
+/// Lists the photos that aren't in any album from the albums info file, as JSON
+///
+/// Only available in debug builds; it's just an aid for filing photos into albums.
+#[get("/orphans")]
+pub fn orphans() -> Option<Json<Vec<String>>> {
+    if cfg!(not(debug_assertions)) {
+        return None;
+    }
 
-    // The 'size' must be one of `small` or `full`
-    let is_full = match size.as_str() {
-        "full" => true,
-        "small" => false,
+    Some(Json(with_state(|s| s.orphans.clone())))
+}
+
+/// Lists the photos without explicit alt text, as JSON. See `PhotosState::missing_alt_text`.
+///
+/// Only available in debug builds, like `orphans`.
+#[get("/missing-alt")]
+pub fn missing_alt_text() -> Option<Json<Vec<String>>> {
+    if cfg!(not(debug_assertions)) {
+        return None;
+    }
+
+    Some(Json(with_state(|s| s.missing_alt_text(&CONFIG.load()))))
+}
+
+/// Displays a map of every photo with a location -- or only the favorites, and/or only the photos
+/// from a particular album
+#[get("/map?<favorites>&<album>")]
+pub fn map(favorites: Option<bool>, album: Option<String>) -> Option<Template> {
+    let ctx = with_state(|s| s.map_context(favorites.unwrap_or(false), album.as_deref()))?;
+    Some(Template::render(MAP_TEMPLATE_NAME, ctx))
+}
+
+/// Displays all of the photos taken on a particular day of the year (in any year), with the date
+/// given as "MM-DD". If no date is given, today's date is used.
+#[get("/on-this-day?<date>")]
+pub fn on_this_day(date: Option<String>) -> Result<Template, http::Status> {
+    let (month, day) = match date {
+        None => {
+            let today = Local::today();
+            (today.month(), today.day())
+        }
+        Some(d) => parse_month_day(&d).ok_or(http::Status::BadRequest)?,
+    };
+
+    let ctx = with_state(|s| s.on_this_day_context(month, day));
+    Ok(Template::render(ON_THIS_DAY_TEMPLATE_NAME, ctx))
+}
+
+/// Displays all of the photos taken within `radius_km` of the location, most recent first
+///
+/// The radius defaults to `DEFAULT_NEAR_RADIUS_KM`, and is capped at `MAX_NEAR_RADIUS_KM`. Both
+/// `lat` and `lon` are required.
+#[get("/near?<lat>&<lon>&<radius_km>")]
+pub fn near(
+    lat: Option<f64>,
+    lon: Option<f64>,
+    radius_km: Option<f64>,
+) -> Result<Template, http::Status> {
+    let center = match (lat, lon) {
+        (Some(lat), Some(lon))
+            if (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon) =>
+        {
+            GPSCoords { lat, lon }
+        }
         _ => return Err(http::Status::BadRequest),
     };
 
-    // Store a state guard separately to get around borrowing issues
-    let state = STATE.load();
+    let radius_km = match radius_km {
+        None => DEFAULT_NEAR_RADIUS_KM,
+        Some(r) if r > 0.0 && r.is_finite() => r.min(MAX_NEAR_RADIUS_KM),
+        Some(_) => return Err(http::Status::BadRequest),
+    };
+
+    let ctx = with_state(|s| s.near_context(center, radius_km));
+    Ok(Template::render(NEAR_TEMPLATE_NAME, ctx))
+}
 
+/// Displays all of the photos taken with a particular camera, given by its `CameraInfo::camera_slug`
+#[get("/camera/<id>")]
+pub fn camera(id: Cow<str>) -> Option<Template> {
+    let ctx = with_state(|s| s.gear_context(&s.by_camera, &id))?;
+    Some(Template::render(GEAR_TEMPLATE_NAME, ctx))
+}
+
+/// Displays all of the photos taken with a particular lens, given by its `CameraInfo::lens_slug`
+#[get("/lens/<id>")]
+pub fn lens(id: Cow<str>) -> Option<Template> {
+    let ctx = with_state(|s| s.gear_context(&s.by_lens, &id))?;
+    Some(Template::render(GEAR_TEMPLATE_NAME, ctx))
+}
+
+/// Provides all of the metadata we've extracted from the photo's EXIF tags, as JSON
+#[get("/meta/<name>")]
+pub fn meta(name: Cow<str>) -> Result<Json<PhotoMeta>, http::Status> {
+    let state = STATE.load();
     let img = state
         .images
         .get(name.as_ref())
         .ok_or(http::Status::NotFound)?;
 
-    let target_hash = match is_full {
-        true => &img.full_img_hash,
-        false => &img.smaller_webp.hash,
+    let exif_info = img.exif_info.clone();
+    Ok(Json(PhotoMeta {
+        datetime: exif_info.actual_datetime.to_rfc3339(),
+        exif_info,
+    }))
+}
+
+/// Provides the next `count` photos after the one named by `after`, in the order they were taken,
+/// for adding more photos to a page without reloading it
+///
+/// If `after` isn't given, the list starts from the first photo. The count defaults to
+/// `DEFAULT_MORE_COUNT`, and is capped at `MAX_MORE_COUNT`. Past the end of the list, the result
+/// is empty.
+#[get("/more?<after>&<count>")]
+pub fn more(
+    after: Option<String>,
+    count: Option<usize>,
+) -> Result<Json<Vec<MorePhoto>>, http::Status> {
+    let state = STATE.load();
+    let photos = &state.images_by_time;
+
+    let start = match after {
+        None => 0,
+        Some(name) => {
+            let idx = (photos.iter())
+                .position(|p| p.file_name == name)
+                .ok_or(http::Status::BadRequest)?;
+            idx + 1
+        }
     };
 
-    let rev_is_some = rev.is_some();
-    if *target_hash != rev.unwrap_or_default() {
-        return Ok(MaybeRedirect::Redirect {
-            new_url: uri!("/photos", img: name, size, target_hash),
-            // Only permanently redirect previous revisions. Perma-links to the image might
-            // eventually change
-            is_permanent: rev_is_some,
-        });
-    }
+    let count = count.unwrap_or(DEFAULT_MORE_COUNT).min(MAX_MORE_COUNT);
+    let list = (photos[start..].iter())
+        .take(count)
+        .map(|p| MorePhoto {
+            file_name: p.file_name.clone(),
+            title: p.exif_info.title.clone(),
+            alt: p.alt.clone(),
+            small_img_path: p.small_img_path(),
+            width: p.smaller_webp.width,
+            height: p.smaller_webp.height,
+        })
+        .collect();
 
-    if !is_full {
-        Ok(MaybeRedirect::Dont(ImageSource::InMem(
-            img.smaller_webp.clone(),
-        )))
-    } else {
-        NamedFile::open(full_img_path(name.as_ref()))
-            // We already had an entry for this file; if we couldn't find it, then that's an error on
-            // our part.
-            .map_err(|_| http::Status::InternalServerError)
-            .map(StoredImage)
-            .map(ImageSource::File)
-            .map(MaybeRedirect::Dont)
-    }
+    Ok(Json(list))
 }
 
-/// Returns the path of the full image with the given name
-fn full_img_path(img_name: &str) -> PathBuf {
-    let mut p = Path::new(IMGS_DIRECTORY).join(img_name);
-    p.set_extension(FULL_IMG_EXT);
-    p
-}
+/// Compact JSON listing of the albums, without their photos, for clients that load each album
+/// separately
+///
+/// Albums are listed in the same order as on the albums page. The list can be limited to one kind
+/// of album with `?kind=normal`, `?kind=day`, or `?kind=location`.
+#[get("/api/albums?<kind>")]
+pub fn api_albums(kind: Option<String>) -> Result<Json<Vec<AlbumSummary>>, http::Status> {
+    let state = STATE.load();
+    let in_order = &state.albums_in_order;
 
-impl PhotosState {
-    /// Creates the `PhotosState`
-    fn new() -> Result<Self> {
-        // Step 1
-        //
-        // Parse the information about the albums & collect album membership for each image
-        let (all_albums, all_album_paths) = {
-            let parsed = Self::get_albums_info().context("failed to read albums info file")?;
+    let groups = [
+        ("normal", &in_order.normal_albums),
+        ("day", &in_order.days),
+        ("location", &in_order.locations),
+    ];
 
-            let names = parsed
-                .iter()
-                .map(|(name, _)| name.clone())
-                .collect::<Vec<_>>();
-            let all = parsed.into_iter().collect::<HashMap<_, _>>();
+    if let Some(k) = &kind {
+        if !groups.iter().any(|(name, _)| name == k) {
+            return Err(http::Status::BadRequest);
+        }
+    }
 
-            (all, names)
-        };
+    let list = (groups.iter())
+        .filter(|(name, _)| kind.as_deref().map_or(true, |k| k == *name))
+        .flat_map(|&(name, albums)| {
+            albums.iter().map(move |a| AlbumSummary {
+                path: a.path.clone(),
+                name: a.name.clone(),
+                kind: name,
+                photo_count: a.photo_count,
+                cover_img_path: a.cover_img.small_img_path(),
+            })
+        })
+        .collect();
 
-        if all_albums.contains_key(ALL_ALBUM_NAME) {
-            bail!(
-                "albums info file contains reserved album name {:?}",
-                ALL_ALBUM_NAME
-            )
-        }
+    Ok(Json(list))
+}
 
-        // Photo file name -> unsorted list of album memberships
-        let mut album_membership = <HashMap<String, Vec<AlbumReference>>>::new();
+/// The Deep Zoom descriptor for the image, for use with a zooming viewer
+///
+/// The tiles themselves are served by [`tile`], at the location relative to this that the DZI
+/// format expects.
+#[get("/tiles/<name>/info.dzi")]
+pub fn tiles_info(name: Cow<str>) -> Result<Content<String>, http::Status> {
+    // Like in `decoded_full_img`, watermarked photos (and clips) don't have zoom tiles -- and
+    // neither do photos that are too large to decode in full
+    let max_pixelcount = CONFIG.load().max_source_pixelcount;
+    let (width, height) = with_state(|s| {
+        s.images
+            .get(name.as_ref())
+            .filter(|i| !i.watermarked)
+            .and_then(|i| i.full_size)
+    })
+    .filter(|&(w, h)| w as u64 * h as u64 <= max_pixelcount)
+    .ok_or(http::Status::NotFound)?;
+
+    Ok(Content(
+        http::ContentType::XML,
+        tiles::dzi_descriptor(width, height),
+    ))
+}
 
-        for (path, info) in all_albums.iter() {
-            if !is_uri_idempotent(path) {
-                bail!(
-                    "bad album name {:?}: must URI encode to the same value",
-                    path
-                );
+/// A single tile of the full image, as a JPEG. `tile` is given as "<x>_<y>.jpg"
+#[get("/tiles/<name>/info_files/<level>/<tile>")]
+pub fn tile(name: Cow<str>, level: u32, tile: String) -> Result<Tile, http::Status> {
+    let (x, y) = tile
+        .strip_suffix(".jpg")
+        .and_then(|t| t.split_once('_'))
+        .and_then(|(x, y)| Some((x.parse().ok()?, y.parse().ok()?)))
+        .ok_or(http::Status::NotFound)?;
+
+    let hash = with_state(|s| {
+        s.images
+            .get(name.as_ref())
+            .filter(|i| !i.watermarked)
+            .map(|i| i.full_img_hash.clone())
+    })
+    .ok_or(http::Status::NotFound)?;
+    let key = (hash, level, x, y);
+
+    if let Some(data) = TILE_CACHE.lock().unwrap().get(&key) {
+        return Ok(Tile(data));
+    }
+
+    let img = decoded_full_img(&name)?;
+    let data = tiles::render_tile(&img, level, x, y)
+        .map_err(|e| {
+            error!(
+                "failed to render tile {:?} for image {:?}: {:#}",
+                key, name, e
+            );
+            http::Status::InternalServerError
+        })?
+        .ok_or(http::Status::NotFound)?;
+
+    let data: Arc<[u8]> = data.into();
+    TILE_CACHE.lock().unwrap().insert(key, data.clone());
+    Ok(Tile(data))
+}
+
+/// A single zoom tile, as a JPEG -- shared with `TILE_CACHE`, so that it isn't copied
+pub struct Tile(Arc<[u8]>);
+
+impl<'r> Responder<'r> for Tile {
+    fn respond_to(self, _: &Request) -> response::Result<'r> {
+        let mut builder = rocket::Response::build();
+        builder
+            .header(http::ContentType::JPEG)
+            .sized_body(Cursor::new(self.0));
+        Ok(builder.finalize())
+    }
+}
+
+/// The layout of the album's sprite sheet, as JSON
+///
+/// The sheet packs the small versions of every photo in the album into a single image, so that
+/// they can all be loaded with one request.
+#[get("/sprites/<name>/manifest.json")]
+pub fn sprite_manifest(name: Cow<str>) -> Result<Json<SpriteManifest>, http::Status> {
+    let sheet = album_sprite_sheet(&name)?;
+    Ok(Json(sheet.manifest.clone()))
+}
+
+/// The album's sprite sheet image, as a WEBP. Refer to [`sprite_manifest`] for where each photo is
+#[get("/sprites/<name>/sheet.webp")]
+pub fn sprite_sheet(name: Cow<str>) -> Result<Content<Vec<u8>>, http::Status> {
+    let sheet = album_sprite_sheet(&name)?;
+    Ok(Content(http::ContentType::WEBP, sheet.webp.to_vec()))
+}
+
+/// Returns the sprite sheet for the album, building it if there isn't one for the album's current
+/// set of photos
+///
+/// Returns `NotFound` if the album doesn't exist, has no photos, or has too many photos to fit in
+/// a single sheet.
+fn album_sprite_sheet(name: &str) -> Result<Arc<SpriteSheet>, http::Status> {
+    let album = with_state(|s| s.albums.get(name).cloned()).ok_or(http::Status::NotFound)?;
+    let key = album.sprite_key();
+
+    if let Some(sheet) = SPRITE_CACHE.lock().unwrap().get(&key) {
+        return Ok(sheet.clone());
+    }
+
+    // Like the OG images for blog posts, we don't hold the lock while building the sheet. It might
+    // occasionally be built twice, but the result is the same either way.
+    let sheet = SpriteSheet::build(&album, &key)
+        .map_err(|e| {
+            error!("failed to build sprite sheet for album {:?}: {:#}", name, e);
+            http::Status::InternalServerError
+        })?
+        .ok_or(http::Status::NotFound)?;
+
+    let sheet = Arc::new(sheet);
+    SPRITE_CACHE.lock().unwrap().insert(key, sheet.clone());
+    Ok(sheet)
+}
+
+/// A single image containing the small versions of every photo in an album
+struct SpriteSheet {
+    manifest: SpriteManifest,
+    /// The WEBP-encoded sheet
+    webp: Arc<[u8]>,
+}
+
+/// The information provided by `sprite_manifest`
+#[derive(Clone, Serialize)]
+pub struct SpriteManifest {
+    /// URL of the sheet image, which changes whenever the sheet does
+    sheet: String,
+    width: u32,
+    height: u32,
+    /// Where each photo is in the sheet, in the same order as the album
+    photos: Vec<SpritePhoto>,
+}
+
+#[derive(Clone, Serialize)]
+struct SpritePhoto {
+    /// The "path name" of the photo
+    name: String,
+    #[serde(flatten)]
+    rect: sprite::SpriteRect,
+}
+
+impl SpriteSheet {
+    /// Builds the sprite sheet for the album, returning `None` if it has too many photos to fit (or
+    /// none at all)
+    fn build(album: &Album, key: &str) -> Result<Option<Self>> {
+        let dimensions: Vec<_> = album
+            .photos
+            .iter()
+            .map(|p| (p.smaller_webp.width, p.smaller_webp.height))
+            .collect();
+
+        let layout = match sprite::layout(&dimensions) {
+            Some(l) => l,
+            None => return Ok(None),
+        };
+
+        let imgs = album
+            .photos
+            .iter()
+            .map(|p| {
+                webp::Decoder::new(&p.smaller_webp.img_data)
+                    .decode()
+                    .map(|img| img.to_image())
+                    .ok_or_else(|| anyhow!("failed to decode small image for {:?}", p.file_name))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let webp = sprite::render(&layout, &imgs, CONFIG.load().small_img_quality)?;
+
+        let photos = album
+            .photos
+            .iter()
+            .zip(&layout.rects)
+            .map(|(p, rect)| SpritePhoto {
+                name: p.file_name.clone(),
+                rect: *rect,
+            })
+            .collect();
+
+        Ok(Some(SpriteSheet {
+            manifest: SpriteManifest {
+                sheet: format!("/photos/sprites/{}/sheet.webp?rev={}", album.path, key),
+                width: layout.width,
+                height: layout.height,
+                photos,
+            },
+            webp: Arc::from(webp.into_boxed_slice()),
+        }))
+    }
+}
+
+/// Decoded full image, shared by every request for tiles from it
+///
+/// The inner value is `None` only while the image is being decoded (or if decoding failed).
+type DecodedImgSlot = Arc<Mutex<Option<Arc<image::DynamicImage>>>>;
+
+/// Returns the decoded full version of the image, reusing the last one if it was the same image
+///
+/// Zooming in on an image typically requests a bunch of tiles from it at once, and decoding the
+/// full image is by far the most expensive part of producing a tile, so it's worth keeping
+/// around. Concurrent requests for the same image wait on its slot, so that only one of them
+/// decodes it; requests for other images aren't held up.
+fn decoded_full_img(name: &str) -> Result<Arc<image::DynamicImage>, http::Status> {
+    // Clips can't be decoded, so they don't have zoom tiles. Watermarked photos don't either,
+    // because the tiles would give the full-size image without the watermark.
+    let (hash, path) = with_state(|s| {
+        s.images
+            .get(name)
+            .filter(|i| i.media_kind == MediaKind::Photo && !i.watermarked)
+            .map(|i| (i.full_img_hash.clone(), i.path.clone()))
+    })
+    .ok_or(http::Status::NotFound)?;
+
+    let slot = {
+        let mut last = LAST_DECODED_IMG.lock().unwrap_or_else(|e| e.into_inner());
+        match &*last {
+            Some((last_hash, slot)) if *last_hash == hash => slot.clone(),
+            _ => {
+                let slot = DecodedImgSlot::default();
+                *last = Some((hash, slot.clone()));
+                slot
             }
+        }
+    };
 
-            for p in &info.photos {
-                let album_ref = AlbumReference {
-                    path: path.clone(),
-                    name: info.name.clone(),
-                };
+    // If an earlier attempt failed, we'll try again ourselves
+    let mut decoded = slot.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(img) = &*decoded {
+        return Ok(img.clone());
+    }
 
-                album_membership
-                    .entry(p.clone())
-                    .or_default()
-                    .push(album_ref);
+    let max_pixelcount = CONFIG.load().max_source_pixelcount;
+    let img = fs::read(&path)
+        .with_context(|| format!("failed to read file {:?}", path))
+        .and_then(|data| {
+            decode_full_img(&data, max_pixelcount)
+                .with_context(|| format!("failed to decode image {:?}", path))
+        })
+        .map_err(|e| {
+            error!("{:#}", e);
+            http::Status::InternalServerError
+        })?;
+
+    let img = Arc::new(img);
+    *decoded = Some(img.clone());
+    Ok(img)
+}
+
+/// Decodes the full image, at full scale
+///
+/// Unlike when making the smaller versions of images, a reduced scale wouldn't do here. So
+/// anything over `max_pixelcount` is a [`SourceTooLarge`] error instead, checked before decoding.
+fn decode_full_img(img_data: &[u8], max_pixelcount: u64) -> Result<image::DynamicImage> {
+    let (width, height) = image::io::Reader::new(Cursor::new(img_data))
+        .with_guessed_format()
+        .context("failed to guess image format")?
+        .into_dimensions()
+        .context("failed to read image dimensions")?;
+    if width as u64 * height as u64 > max_pixelcount {
+        return Err(SourceTooLarge { width, height }.into());
+    }
+
+    image::load_from_memory(img_data).context("failed to decode image")
+}
+
+/// Loads the watermark given by the config, or the reason there isn't one
+fn load_watermark(config: &PhotosConfig) -> Result<Watermark, String> {
+    match &config.watermark_font {
+        Some(font) => Watermark::load(font, &config.watermark_text)
+            .map_err(|e| format!("failed to load watermark: {:#}", e)),
+        None => Err("no watermark font set".to_owned()),
+    }
+}
+
+/// Watermarked image that's being rendered, shared by every request for it in the meantime
+///
+/// Like `DecodedImgSlot`, the inner value is `None` until it's been rendered (or if that failed).
+type WatermarkedSlot = Arc<Mutex<Option<Arc<[u8]>>>>;
+
+/// Returns the watermarked version of the full image (given by its `PhotoInfo::full_img_rev`),
+/// rendering it if it isn't already cached
+///
+/// Rendering happens without holding the lock on the cache, so other images can still be served
+/// from it in the meantime. Concurrent requests for the same image wait on its entry in
+/// `WATERMARKING`, so that only one of them does the expensive work.
+fn watermarked_img(path: &Path, rev: &str) -> Result<Arc<[u8]>, http::Status> {
+    let cached = || {
+        let cache = WATERMARKED_CACHE.lock().unwrap_or_else(|e| e.into_inner());
+        cache.get(&rev.to_owned())
+    };
+
+    if let Some(data) = cached() {
+        return Ok(data);
+    }
+
+    let slot = {
+        let mut in_flight = WATERMARKING.lock().unwrap_or_else(|e| e.into_inner());
+        in_flight.entry(rev.to_owned()).or_default().clone()
+    };
+
+    // If an earlier attempt failed, we'll try again ourselves. The image might have also been
+    // finished (and its entry removed) just before we got the slot.
+    let mut rendered = slot.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(data) = rendered.clone().or_else(cached) {
+        return Ok(data);
+    }
+
+    let result = render_watermarked(path);
+
+    if let Ok(data) = &result {
+        *rendered = Some(data.clone());
+        let mut cache = WATERMARKED_CACHE.lock().unwrap_or_else(|e| e.into_inner());
+        cache.insert(rev.to_owned(), data.clone());
+    }
+
+    // Anyone still waiting on the slot has their own reference to it
+    let mut in_flight = WATERMARKING.lock().unwrap_or_else(|e| e.into_inner());
+    if in_flight.get(rev).is_some_and(|s| Arc::ptr_eq(s, &slot)) {
+        in_flight.remove(rev);
+    }
+
+    result
+}
+
+/// Reads and watermarks the full image, producing a JPEG
+fn render_watermarked(path: &Path) -> Result<Arc<[u8]>, http::Status> {
+    let watermark = WATERMARK.load();
+    let watermark = (**watermark).as_ref().map_err(|e| {
+        error!("{}", e);
+        http::Status::InternalServerError
+    })?;
+
+    let max_pixelcount = CONFIG.load().max_source_pixelcount;
+    let data = fs::read(path)
+        .with_context(|| format!("failed to read file {:?}", path))
+        .and_then(|data| decode_full_img(&data, max_pixelcount))
+        .and_then(|img| {
+            watermark
+                .apply(img)
+                .with_context(|| format!("failed to watermark image {:?}", path))
+        })
+        .map_err(|e| {
+            error!("{:#}", e);
+            http::Status::InternalServerError
+        })?;
+
+    Ok(data.into())
+}
+
+/// Key for a single zoom tile: (full image hash, level, x, y)
+///
+/// Using the hash instead of the image name means that tiles from an old version of an image are
+/// never served.
+type TileKey = (String, u32, u32, u32);
+
+/// Bounded cache of rendered images (like zoom tiles), evicting the oldest first
+struct BoundedCache<K> {
+    max_len: usize,
+    items: HashMap<K, Arc<[u8]>>,
+    /// Keys in the order they were inserted
+    order: VecDeque<K>,
+}
+
+impl<K: Clone + Eq + std::hash::Hash> BoundedCache<K> {
+    fn new(max_len: usize) -> Self {
+        BoundedCache {
+            max_len,
+            items: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn get(&self, key: &K) -> Option<Arc<[u8]>> {
+        self.items.get(key).cloned()
+    }
+
+    fn insert(&mut self, key: K, data: Arc<[u8]>) {
+        if self.items.insert(key.clone(), data).is_some() {
+            // Already present (e.g. rendered concurrently); the order doesn't need to change
+            return;
+        }
+
+        self.order.push_back(key);
+        while self.order.len() > self.max_len {
+            if let Some(old) = self.order.pop_front() {
+                self.items.remove(&old);
             }
+        }
+    }
+
+    /// Removes everything with a key that doesn't satisfy `keep`
+    fn retain(&mut self, mut keep: impl FnMut(&K) -> bool) {
+        self.order.retain(|k| keep(k));
+        let order = &self.order;
+        self.items.retain(|k, _| order.contains(k));
+    }
+}
+
+/// Parses a "MM-DD" string into the month and day, returning `None` if it isn't a valid date in
+/// some year
+fn parse_month_day(s: &str) -> Option<(u32, u32)> {
+    let (month, day) = s.split_once('-')?;
+    let (month, day) = (month.parse().ok()?, day.parse().ok()?);
+
+    // 2000 was a leap year, so February 29th is allowed
+    NaiveDate::from_ymd_opt(2000, month, day)?;
+    Some((month, day))
+}
+
+/// Returns the path of the album that holds every photo, for linking photos in their context
+pub fn all_album_path() -> String {
+    CONFIG.load().all_album_path.clone()
+}
+
+/// Returns up to `limit` photos from the preview album, for the site root
+pub fn recent_photos_context(limit: usize) -> Vec<Arc<PhotoInfo>> {
+    STATE
+        .load()
+        .featured_photos(&CONFIG.load().preview_album, limit)
+        .photos
+}
+
+/// Re-processes a single photo from its file on disk, replacing it in the current state
+///
+/// This is much cheaper than a full update, so it's useful for iterating on a photo's metadata --
+/// like its description or alt text. Changes that would move the photo around (i.e. to the time it
+/// was taken, its camera or lens, or its albums) require a full update instead, and produce an
+/// error here.
+///
+/// Returns `Ok(None)` if there's no photo with the name.
+pub fn reprocess(name: &str) -> Result<Option<PhotoMeta>> {
+    let config = CONFIG.load();
+    let old = match STATE.load().images.get(name) {
+        Some(img) => img.clone(),
+        None => return Ok(None),
+    };
+
+    // The albums might have changed since the last update, so we get their current versions for
+    // any settings that apply to this photo
+    let (parsed, _, _) =
+        PhotosState::get_albums_info(&config).context("failed to read albums info file")?;
+    let all_albums: HashMap<_, _> = parsed.into_iter().collect();
+    let albums = all_albums
+        .iter()
+        .filter(|(_, a)| a.photos.iter().any(|p| p == name))
+        .map(|(path, a)| AlbumReference {
+            path: path.clone(),
+            name: a.name.clone(),
+        })
+        .collect();
+
+    let new = PhotosState::process_photo(
+        &config,
+        &old.path,
+        name,
+        old.media_kind,
+        albums,
+        &all_albums,
+        &Mutex::new(HashMap::new()),
+        &Mutex::new(HashMap::new()),
+        &GearNames::load(&config.imgs_dir)?,
+    )
+    .with_context(|| format!("failed to process photo {:?}", name))?;
+
+    let gear = |img: &PhotoInfo| {
+        let c = img.exif_info.camera.as_ref()?;
+        Some((c.camera_slug.clone(), c.lens_slug.clone()))
+    };
+    if new.exif_info.actual_datetime != old.exif_info.actual_datetime
+        || gear(&new) != gear(&old)
+        || new.album_paths(&config) != old.album_paths(&config)
+    {
+        bail!(
+            "photo {:?} changed its time, gear, or albums; that requires a full update",
+            name
+        );
+    }
+
+    let new = Arc::new(new);
+    STATE.rcu(|state| Arc::new(state.with_replaced(&new)));
+    info!("reprocessed photo {:?}", name);
+
+    Ok(Some(PhotoMeta {
+        datetime: new.exif_info.actual_datetime.to_rfc3339(),
+        exif_info: new.exif_info.clone(),
+    }))
+}
+
+/// Returns the number of photos currently loaded
+pub fn num_photos() -> usize {
+    STATE.load().images.len()
+}
+
+/// Returns the number of albums currently listed, including the automatically generated ones
+///
+/// Hidden albums aren't listed (outside of debug builds), so they aren't counted.
+pub fn num_albums() -> usize {
+    let state = STATE.load();
+    let in_order = &state.albums_in_order;
+    in_order.normal_albums.len() + in_order.days.len() + in_order.locations.len()
+}
+
+/// Returns the path of every album currently loaded with the number of photos in it, sorted by
+/// path
+pub fn album_photo_counts() -> Vec<(String, usize)> {
+    let state = STATE.load();
+    let mut counts: Vec<_> = (state.albums.iter())
+        .map(|(path, a)| (path.clone(), a.photo_count))
+        .collect();
+    counts.sort_unstable();
+    counts
+}
+
+/// Returns when the photos were last loaded from disk
+pub fn generated_at() -> DateTime<Utc> {
+    STATE.load().generated_at
+}
+
+/// Returns whether there's a photo with the name
+pub fn photo_exists(name: &str) -> bool {
+    STATE.load().images.contains_key(name)
+}
+
+/// Returns whether there's an album at the path
+pub fn album_exists(path: &str) -> bool {
+    STATE.load().albums.contains_key(path)
+}
+
+/// Returns the rendered HTML of every album and photo description, each labelled with where it's
+/// from, for `crate::links`
+pub fn rendered_html() -> Vec<(String, String)> {
+    let state = STATE.load();
+
+    let albums = state.albums.values().map(|a| {
+        let html = format!(
+            "{}\n{}",
+            a.description,
+            a.notes.as_deref().unwrap_or_default()
+        );
+        (format!("album {:?}", a.path), html)
+    });
+    let photos = state.images.values().filter_map(|img| {
+        let desc = img.exif_info.description.clone()?;
+        Some((format!("photo {:?}", img.file_name), desc))
+    });
+
+    albums.chain(photos).collect()
+}
+
+/// Returns information about the photo referenced by the path, if it's for the `img` route --
+/// i.e. "/photos/img-file/<name>?..."
+///
+/// This is used to improve how photos are displayed when they're included in blog posts. The
+/// URLs provided always point to the current revision of the image, so they can be cached
+/// forever.
+///
+/// If the photos haven't been loaded yet, this always returns `None`, instead of loading them.
+pub fn markdown_image(path: &str) -> Option<LocalImage> {
+    let name = path
+        .strip_prefix("/photos/img-file/")?
+        .split(|c| c == '?' || c == '#')
+        .next()?;
+
+    if !is_loaded() {
+        return None;
+    }
+
+    STATE.load().images.get(name).map(|img| local_image(img))
+}
+
+/// Returns the information for displaying a photo as the cover of a blog post, or `None` if there's
+/// no photo with the name
+///
+/// Like `markdown_image`, the URLs point to the current revision of the image, and this returns
+/// `None` if the photos haven't been loaded yet.
+pub fn cover_image(name: &str) -> Option<CoverImage> {
+    if !is_loaded() {
+        return None;
+    }
+
+    let state = STATE.load();
+    let img = state.images.get(name)?;
+
+    Some(CoverImage {
+        name: img.file_name.clone(),
+        alt: img.alt.clone(),
+        img: local_image(img),
+    })
+}
+
+fn local_image(img: &PhotoInfo) -> LocalImage {
+    let name = Cow::Borrowed(img.file_name.as_str());
+    let small_hash = &img.smaller_webp.hash;
+    let full_rev = &img.full_img_rev;
+
+    // The smaller image has the same aspect ratio as the full one, which is all the browser
+    // really needs to reserve space for it
+    LocalImage {
+        width: img.smaller_webp.width,
+        height: img.smaller_webp.height,
+        webp_url: uri!("/photos", img: name.clone(), "small", small_hash).to_string(),
+        jpeg_url: uri!("/photos", img: name, "full", full_rev).to_string(),
+    }
+}
+
+// We include hashes in the image URLs so that they can be cached forever -- any updates to the
+// image will change the hash, so it'll be a different URL.
+//
+// Per MDN:
+//
+//     A modern best practice for static resources is to include version/hashes in their URLs,
+//     while never modifying the resources — but instead, when necessary, updating the resources
+//     with newer versions that have new version-numbers/hashes, so that their URLs are different.
+//     That’s called the cache-busting pattern.
+//
+// https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Cache-Control#immutable
+#[get("/img-file/<name>?<size>&<rev>")]
+pub fn img(
+    name: Cow<str>,
+    size: Option<String>,
+    rev: Option<String>,
+    if_none_match: IfNoneMatch,
+) -> Result<MaybeRedirect<ImageSource>, http::Status> {
+    let size = size.unwrap_or_default();
+
+    // The 'size' must be one of `small`, `cover`, or `full`
+    let is_full = match size.as_str() {
+        "full" => true,
+        "small" | "cover" => false,
+        _ => return Err(http::Status::BadRequest),
+    };
+
+    // Store a state guard separately to get around borrowing issues
+    let state = STATE.load();
+
+    let img = state
+        .images
+        .get(name.as_ref())
+        .ok_or(http::Status::NotFound)?;
+
+    // The full version of a clip is served by `media` instead
+    if is_full && img.media_kind != MediaKind::Photo {
+        return Err(http::Status::NotFound);
+    }
+
+    // Album cover crops are stored with the albums. Every album with the same cover has the same
+    // crop, so any of them will do.
+    let cover_crop = match size.as_str() {
+        "cover" => {
+            let crop = state
+                .albums
+                .values()
+                .filter(|a| a.cover_img.file_name == img.file_name)
+                .find_map(|a| a.cover_crop.as_ref());
+            Some(crop.ok_or(http::Status::NotFound)?)
+        }
+        _ => None,
+    };
+
+    let target_hash = match (is_full, cover_crop) {
+        (true, _) => &img.full_img_rev,
+        (false, Some(crop)) => &crop.hash,
+        (false, None) => &img.smaller_webp.hash,
+    };
+
+    let rev_is_some = rev.is_some();
+    if *target_hash != rev.unwrap_or_default() {
+        return Ok(MaybeRedirect::Redirect {
+            new_url: uri!("/photos", img: name, size, target_hash),
+            // Only permanently redirect previous revisions. Perma-links to the image might
+            // eventually change
+            is_permanent: rev_is_some,
+        });
+    }
+
+    if if_none_match.matches(target_hash) {
+        return Ok(MaybeRedirect::Dont(ImageSource::NotModified(
+            target_hash.clone(),
+        )));
+    }
+
+    if !is_full {
+        let smaller = cover_crop.unwrap_or(&img.smaller_webp);
+        Ok(MaybeRedirect::Dont(ImageSource::InMem(smaller.clone())))
+    } else if img.watermarked {
+        watermarked_img(&img.path, &img.full_img_rev)
+            .map(|data| WatermarkedImg {
+                data,
+                rev: img.full_img_rev.clone(),
+            })
+            .map(ImageSource::Watermarked)
+            .map(MaybeRedirect::Dont)
+    } else {
+        NamedFile::open(&img.path)
+            // We already had an entry for this file; if we couldn't find it, then that's an error on
+            // our part.
+            .map_err(|_| http::Status::InternalServerError)
+            .map(|file| StoredImage {
+                file,
+                hash: img.full_img_hash.clone(),
+            })
+            .map(ImageSource::File)
+            .map(MaybeRedirect::Dont)
+    }
+}
+
+/// Serves the original file for a video clip or animation, with the same cache-busting as `img`
+#[get("/media/<name>?<rev>")]
+pub fn media(
+    name: Cow<str>,
+    rev: Option<String>,
+    if_none_match: IfNoneMatch,
+) -> Result<MaybeRedirect<StoredClip>, http::Status> {
+    let (kind, hash, path) = with_state(|s| {
+        s.images
+            .get(name.as_ref())
+            .filter(|i| i.media_kind != MediaKind::Photo)
+            .map(|i| (i.media_kind, i.full_img_hash.clone(), i.path.clone()))
+    })
+    .ok_or(http::Status::NotFound)?;
+
+    let rev_is_some = rev.is_some();
+    if hash != rev.unwrap_or_default() {
+        return Ok(MaybeRedirect::Redirect {
+            new_url: uri!("/photos", media: name, hash),
+            is_permanent: rev_is_some,
+        });
+    }
+
+    if if_none_match.matches(&hash) {
+        return Ok(MaybeRedirect::Dont(StoredClip {
+            kind,
+            hash,
+            file: None,
+        }));
+    }
+
+    // Browsers make lots of range requests for videos, so we only read the part that's requested
+    let open = || -> io::Result<_> {
+        let file = fs::File::open(&path)?;
+        let len = file.metadata()?.len() as usize;
+        Ok((file, len))
+    };
+    let file = open().map_err(|e| {
+        error!("failed to open clip {:?}: {}", path, e);
+        http::Status::InternalServerError
+    })?;
+
+    Ok(MaybeRedirect::Dont(StoredClip {
+        kind,
+        hash,
+        file: Some(file),
+    }))
+}
+
+impl PhotosState {
+    /// Creates the `PhotosState`
+    fn new(config: &PhotosConfig) -> Result<Self> {
+        // Step 1
+        //
+        // Parse the information about the albums
+        let (all_albums, all_album_paths, all_photos_album, collections) = {
+            let (parsed, all_photos_album, collections) =
+                Self::get_albums_info(config).context("failed to read albums info file")?;
 
-            // Ensure that all of the album cover images are accounted for by putting them in
-            // `album_membership`:
-            album_membership.entry(info.cover_img.clone()).or_default();
-        }
+            let names = parsed
+                .iter()
+                .map(|(name, _)| name.clone())
+                .collect::<Vec<_>>();
+            let all = parsed.into_iter().collect::<HashMap<_, _>>();
 
-        let glob_pat = format!("{}/{}", IMGS_DIRECTORY, IMGS_GLOB);
-        let candidates = glob(&glob_pat)
-            .expect("failed to read glob pattern")
+            (all, names, all_photos_album, collections)
+        };
+
+        // Step 2
+        //
+        // Find all of the images on disk. This is cheap, so we can check that everything lines up
+        // with the albums *before* doing the expensive stuff.
+        let mut problems = Vec::new();
+        let mut seen_names = HashSet::new();
+        let candidates = IMGS_GLOBS
+            .iter()
+            .chain(CLIP_GLOBS)
+            .flat_map(|pat| {
+                let glob_pat = format!("{}/{}", config.imgs_dir.display(), pat);
+                glob(&glob_pat).expect("failed to read glob pattern")
+            })
             .map(|glob_result| {
                 let path = glob_result.context("failed to get glob item for images")?;
+                let kind = MediaKind::from_path(&path)
+                    .expect("expected glob result to have known extension");
 
                 let file_name: PathBuf = path
                     .file_prefix()
@@ -488,71 +1924,145 @@ impl PhotosState {
                     .into();
 
                 let file_string: String = file_name.to_string_lossy().into();
-
-                if !is_uri_idempotent(&file_string) {
-                    bail!(
+                Ok((path, file_string, kind))
+            })
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .filter(|(path, file_string, _)| {
+                if !is_uri_idempotent(file_string) {
+                    problems.push(format!(
                         "bad image file name {:?}: must URI encode to the same value",
                         path.file_name().unwrap()
-                    );
+                    ));
+                    return false;
                 }
 
-                // Fetch the albums for this image, if it has any. Doing this now means that we
-                // can check -- *before* doing the expensive stuff -- that every image is accounted
-                // for.
+                // Photos and clips share the same namespace, so e.g. "foo.jpg" and "foo.mp4" can't
+                // both exist
+                if !seen_names.insert(file_string.clone()) {
+                    problems.push(format!(
+                        "multiple files with the same image name {:?}",
+                        file_string
+                    ));
+                    return false;
+                }
 
-                let albums = album_membership.remove(&file_string).unwrap_or_default();
-                Ok((path, file_string, albums))
+                true
             })
-            .collect::<Result<Vec<_>, _>>()?;
+            .collect::<Vec<_>>();
 
-        // Each image should have claimed its albums. So if there's anything left in
-        // `album_membership`, there's images referenced that aren't on disk.
-        if !album_membership.is_empty() {
-            let referenced_set: Vec<_> = album_membership.keys().collect();
+        // Step 3
+        //
+        // Check that every image referenced by the albums is on disk. Everything is reported
+        // together, so that a new album with a bunch of typos only needs to be fixed once.
+        problems.extend(Self::album_problems(
+            config,
+            &all_albums,
+            &all_album_paths,
+            &all_photos_album,
+            &collections,
+            &seen_names,
+        ));
+
+        if !problems.is_empty() {
             bail!(
-                "some image(s) referenced in albums but aren't on disk: {:?}",
-                referenced_set
+                "found {} problem(s) with the images and albums:\n  - {}",
+                problems.len(),
+                problems.join("\n  - ")
             );
         }
 
+        // The pages that show these fall back to the most recent photos, so it isn't worth failing
+        // over -- but it's probably not intended.
+        for (kind, path) in [
+            ("favorites", &config.favorites_album),
+            ("preview", &config.preview_album),
+        ] {
+            if !all_albums.contains_key(path) && *path != config.all_album_path {
+                warn!(
+                    "configured {} album {:?} doesn't exist; using the most recent photos instead",
+                    kind, path
+                );
+            }
+        }
+
+        // Photo file name -> unsorted list of album memberships
+        let mut album_membership = <HashMap<String, Vec<AlbumReference>>>::new();
+        for (path, info) in all_albums.iter() {
+            for p in &info.photos {
+                let album_ref = AlbumReference {
+                    path: path.clone(),
+                    name: info.name.clone(),
+                };
+
+                album_membership
+                    .entry(p.clone())
+                    .or_default()
+                    .push(album_ref);
+            }
+        }
+
+        let candidates = candidates
+            .into_iter()
+            .map(|(path, file_string, kind)| {
+                let albums = album_membership.remove(&file_string).unwrap_or_default();
+                (path, file_string, albums, kind)
+            })
+            .collect::<Vec<_>>();
+
+        // Photos that aren't in any of the albums from the albums info file will only end up in
+        // their auto-generated date album. That's fine, but it's useful to know which ones need
+        // filing.
+        let mut orphans: Vec<_> = candidates
+            .iter()
+            .filter(|(_, _, albums, _)| albums.is_empty())
+            .map(|(_, name, _, _)| name.clone())
+            .collect();
+        orphans.sort_unstable();
+
+        let gear_names = GearNames::load(&config.imgs_dir)?;
+
         let auto_date_albums = Mutex::new(HashMap::new());
+        let smaller_imgs = Mutex::new(HashMap::new());
 
         let total_imgs = candidates.len();
 
+        let progress_mode = ProgressMode::from_env();
         let (tx, rx) = mpsc::channel::<()>();
         let status = thread::spawn(move || {
-            let mut seen = 0;
-            let mut stdout = io::stdout();
-
-            loop {
-                print!("\rProcessing images... {}/{} done", seen, total_imgs);
-                let _ = stdout.flush();
-
-                if let Err(_) = rx.recv() {
-                    println!("");
-                    break;
-                }
-
-                seen += 1;
-            }
+            info!("processing {} images...", total_imgs);
+            let seen = progress::report(progress_mode, "processing images", total_imgs, rx);
+            info!("finished processing {}/{} images", seen, total_imgs);
         });
 
         let images_list_result = candidates
             .into_par_iter()
-            .map_with(tx, |tx, (path, file_string, albums)| {
+            .map_with(tx, |tx, (path, file_string, albums, kind)| {
                 let info_result = Self::process_photo(
+                    config,
                     &path,
                     &file_string,
+                    kind,
                     albums,
                     &all_albums,
                     &auto_date_albums,
+                    &smaller_imgs,
+                    &gear_names,
                 )
                 .with_context(|| format!("failed to process photo {:?}", file_string));
 
                 // Send a signal to indicate that we've finished processing this image
                 let _ = tx.send(());
 
-                Ok((file_string, Arc::new(info_result?)))
+                match info_result {
+                    Ok(info) => Ok(Some((file_string, Arc::new(info)))),
+                    // One enormous photo shouldn't take down everything else
+                    Err(e) if e.chain().any(|c| c.is::<SourceTooLarge>()) => {
+                        warn!("skipping photo: {:#}", e);
+                        Ok(None)
+                    }
+                    Err(e) => Err(e),
+                }
             })
             .collect::<Result<Vec<_>>>();
 
@@ -560,56 +2070,115 @@ impl PhotosState {
         let _ = status.join(); // shoudn't produce an error, it won't panic
 
         // And produce the mapping of image names to their infos
-        let images: HashMap<_, _> = images_list_result?.into_iter().collect();
+        let images: HashMap<_, _> = images_list_result?.into_iter().flatten().collect();
+
+        // Anything that was skipped can't be in any of the albums. The auto-generated date albums
+        // only exist for the photos in them, so they might disappear entirely.
+        orphans.retain(|name| images.contains_key(name));
+        let mut auto_date_albums = auto_date_albums.into_inner().unwrap();
+        auto_date_albums.retain(|_, auto| {
+            auto.photos.retain(|(_, p)| images.contains_key(p));
+            !auto.photos.is_empty()
+        });
+
+        // Identical files will have shared their smaller images, but it's still probably a
+        // mistake to have them.
+        let mut names_by_hash: HashMap<&str, Vec<&str>> = HashMap::new();
+        for (name, info) in &images {
+            names_by_hash
+                .entry(&info.full_img_hash)
+                .or_default()
+                .push(name);
+        }
+        for names in names_by_hash.values_mut().filter(|ns| ns.len() > 1) {
+            names.sort_unstable();
+            warn!("found identical images: {:?}", names);
+        }
 
         // Earlier, we checked that everything present in `albums` *was* a key in
         // `album_membership`; we can now go through the albums & all of their referenced image
         // names will be present in `images`.
 
+        // Photos that can't be downloaded from their albums shouldn't be downloadable from the
+        // albums for their dates either
+        let download_disabled: HashSet<String> = (all_albums.values())
+            .filter(|a| a.disable_download)
+            .flat_map(|a| a.photos.iter().cloned())
+            .collect();
+
         let mut albums = all_albums
             .into_iter()
             .map(|(path, parsed)| {
-                let mut a = Album {
+                let mut photos: Vec<_> = parsed
+                    .photos
+                    .iter()
+                    .filter_map(|p| images.get(p).cloned())
+                    .collect();
+
+                if parsed.display == AlbumDisplayOrder::FromLast {
+                    photos.reverse();
+                }
+
+                // Albums without an explicit cover were checked above to have at least one photo,
+                // but it (or the cover) might have been skipped
+                let cover_img = match parsed.cover_img.as_ref().and_then(|img| images.get(img)) {
+                    Some(img) => img.clone(),
+                    None => match photos.first() {
+                        Some(img) => img.clone(),
+                        None => bail!("album {:?} has no photos left after skipping some", path),
+                    },
+                };
+
+                let downloadable = Album::is_downloadable(parsed.disable_download, &photos);
+
+                let a = Album {
                     name: parsed.name,
                     path: path.clone(),
-                    cover_img: images[&parsed.cover_img].clone(),
-                    description: markdown_to_html(&parsed.description),
-                    photos: parsed
-                        .photos
-                        .into_iter()
-                        .map(|p| images[&p].clone())
-                        .collect(),
+                    cover_img,
+                    description: markdown_to_html(&parsed.description, MarkdownKind::Snippet),
+                    notes: parsed
+                        .notes
+                        .map(|n| markdown_to_html(&n, MarkdownKind::Document)),
+                    photo_count: photos.len(),
+                    date_range: DateRange::of_photos(&photos),
+                    photos,
                     kind: parsed.kind.map(|k| k.into()),
+                    hidden: parsed.hidden,
+                    flex_grid: parsed.flex_grid,
+                    cover_crop: None,
+                    downloadable,
                 };
 
-                if parsed.display == AlbumDisplayOrder::FromLast {
-                    a.photos.reverse();
-                }
-
-                (path, Arc::new(a))
+                Ok((path, Arc::new(a)))
             })
-            .chain(
-                auto_date_albums
-                    .into_inner()
-                    .unwrap()
-                    .into_iter()
-                    .map(|(_, auto)| {
-                        let photos: Vec<_> =
-                            auto.photos.iter().map(|(_, p)| images[p].clone()).collect();
-                        let a = Arc::new(Album {
-                            path: auto.path.clone(),
-                            name: auto.name,
-                            description: markdown_to_html(&auto.description),
-                            cover_img: photos[0].clone(),
-                            photos,
-                            kind: Some(AlbumKind::Day),
-                        });
-                        (auto.path, a)
-                    }),
-            )
-            .collect::<HashMap<String, Arc<Album>>>();
-
-        // Finally, add in the album for all of the images
+            .chain(auto_date_albums.into_iter().map(|(_, auto)| {
+                let photos: Vec<_> = auto.photos.iter().map(|(_, p)| images[p].clone()).collect();
+                let disable_download = auto
+                    .photos
+                    .iter()
+                    .any(|(_, p)| download_disabled.contains(p));
+                let downloadable = Album::is_downloadable(disable_download, &photos);
+                let a = Arc::new(Album {
+                    path: auto.path.clone(),
+                    name: auto.name,
+                    description: markdown_to_html(&auto.description, MarkdownKind::Snippet),
+                    notes: None,
+                    cover_img: photos[0].clone(),
+                    photo_count: photos.len(),
+                    date_range: DateRange::of_photos(&photos),
+                    photos,
+                    kind: Some(AlbumKind::Day),
+                    hidden: false,
+                    flex_grid: FlexGridOverrides::default(),
+                    cover_crop: None,
+                    downloadable,
+                });
+                Ok((auto.path, a))
+            }))
+            .collect::<Result<HashMap<String, Arc<Album>>>>()?;
+
+        // Finally, add in the album for all of the images. It's the entire library, so it's far
+        // too big to download all at once.
         let images_sorted = {
             let mut imgs: Vec<_> = images.values().cloned().collect();
             // Sort so that later images come first
@@ -622,26 +2191,65 @@ impl PhotosState {
             imgs
         };
 
-        let midpoint_img = images_sorted[images_sorted.len() / 2].clone();
+        let all_photos_cover = match all_photos_album.cover_img.and_then(|img| images.get(&img)) {
+            Some(img) => img.clone(),
+            None => images_sorted[images_sorted.len() / 2].clone(),
+        };
         albums.insert(
-            ALL_ALBUM_PATH.into(),
+            config.all_album_path.clone(),
             Arc::new(Album {
-                path: ALL_ALBUM_PATH.into(),
-                name: ALL_ALBUM_NAME.to_owned(),
-                cover_img: midpoint_img,
-                description: ALL_ALBUM_DESC.to_owned(),
+                path: config.all_album_path.clone(),
+                name: config.all_album_name.clone(),
+                cover_img: all_photos_cover,
+                description: config.all_album_description.clone(),
+                notes: None,
                 kind: Some(AlbumKind::All),
+                photo_count: images_sorted.len(),
+                date_range: DateRange::of_photos(&images_sorted),
                 photos: images_sorted,
+                hidden: false,
+                flex_grid: FlexGridOverrides::default(),
+                cover_crop: None,
+                downloadable: false,
             }),
         );
 
+        // Nothing else refers to the albums yet, so `make_mut` won't need to clone them
+        if config.crop_album_covers {
+            albums.par_iter_mut().for_each(|(path, a)| {
+                let crop = Self::cover_crop_or_warn(path, &a.cover_img, config.small_img_quality);
+                Arc::make_mut(a).cover_crop = crop;
+            });
+        }
+
         let mut images_by_time = images.values().cloned().collect::<Vec<_>>();
         images_by_time.sort_by_key(|img| img.exif_info.actual_datetime);
 
+        // A single photo doesn't say much about where the rest of them will be, so we only fit the
+        // global map to the photos once there's more than one with a location
+        let num_located = images_by_time
+            .iter()
+            .filter(|img| img.exif_info.coords.is_some())
+            .count();
+        let global_map_view = match num_located {
+            0 | 1 => None,
+            _ => MapView::fitting(&images_by_time),
+        };
+        let global_map_view = global_map_view.unwrap_or(MapView {
+            centered_at: GPSCoords {
+                lat: config.fallback_map_center[0],
+                lon: config.fallback_map_center[1],
+            },
+            zoom_level: config.fallback_map_zoom,
+        });
+
         let mut albums_in_order = AlbumsInOrder::default();
 
         for a_path in all_album_paths {
             let a = albums[&a_path].clone();
+            if a.hidden && !cfg!(debug_assertions) {
+                continue;
+            }
 
             let list = match a.kind {
                 None | Some(AlbumKind::All) => &mut albums_in_order.normal_albums,
@@ -652,43 +2260,320 @@ impl PhotosState {
             list.push(a);
         }
 
+        let mut grouped = HashSet::new();
+        for c in collections {
+            let mut collection_albums = Vec::new();
+            for path in &c.albums {
+                let a = albums[path].clone();
+                grouped.insert(path.clone());
+                if a.hidden && !cfg!(debug_assertions) {
+                    continue;
+                }
+
+                collection_albums.push(a);
+            }
+
+            albums_in_order.collections.push(Collection {
+                name: c.name,
+                description: markdown_to_html(&c.description, MarkdownKind::Snippet),
+                albums: collection_albums,
+            });
+        }
+
+        albums_in_order.ungrouped = (albums_in_order.normal_albums.iter())
+            .filter(|a| !grouped.contains(&a.path))
+            .cloned()
+            .collect();
+
+        let mut by_camera = HashMap::<_, GearPhotos>::new();
+        let mut by_lens = HashMap::<_, GearPhotos>::new();
+        for img in images_by_time.iter().rev() {
+            let camera = match &img.exif_info.camera {
+                Some(c) => c,
+                None => continue,
+            };
+
+            let (make, model) = &camera.id;
+            by_camera
+                .entry(camera.camera_slug.clone())
+                .or_insert_with(|| GearPhotos {
+                    name: format!("{} {}", make, model),
+                    photos: Vec::new(),
+                })
+                .photos
+                .push(img.clone());
+
+            if let (Some((make, model)), Some(slug)) = (&camera.lens_id, &camera.lens_slug) {
+                by_lens
+                    .entry(slug.clone())
+                    .or_insert_with(|| GearPhotos {
+                        name: format!("{} {}", make, model),
+                        photos: Vec::new(),
+                    })
+                    .photos
+                    .push(img.clone());
+            }
+        }
+
         Ok(PhotosState {
             albums,
             albums_in_order,
             images,
             images_by_time,
+            global_map_view,
+            orphans,
+            by_camera,
+            by_lens,
+            generated_at: Utc::now(),
         })
     }
 
     /// Updates the `PhotosState`, checking returning `None` if there haven't been any changes
     fn update_from_fs(&self) -> Result<Option<Self>> {
         // TODO: Currently this is very expensive and will take a long while. We can do better.
-        PhotosState::new().map(Some)
+        PhotosState::new(&CONFIG.load()).map(Some)
+    }
+
+    /// Returns a copy of the state with the photo of the same name replaced by `new`
+    ///
+    /// Only the references to the photo are replaced; anything derived from its metadata (like the
+    /// order of the photos, or which albums it's in) is left as-is. See `reprocess`.
+    fn with_replaced(&self, new: &Arc<PhotoInfo>) -> PhotosState {
+        let swap = |p: &Arc<PhotoInfo>| match p.file_name == new.file_name {
+            true => new.clone(),
+            false => p.clone(),
+        };
+        let swap_all = |photos: &[Arc<PhotoInfo>]| photos.iter().map(swap).collect::<Vec<_>>();
+
+        let albums: HashMap<_, _> = self
+            .albums
+            .iter()
+            .map(|(path, a)| {
+                let has_photo = a.cover_img.file_name == new.file_name
+                    || a.photos.iter().any(|p| p.file_name == new.file_name);
+                if !has_photo {
+                    return (path.clone(), a.clone());
+                }
+
+                // The photo's small image might have changed, so its crop has to be re-made too
+                let cover_crop = match &a.cover_crop {
+                    Some(_) if a.cover_img.file_name == new.file_name => {
+                        let quality = CONFIG.load().small_img_quality;
+                        Self::cover_crop_or_warn(path, new, quality)
+                    }
+                    c => c.clone(),
+                };
+
+                let album = Album {
+                    cover_img: swap(&a.cover_img),
+                    photos: swap_all(&a.photos),
+                    cover_crop,
+                    ..(**a).clone()
+                };
+                (path.clone(), Arc::new(album))
+            })
+            .collect();
+
+        // The ordered albums need to refer to the new versions
+        let reorder = |list: &[Arc<Album>]| {
+            list.iter()
+                .map(|a| albums[&a.path].clone())
+                .collect::<Vec<_>>()
+        };
+        let albums_in_order = AlbumsInOrder {
+            normal_albums: reorder(&self.albums_in_order.normal_albums),
+            days: reorder(&self.albums_in_order.days),
+            locations: reorder(&self.albums_in_order.locations),
+            collections: (self.albums_in_order.collections.iter())
+                .map(|c| Collection {
+                    albums: reorder(&c.albums),
+                    ..c.clone()
+                })
+                .collect(),
+            ungrouped: reorder(&self.albums_in_order.ungrouped),
+        };
+
+        let mut images = self.images.clone();
+        images.insert(new.file_name.clone(), new.clone());
+
+        let swap_gear = |gear: &HashMap<String, GearPhotos>| {
+            gear.iter()
+                .map(|(slug, g)| {
+                    let photos = GearPhotos {
+                        name: g.name.clone(),
+                        photos: swap_all(&g.photos),
+                    };
+                    (slug.clone(), photos)
+                })
+                .collect::<HashMap<_, _>>()
+        };
+
+        PhotosState {
+            albums,
+            albums_in_order,
+            images,
+            images_by_time: swap_all(&self.images_by_time),
+            orphans: self.orphans.clone(),
+            global_map_view: self.global_map_view,
+            by_camera: swap_gear(&self.by_camera),
+            by_lens: swap_gear(&self.by_lens),
+            generated_at: Utc::now(),
+        }
     }
 
-    /// Reads and parses the album info file
-    fn get_albums_info() -> Result<AlbumsInformation> {
-        let path = Path::new(IMGS_DIRECTORY).join(Path::new(ALBUMS_META_FILENAME));
-        let content = fs::read_to_string(path)?;
+    /// Reads and merges the album information from `ALBUMS_META_FILENAME` and every file matching
+    /// `ALBUMS_META_GLOB`, either of which may be absent
+    ///
+    /// The albums are ordered by file (the main file first, then the rest by name), then by their
+    /// order within each file. An album path appearing more than once is an error -- including the
+    /// reserved paths for the "all photos" album and the collections.
+    fn get_albums_info(
+        config: &PhotosConfig,
+    ) -> Result<(
+        AlbumsInformation,
+        ParsedAllPhotosAlbum,
+        Vec<ParsedCollection>,
+    )> {
+        let mut paths = Vec::new();
+
+        let imgs_dir = &config.imgs_dir;
+        let main_path = imgs_dir.join(Path::new(ALBUMS_META_FILENAME));
+        if main_path.exists() {
+            paths.push(main_path);
+        }
+
+        let glob_pat = format!("{}/{}", imgs_dir.display(), ALBUMS_META_GLOB);
+        let mut extra_paths = glob(&glob_pat)
+            .expect("failed to read glob pattern")
+            .collect::<Result<Vec<_>, _>>()
+            .context("failed to get glob item for albums info files")?;
+        extra_paths.sort();
+        paths.extend(extra_paths);
+
+        if paths.is_empty() {
+            bail!(
+                "no albums info files; expected {:?} or files matching {:?}",
+                ALBUMS_META_FILENAME,
+                ALBUMS_META_GLOB
+            );
+        }
+
+        let mut albums = Vec::new();
+        let mut all_photos_album = None;
+        let mut collections = Vec::new();
+        // Album path -> the file it came from, to detect duplicates
+        let mut seen: HashMap<String, PathBuf> = HashMap::new();
+
+        for file_path in paths {
+            let content = fs::read_to_string(&file_path)
+                .with_context(|| format!("failed to read file {:?}", file_path))?;
+            let entries = match serde_json::from_str::<AlbumsInfoFile>(&content)
+                .with_context(|| format!("failed to parse file {:?}", file_path))?
+            {
+                AlbumsInfoFile::List(list) => list,
+                AlbumsInfoFile::Map(map) => map.into_iter().collect(),
+            };
+
+            for (path, value) in entries {
+                if let Some(previous) = seen.get(&path) {
+                    bail!(
+                        "duplicate album {:?} in {:?}; already given in {:?}",
+                        path,
+                        file_path,
+                        previous
+                    );
+                }
+                seen.insert(path.clone(), file_path.clone());
+
+                // The entry for the "all photos" album has a different format from the rest, so we
+                // have to parse each entry separately
+                if path == config.all_album_path {
+                    let parsed = serde_json::from_value(value).with_context(|| {
+                        format!(
+                            "failed to parse reserved album {:?} in {:?}",
+                            path, file_path
+                        )
+                    })?;
+                    all_photos_album = Some(parsed);
+                } else if path == COLLECTIONS_PATH {
+                    collections = serde_json::from_value(value).with_context(|| {
+                        format!("failed to parse collections in {:?}", file_path)
+                    })?;
+                } else {
+                    let album = serde_json::from_value(value).with_context(|| {
+                        format!("failed to parse album {:?} in {:?}", path, file_path)
+                    })?;
+                    albums.push((path, album));
+                }
+            }
+        }
 
-        Ok(serde_json::from_str(&content)?)
+        Ok((albums, all_photos_album.unwrap_or_default(), collections))
     }
 
     fn process_photo(
+        config: &PhotosConfig,
         file_path: &Path,
         file_string: &str,
+        kind: MediaKind,
         mut albums: Vec<AlbumReference>,
         all_albums: &HashMap<String, ParsedAlbum>,
         auto_date_albums: &Mutex<HashMap<Date<FixedOffset>, AutoDateAlbumBuilder>>,
+        smaller_imgs: &SmallerImgCache,
+        gear_names: &GearNames,
     ) -> Result<PhotoInfo> {
         let img_data =
             fs::read(&file_path).with_context(|| format!("failed to read file {:?}", file_path))?;
 
-        let exif_info = PhotoExifInfo::from_img_data(&img_data)
-            .with_context(|| format!("failed to get photo metadata for file {:?}", file_path))?;
+        // Clips don't have EXIF data, and we can't decode them ourselves, so they get a poster
+        // frame to use in place of the image itself
+        let (mut exif_info, poster) = match kind {
+            MediaKind::Photo => {
+                let exif_result = PhotoExifInfo::from_img_data(&img_data, gear_names);
+                let is_jpeg = image::guess_format(&img_data).ok() == Some(image::ImageFormat::Jpeg);
+
+                let exif_info = match exif_result {
+                    Ok(info) => info,
+                    // Our JPEGs all come from a camera (or at least an editor), so they should
+                    // have everything. Other formats often can't carry the metadata at all.
+                    Err(e) if !is_jpeg => {
+                        warn!(
+                            "failed to get photo metadata for file {:?}, using defaults: {:#}",
+                            file_path, e
+                        );
+                        PhotoExifInfo::without_metadata(file_path, file_string)?
+                    }
+                    Err(e) => {
+                        return Err(e).with_context(|| {
+                            format!("failed to get photo metadata for file {:?}", file_path)
+                        })
+                    }
+                };
+                (exif_info, None)
+            }
+            MediaKind::Video | MediaKind::Animation => {
+                let exif_info = PhotoExifInfo::from_clip(file_path, file_string)
+                    .with_context(|| format!("failed to get metadata for clip {:?}", file_path))?;
+                let poster = clips::poster_frame(file_path).with_context(|| {
+                    format!("failed to get poster frame for clip {:?}", file_path)
+                })?;
+                (exif_info, Some(poster))
+            }
+        };
+
+        if let Some(timezone) = config.display_timezone {
+            exif_info.display_in_timezone(timezone);
+        }
+
+        if let Some(overrides) = PhotoOverrides::load(&config.imgs_dir, file_string)? {
+            overrides
+                .apply(&mut exif_info)
+                .with_context(|| format!("bad overrides for photo {:?}", file_string))?;
+        }
 
         // Extract the location album from the list, if there is a single one. If there's more
-        // than one, return error:
+        // than one, return error. Hidden location albums still count here, because their settings
+        // apply to the photo -- they're just not linked to (see below).
         let location_album_idx = albums
             .iter()
             .enumerate()
@@ -697,7 +2582,7 @@ impl PhotosState {
             .try_fold(None, Self::fold_extract_single)
             .map_err(|()| anyhow!("found multiple 'location' albums containing this image"))
             .with_context(|| format!("failed to process photo {:?}", file_string))?;
-        let location = location_album_idx.map(|i| albums.remove(i));
+        let mut location = location_album_idx.map(|i| albums.remove(i));
 
         let maybe_day_album = albums
             .iter()
@@ -716,12 +2601,9 @@ impl PhotosState {
                 let mut guard = auto_date_albums.lock().unwrap();
                 match guard.entry(date) {
                     Entry::Vacant(v) => {
+                        // There's no conflict with any of the existing albums, because paths that
+                        // look like dates are rejected by `album_problems`
                         let mut album = AutoDateAlbumBuilder::new(date);
-
-                        if all_albums.contains_key(&album.path) {
-                            bail!("preexisting album path {:?} conflicts with auto-generated date path", &album.path)
-                        }
-
                         album
                             .photos
                             .insert((exif_info.actual_datetime, file_string.to_owned()));
@@ -742,31 +2624,258 @@ impl PhotosState {
         // (and consistently); the order from the hashmap isn't guaranteed anyways.
         albums.sort_by(|rx, ry| rx.name.cmp(&ry.name));
 
+        // Albums can override the settings for the smaller image. The precedence here is
+        // documented in `ParsedAlbum`. Auto-generated day albums aren't in `all_albums`, so they're
+        // naturally skipped.
+        let mut overriding_albums = location
+            .iter()
+            .chain(Some(&day_album))
+            .chain(albums.iter())
+            .filter_map(|r| all_albums.get(&r.path));
+        let small_img_settings = SmallImgSettings {
+            aprox_pixelcount: overriding_albums
+                .clone()
+                .find_map(|a| a.small_img_aprox_pixelcount)
+                .unwrap_or(config.small_img_aprox_pixelcount),
+            quality: overriding_albums
+                .clone()
+                .find_map(|a| a.small_img_quality)
+                .unwrap_or(config.small_img_quality),
+            max_bytes: config.small_img_max_bytes,
+            max_source_pixelcount: config.max_source_pixelcount,
+        };
+        let map_zoom_level = overriding_albums.clone().find_map(|a| a.map_zoom_level);
+        let watermarked = overriding_albums.any(|a| a.watermark);
+
         let mut is_favorite = false;
 
-        let favorite_idx = albums.binary_search_by_key(&FAVORITES_ALBUM_NAME, |a| a.path.as_str());
+        let favorite_idx =
+            albums.binary_search_by_key(&config.favorites_album.as_str(), |a| a.path.as_str());
         if let Ok(i) = favorite_idx {
             is_favorite = true;
             albums.remove(i);
         }
 
-        let hash = Self::hash(&img_data);
+        // Linking to hidden albums from their photos would defeat the point of hiding them
+        if !cfg!(debug_assertions) {
+            albums.retain(|r| !all_albums[&r.path].hidden);
+            location = location.filter(|r| !all_albums[&r.path].hidden);
+        }
+
+        let alt = Self::alt_text(&exif_info, location.as_ref());
+        let hash = content_hash(&img_data);
+
+        let smaller_webp = Self::get_smaller_img(
+            smaller_imgs,
+            &hash,
+            small_img_settings,
+            file_path,
+            poster.as_deref().unwrap_or(&img_data),
+        )
+        .with_context(|| format!("could not create small image for file {:?}", file_path))?;
+
+        let full_img_rev = match watermarked {
+            false => hash.clone(),
+            true => {
+                let watermark = WATERMARK.load();
+                let key = (**watermark).as_ref().map_or("", |w| w.key());
+                content_hash(format!("{}:{}", hash, key).as_bytes())
+            }
+        };
+
+        // Only the header needs to be read for this, so it's cheap compared to decoding
+        let full_size = match kind {
+            MediaKind::Photo => Some(
+                image::io::Reader::new(Cursor::new(&img_data))
+                    .with_guessed_format()
+                    .context("failed to guess image format")?
+                    .into_dimensions()
+                    .with_context(|| {
+                        format!("could not get image size for file {:?}", file_path)
+                    })?,
+            ),
+            MediaKind::Video | MediaKind::Animation => None,
+        };
 
-        let smaller_webp = Self::make_smaller_img(&img_data)
-            .with_context(|| format!("could not create small image for file {:?}", file_path))?;
+        let srcset = Self::img_variants(
+            file_string,
+            &img_data,
+            full_size,
+            &full_img_rev,
+            &smaller_webp,
+        );
 
         Ok(PhotoInfo {
             file_name: file_string.to_owned(),
+            media_kind: kind,
+            path: file_path.to_owned(),
             exif_info,
+            alt,
             is_favorite,
             albums,
             location,
             day_album,
+            map_zoom_level,
+            watermarked,
             smaller_webp,
             full_img_hash: hash,
+            full_img_rev,
+            full_size,
+            srcset,
         })
     }
 
+    /// Returns every size that the photo is available in through the `img` route, from smallest to
+    /// largest
+    ///
+    /// Clips and animations only have their smaller image here; the full versions aren't images.
+    fn img_variants(
+        file_name: &str,
+        img_data: &[u8],
+        full_size: Option<(u32, u32)>,
+        full_rev: &str,
+        smaller: &InMemImg,
+    ) -> Vec<ImgVariant> {
+        let name = Cow::Borrowed(file_name);
+        let mut variants = vec![ImgVariant {
+            size_name: "small",
+            url: uri!("/photos", img: name.clone(), "small", &smaller.hash).to_string(),
+            width: smaller.width,
+        }];
+
+        // Most browsers can't display TIFFs, so they'd never pick the full image anyways
+        let is_tiff = image::guess_format(img_data).ok() == Some(image::ImageFormat::Tiff);
+
+        if let (Some((width, _)), false) = (full_size, is_tiff) {
+            variants.push(ImgVariant {
+                size_name: "full",
+                url: uri!("/photos", img: name, "full", full_rev).to_string(),
+                width,
+            });
+        }
+
+        variants
+    }
+
+    /// Returns the alt text to display for a photo
+    ///
+    /// Explicit alt text (from the `ALT_TEXT_PREFIX` line of the description) always takes
+    /// precedence. Otherwise, we fall back to the title, along with the name of the location if
+    /// there is one -- which isn't as good, but is better than nothing. The description is never
+    /// used, because it's usually about the photo rather than describing what's in it.
+    fn alt_text(exif_info: &PhotoExifInfo, location: Option<&AlbumReference>) -> String {
+        if let Some(alt) = &exif_info.alt_text {
+            return alt.clone();
+        }
+
+        match location {
+            Some(loc) => format!("{}, in {}", exif_info.title, loc.name),
+            None => exif_info.title.clone(),
+        }
+    }
+
+    /// Returns a description of every problem with the albums' references to images, given the
+    /// names of all of the images on disk
+    ///
+    /// Albums are checked in the order they appear in the albums info files, so that the problems
+    /// are listed in a predictable order.
+    fn album_problems(
+        config: &PhotosConfig,
+        all_albums: &HashMap<String, ParsedAlbum>,
+        all_album_paths: &[String],
+        all_photos_album: &ParsedAllPhotosAlbum,
+        collections: &[ParsedCollection],
+        img_names: &HashSet<String>,
+    ) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if config.all_album_path == COLLECTIONS_PATH {
+            problems.push(format!(
+                "configured all photos album path {:?} is reserved for the collections",
+                config.all_album_path
+            ));
+        }
+
+        for path in all_album_paths {
+            let info = &all_albums[path];
+
+            if !is_uri_idempotent(path) {
+                problems.push(format!(
+                    "bad album name {:?}: must URI encode to the same value",
+                    path
+                ));
+            }
+
+            if is_auto_date_path(path) {
+                problems.push(format!(
+                    "bad album name {:?}: conflicts with the auto-generated album for that date",
+                    path
+                ));
+            }
+
+            let missing: Vec<_> = info
+                .photos
+                .iter()
+                .filter(|p| !img_names.contains(*p))
+                .collect();
+            if !missing.is_empty() {
+                problems.push(format!(
+                    "album {:?} references image(s) that aren't on disk: {:?}",
+                    path, missing
+                ));
+            }
+
+            match &info.cover_img {
+                Some(img) if !img_names.contains(img) => problems.push(format!(
+                    "album {:?} has a cover image that isn't on disk: {:?}",
+                    path, img
+                )),
+                None if info.photos.is_empty() => problems.push(format!(
+                    "album {:?} has no photos to pick a cover image from",
+                    path
+                )),
+                _ => (),
+            }
+
+            if info.watermark && config.watermark_font.is_none() {
+                problems.push(format!(
+                    "album {:?} is watermarked, but there's no watermark font configured",
+                    path
+                ));
+            }
+
+            if let Err(e) = info.flex_grid.apply(FlexGridSettings::default()).validate() {
+                problems.push(format!(
+                    "album {:?} has invalid flex-grid settings: {:#}",
+                    path, e
+                ));
+            }
+        }
+
+        for c in collections {
+            let missing: Vec<_> = (c.albums.iter())
+                .filter(|p| !all_albums.contains_key(*p) && **p != config.all_album_path)
+                .collect();
+            if !missing.is_empty() {
+                problems.push(format!(
+                    "collection {:?} references album(s) that don't exist: {:?}",
+                    c.name, missing
+                ));
+            }
+        }
+
+        if let Some(img) = &all_photos_album.cover_img {
+            if !img_names.contains(img) {
+                problems.push(format!(
+                    "album {:?} has a cover image that isn't on disk: {:?}",
+                    config.all_album_path, img
+                ));
+            }
+        }
+
+        problems
+    }
+
     /// Helper function for [`Iterator::try_fold`] to extract an item from an iterator only if
     /// there's exactly one
     fn fold_extract_single<T>(acc: Option<T>, val: T) -> Result<Option<T>, ()> {
@@ -776,34 +2885,87 @@ impl PhotosState {
         }
     }
 
-    /// Returns the base64-encoded sha256 hash of the data
+    /// Returns the smaller version of the image with the given hash, only creating it if an
+    /// identical image hasn't already been processed
     ///
-    /// The hashing function is subject to change, though sha256 seems to be the best version for
-    /// us right now.
-    ///
-    /// The returned string is URL-safe.
-    fn hash(data: &[u8]) -> String {
-        use sha2::{Digest, Sha256};
+    /// If there are multiple identical images, whichever is processed first determines the
+    /// settings used for all of them.
+    fn get_smaller_img(
+        cache: &SmallerImgCache,
+        hash: &str,
+        settings: SmallImgSettings,
+        file_path: &Path,
+        img_data: &[u8],
+    ) -> Result<InMemImg> {
+        let mut cache_guard = cache.lock().unwrap();
 
-        let mut hasher = Sha256::new();
-        hasher.update(data);
+        let entry = match cache_guard.get(hash) {
+            Some(e) => e.clone(),
+            None => {
+                // We're the first one to process this image. Lock the entry *before* adding it to
+                // the cache, so that any duplicates wait for us to finish.
+                let entry = Arc::new(Mutex::new(None));
+                let mut entry_guard = entry.lock().unwrap();
+                cache_guard.insert(hash.to_owned(), entry.clone());
+                drop(cache_guard);
+
+                let img = Self::make_smaller_img(settings, file_path, img_data)?;
+                *entry_guard = Some(img.clone());
+                return Ok(img);
+            }
+        };
+
+        drop(cache_guard);
 
-        base64::encode_config(hasher.finalize(), base64::URL_SAFE_NO_PAD)
+        // If the first attempt failed, we'll try again ourselves. It'll likely fail again, but
+        // then at least the error will be attributed to this file as well.
+        let mut entry_guard = entry.lock().unwrap_or_else(|e| e.into_inner());
+        match &*entry_guard {
+            Some(img) => Ok(img.clone()),
+            None => {
+                let img = Self::make_smaller_img(settings, file_path, img_data)?;
+                *entry_guard = Some(img.clone());
+                Ok(img)
+            }
+        }
     }
 
     /// Creates a smaller version of the image - or returns the existing one, if it's already
     /// small enough.
     ///
-    /// The input image is expected to be JPEG encoded; the output `InMemImg` will be WEBP, and
-    /// will not have the maximum quality.
-    fn make_smaller_img(bigger_img_data: &[u8]) -> Result<InMemImg> {
-        use image::codecs::jpeg::JpegDecoder;
+    /// The input image can be in any format that we accept for photos (its format is guessed from
+    /// the content); the output `InMemImg` will be WEBP, and will not have the maximum quality.
+    /// If `settings.max_bytes` is set, the quality may be reduced to fit within it (see
+    /// [`encode_small_webp`]). `file_path` is only used for logging.
+    ///
+    /// [`encode_small_webp`]: Self::encode_small_webp
+    fn make_smaller_img(
+        settings: SmallImgSettings,
+        file_path: &Path,
+        bigger_img_data: &[u8],
+    ) -> Result<InMemImg> {
         use image::imageops::FilterType;
-        use image::{DynamicImage, GenericImageView};
+        use image::{GenericImageView, ImageFormat};
+
+        let format =
+            image::guess_format(bigger_img_data).context("failed to guess source image format")?;
+        let mut img = match format {
+            ImageFormat::Jpeg => Self::decode_jpeg_bounded(settings, file_path, bigger_img_data)?,
+            _ => {
+                // Only JPEGs can be decoded at a reduced scale, so anything else that's too big
+                // is an error
+                let (width, height) =
+                    image::io::Reader::with_format(Cursor::new(bigger_img_data), format)
+                        .into_dimensions()
+                        .context("failed to read source image dimensions")?;
+                if width as u64 * height as u64 > settings.max_source_pixelcount {
+                    return Err(SourceTooLarge { width, height }.into());
+                }
 
-        let mut img = JpegDecoder::new(bigger_img_data)
-            .and_then(DynamicImage::from_decoder)
-            .context("failed to construct source JPEG image")?;
+                image::load_from_memory_with_format(bigger_img_data, format)
+                    .context("failed to decode source image")?
+            }
+        };
 
         let (cur_width, cur_height) = {
             let (w, h) = img.dimensions();
@@ -812,8 +2974,10 @@ impl PhotosState {
 
         let current_pixelcount = cur_width * cur_height;
 
-        if current_pixelcount > SMALL_IMG_APROX_PIXELCOUNT {
-            let scale = (SMALL_IMG_APROX_PIXELCOUNT as f32 / current_pixelcount as f32).sqrt();
+        let target_pixelcount = settings.aprox_pixelcount;
+
+        if current_pixelcount > target_pixelcount {
+            let scale = (target_pixelcount as f32 / current_pixelcount as f32).sqrt();
 
             let new_width = (cur_width as f32 * scale) as u32;
             let new_height = (cur_height as f32 * scale) as u32;
@@ -824,22 +2988,171 @@ impl PhotosState {
             img = img.resize(new_width, new_height, FilterType::CatmullRom);
         }
 
-        let webp_repr = webp::Encoder::from_image(&img)
+        let webp_data = Self::encode_small_webp(settings, file_path, &img)?;
+
+        let (width, height) = img.dimensions();
+        let img_data = Arc::from(webp_data.into_boxed_slice());
+        let hash = content_hash(&img_data);
+
+        Ok(InMemImg {
+            height,
+            width,
+            hash,
+            img_data,
+        })
+    }
+
+    /// Encodes the smaller image as WEBP at `settings.quality`, or -- if `settings.max_bytes` is set
+    /// and the result is too big -- at the highest quality that fits
+    ///
+    /// Each attempt reduces the quality by `SMALL_IMG_QUALITY_STEP`, down to
+    /// `MIN_SMALL_IMG_QUALITY`. If it still doesn't fit by then, the lowest quality is used anyways.
+    /// `file_path` is only used for logging.
+    fn encode_small_webp(
+        settings: SmallImgSettings,
+        file_path: &Path,
+        img: &image::DynamicImage,
+    ) -> Result<Vec<u8>> {
+        let encoder = webp::Encoder::from_image(img)
+            .map_err(|e| anyhow!("{}", e))
+            .context("failed to encode WEBP image")?;
+
+        let mut quality = settings.quality;
+        let mut webp_data = encoder.encode(quality).to_vec();
+
+        let max_bytes = match settings.max_bytes {
+            Some(b) => b,
+            None => return Ok(webp_data),
+        };
+
+        while webp_data.len() as u64 > max_bytes && quality > MIN_SMALL_IMG_QUALITY {
+            quality = (quality - SMALL_IMG_QUALITY_STEP).max(MIN_SMALL_IMG_QUALITY);
+            webp_data = encoder.encode(quality).to_vec();
+        }
+
+        if webp_data.len() as u64 > max_bytes {
+            warn!(
+                "small image for {:?} is {} bytes at minimum quality {}, over the limit of {}",
+                file_path,
+                webp_data.len(),
+                quality,
+                max_bytes
+            );
+        } else if quality != settings.quality {
+            debug!(
+                "reduced small image quality for {:?} to {} to fit in {} bytes",
+                file_path, quality, max_bytes
+            );
+        }
+
+        Ok(webp_data)
+    }
+
+    /// Makes the square crop of the photo for an album cover, from its smaller image
+    ///
+    /// Landscape photos are cropped around the center. Portrait photos are cropped a bit above the
+    /// center instead, because that's usually closer to the subject (e.g. someone's face).
+    fn make_cover_crop(img: &PhotoInfo, quality: f32) -> Result<InMemImg> {
+        use image::imageops::FilterType;
+        use image::{GenericImageView, ImageFormat};
+
+        let small =
+            image::load_from_memory_with_format(&img.smaller_webp.img_data, ImageFormat::WebP)
+                .context("failed to decode small image")?;
+
+        let (width, height) = small.dimensions();
+        let side = width.min(height);
+        let (x, y) = match width >= height {
+            true => ((width - side) / 2, 0),
+            false => (0, (height - side) / 3),
+        };
+
+        let mut cropped = small.crop_imm(x, y, side, side);
+        if side > COVER_IMG_SIZE {
+            cropped = cropped.resize_exact(COVER_IMG_SIZE, COVER_IMG_SIZE, FilterType::CatmullRom);
+        }
+
+        let webp_repr = webp::Encoder::from_image(&cropped)
             .map_err(|e| anyhow!("{}", e))
             .context("failed to encode WEBP image")?
-            .encode(SMALL_IMG_QUALITY);
-
-        let (width, height) = img.dimensions();
-        let img_data = Arc::from(webp_repr.to_vec().into_boxed_slice());
-        let hash = Self::hash(&img_data);
+            .encode(quality);
 
+        let img_data: Arc<[u8]> = Arc::from(webp_repr.to_vec().into_boxed_slice());
         Ok(InMemImg {
-            height,
-            width,
-            hash,
+            height: cropped.height(),
+            width: cropped.width(),
+            hash: content_hash(&img_data),
             img_data,
         })
     }
+
+    /// Calls [`make_cover_crop`] for the cover of the album at `album_path`, logging any failure
+    ///
+    /// Returns `None` on failure, so that the album falls back to the cover photo's small image.
+    ///
+    /// [`make_cover_crop`]: Self::make_cover_crop
+    fn cover_crop_or_warn(album_path: &str, img: &PhotoInfo, quality: f32) -> Option<InMemImg> {
+        match Self::make_cover_crop(img, quality) {
+            Ok(crop) => Some(crop),
+            Err(e) => {
+                warn!(
+                    "failed to crop cover image {:?} for album {:?}: {:#}",
+                    img.file_name, album_path, e
+                );
+                None
+            }
+        }
+    }
+
+    /// Decodes the JPEG image, at a reduced scale if it's larger than the maximum pixel count
+    /// from `settings`. `file_path` is only used for logging.
+    ///
+    /// Returns a [`SourceTooLarge`] error if the image is too large even at the smallest scale.
+    fn decode_jpeg_bounded(
+        settings: SmallImgSettings,
+        file_path: &Path,
+        jpeg_data: &[u8],
+    ) -> Result<image::DynamicImage> {
+        use image::codecs::jpeg::JpegDecoder;
+        use image::{DynamicImage, ImageDecoder};
+
+        let mut decoder =
+            JpegDecoder::new(jpeg_data).context("failed to read source JPEG image")?;
+
+        // Decoding an enormous image could use up all of our memory, so those are decoded at a
+        // reduced scale instead. We're only making a smaller version anyways, so as long as the
+        // reduced scale is still bigger than what we're making, it doesn't matter.
+        let (src_width, src_height) = decoder.dimensions();
+        let src_pixelcount = src_width as u64 * src_height as u64;
+        let max_pixelcount = settings.max_source_pixelcount;
+        if src_pixelcount > max_pixelcount {
+            let denom = jpeg_scale_denom(src_pixelcount, max_pixelcount).ok_or(SourceTooLarge {
+                width: src_width,
+                height: src_height,
+            })?;
+            let to_u16 = |x: u32| (x / denom).clamp(1, u16::MAX as u32) as u16;
+
+            let (width, height) = decoder
+                .scale(to_u16(src_width), to_u16(src_height))
+                .context("failed to scale source JPEG image")?;
+
+            warn!(
+                "image {:?} is larger than the maximum pixel count ({}x{}); decoding at {}x{}",
+                file_path, src_width, src_height, width, height
+            );
+        }
+
+        DynamicImage::from_decoder(decoder).context("failed to construct source JPEG image")
+    }
+}
+
+/// Returns the smallest denominator that a JPEG can be decoded at (1/2, 1/4, or 1/8 scale) that
+/// brings its pixel count within `max_pixelcount`, or `None` if even 1/8 scale is too large
+fn jpeg_scale_denom(src_pixelcount: u64, max_pixelcount: u64) -> Option<u32> {
+    // Scaling each side by 1/d divides the pixel count by d^2
+    [2_u32, 4, 8]
+        .into_iter()
+        .find(|&d| src_pixelcount <= max_pixelcount.saturating_mul((d * d) as u64))
 }
 
 /// Helper type for constructing the albums that are auto-generated for dates that don't otherwise
@@ -851,6 +3164,17 @@ struct AutoDateAlbumBuilder {
     photos: BTreeSet<(DateTime<FixedOffset>, String)>,
 }
 
+/// Returns whether the album path has the same form as the auto-generated date albums (i.e.
+/// "YYYY-MM-DD"), and so might conflict with one of them
+///
+/// We can't know which dates the photos are from until they've all been processed, so any path
+/// that *could* be a date album is reserved. That way, conflicts are found before the expensive
+/// processing, and regardless of which photo happens to be processed first.
+fn is_auto_date_path(path: &str) -> bool {
+    NaiveDate::parse_from_str(path, "%Y-%m-%d")
+        .map_or(false, |d| d.format("%Y-%m-%d").to_string() == path)
+}
+
 impl AutoDateAlbumBuilder {
     fn new(date: Date<FixedOffset>) -> Self {
         // e.g. 18 December, 2021
@@ -875,24 +3199,70 @@ impl AutoDateAlbumBuilder {
 }
 
 impl PhotoExifInfo {
+    /// Replaces the displayed time, date, and offset with the ones in `timezone`, instead of the
+    /// offset that the photo was taken at
+    ///
+    /// `actual_datetime` is converted to the timezone's offset at that time as well. It still
+    /// refers to the same instant, so the order of photos doesn't change -- but the date albums and
+    /// "on this day" pages, which use its date, then agree with the displayed `date`.
+    fn display_in_timezone(&mut self, timezone: Tz) {
+        let in_timezone = self.actual_datetime.with_timezone(&timezone);
+        let datetime = in_timezone.with_timezone(&in_timezone.offset().fix());
+        self.actual_datetime = datetime;
+        self.local_time = format_datetime(datetime, FormatLevel::LocalTime);
+        self.tz_offset = format_datetime(datetime, FormatLevel::UtcOffset);
+        self.date = format_datetime(datetime, FormatLevel::Date);
+    }
+
     /// Parses the exif data in the file into the photo's information.
     ///
+    /// The title and description can also come from the IPTC or XMP metadata, for files that don't
+    /// have them in the EXIF data. For each, the first usable source (in order) is taken:
+    ///
+    ///  * Title: EXIF `ImageDescription`, XMP `dc:title`, IPTC `ObjectName`
+    ///  * Description: EXIF `UserComment`, XMP `dc:description`, IPTC `Caption/Abstract`
+    ///
+    /// The camera and lens names are normalized with `gear_names`.
+    ///
     /// Returns an error on EXIF errors or when the data doesn't meet our expectations.
-    fn from_img_data(contents: &[u8]) -> Result<Self> {
+    fn from_img_data(contents: &[u8], gear_names: &GearNames) -> Result<Self> {
         let exif = exif::Reader::new()
             // We need to pass the entire contents here as an *owned* vector because EXIF data can
             // be arbitrarily placed within an image; it's not a simple header.
             .read_from_container(&mut Cursor::new(contents))
             .context("failed to read exif data")?;
 
+        let text_fields = jpeg_meta::TextFields::read(contents);
+
         let datetime =
             Self::get_local_datetime(&exif).context("failed to construct local DateTime")?;
 
+        // A missing or unusable EXIF title is only an error if there's nothing to fall back to.
+        // The EXIF error is the one that's reported, because that's where we expect it to be.
+        let title = match Self::get_title(&exif) {
+            Ok(t) => t,
+            Err(e) => match text_fields.xmp_title.or(text_fields.iptc_title) {
+                Some(t) => t,
+                None => {
+                    return Err(e.context("no XMP or IPTC title to fall back to"))
+                        .context("failed to get photo title")
+                }
+            },
+        };
+
+        let camera_id =
+            gear_names.camera(Self::get_camera_id(&exif).context("failed to get camera name")?);
+        let lens_id = Self::get_lens_id(&exif)
+            .context("failed to get lens ID")?
+            .map(|id| gear_names.lens(id));
+
         let (description, alt_text) = Self::get_description(&exif)
             .context("failed to get photo description")?
+            .or(text_fields.xmp_description)
+            .or(text_fields.iptc_caption)
             .map(|desc| {
                 if !desc.starts_with(ALT_TEXT_PREFIX) {
-                    return (Some(markdown_to_html(&desc)), None);
+                    return (Some(markdown_to_html(&desc, MarkdownKind::Snippet)), None);
                 }
 
                 // Otherwise, extract the alt text from the beginning of the first line
@@ -912,33 +3282,111 @@ impl PhotoExifInfo {
 
                 (
                     Some(first_line[ALT_TEXT_PREFIX.len()..].to_owned()),
-                    Some(markdown_to_html(rest)),
+                    Some(markdown_to_html(rest, MarkdownKind::Snippet)),
                 )
             })
             .unwrap_or((None, None));
 
+        let iso = Self::get_iso(&exif).context("failed to get camera ISO")?;
+        let f_stop = Self::get_f_stop(&exif).context("failed to get camera F-Stop")?;
+        let exposure_time =
+            Self::get_exposure_time(&exif).context("failed to get camera exposure time")?;
+
         Ok(PhotoExifInfo {
-            title: Self::get_title(&exif).context("failed to get photo title")?,
+            title,
             description,
             alt_text,
             coords: Self::get_gps_coords(&exif).context("failed to get GPS coordinates")?,
-            camera: CameraInfo {
-                id: Self::get_camera_id(&exif).context("failed to get camera name")?,
-                lens_id: Self::get_lens_id(&exif).context("failed to get lens ID")?,
-                iso: Self::get_iso(&exif).context("failed to get camera ISO")?,
-                f_stop: Self::get_f_stop(&exif).context("failed to get camera F-Stop")?,
-                focal_length: Self::get_focal_length(&exif)
-                    .context("failed to get camera focal length")?,
-                exposure_time: Self::get_exposure_time(&exif)
-                    .context("failed to get camera exposure time")?,
-            },
+            altitude: Self::get_gps_altitude(&exif),
+            direction: Self::get_gps_direction(&exif),
+            camera: Some(CameraInfo {
+                camera_slug: gear_slug(&camera_id.0, &camera_id.1),
+                lens_slug: lens_id.as_ref().map(|(make, model)| gear_slug(make, model)),
+                id: camera_id,
+                lens_id,
+                exposure_value: exposure_value(f_stop, exposure_time.to_f64(), iso),
+                iso: Some(iso),
+                f_stop: Some(f_stop),
+                focal_length: Some(
+                    Self::get_focal_length(&exif).context("failed to get camera focal length")?,
+                ),
+                exposure_time: Some(format_exposure_time(exposure_time)),
+            }),
+            actual_datetime: datetime,
+            local_time: format_datetime(datetime, FormatLevel::LocalTime),
+            tz_offset: format_datetime(datetime, FormatLevel::UtcOffset),
+            date: format_datetime(datetime, FormatLevel::Date),
+            film: None,
+            notes: None,
+        })
+    }
+
+    /// Produces the information for a video clip or animation, from whatever metadata its
+    /// container has
+    ///
+    /// This is best-effort: the title defaults to the file name, and the time it was taken defaults
+    /// to the file's modification time. Clips never have camera information or GPS coordinates.
+    fn from_clip(path: &Path, file_name: &str) -> Result<Self> {
+        let meta = clips::probe(path).unwrap_or_else(|e| {
+            warn!("failed to read metadata for clip {:?}: {:#}", path, e);
+            clips::ClipMeta::default()
+        });
+
+        let datetime = match meta.creation_time {
+            Some(t) => t,
+            None => Self::modified_time(path)?,
+        };
+
+        Ok(PhotoExifInfo {
+            title: meta.title.unwrap_or_else(|| file_name.to_owned()),
+            description: meta
+                .description
+                .map(|d| markdown_to_html(&d, MarkdownKind::Snippet)),
+            alt_text: None,
+            coords: None,
+            altitude: None,
+            direction: None,
+            camera: None,
+            actual_datetime: datetime,
+            local_time: format_datetime(datetime, FormatLevel::LocalTime),
+            tz_offset: format_datetime(datetime, FormatLevel::UtcOffset),
+            date: format_datetime(datetime, FormatLevel::Date),
+            film: None,
+            notes: None,
+        })
+    }
+
+    /// Produces the information for a photo without any usable metadata, in the same way as
+    /// `from_clip` does for clips without any
+    fn without_metadata(path: &Path, file_name: &str) -> Result<Self> {
+        let datetime = Self::modified_time(path)?;
+
+        Ok(PhotoExifInfo {
+            title: file_name.to_owned(),
+            description: None,
+            alt_text: None,
+            coords: None,
+            altitude: None,
+            direction: None,
+            camera: None,
             actual_datetime: datetime,
             local_time: format_datetime(datetime, FormatLevel::LocalTime),
-            tz_offset: format_datetime(datetime, FormatLevel::Offset),
+            tz_offset: format_datetime(datetime, FormatLevel::UtcOffset),
             date: format_datetime(datetime, FormatLevel::Date),
+            film: None,
+            notes: None,
         })
     }
 
+    /// Returns the file's modification time, in the local timezone
+    fn modified_time(path: &Path) -> Result<DateTime<FixedOffset>> {
+        let modified = fs::metadata(path)
+            .and_then(|m| m.modified())
+            .context("failed to get file modification time")?;
+        let local: DateTime<Local> = modified.into();
+        Ok(local.with_timezone(local.offset()))
+    }
+
     fn get_title(exif: &exif::Exif) -> Result<String> {
         use exif::{In, Tag, Value};
 
@@ -986,7 +3434,9 @@ impl PhotoExifInfo {
         Ok(String::from_utf8(title.clone()).unwrap())
     }
 
-    /// Retrieves the description of the image from the EXIF data & converts it to HTML
+    /// Retrieves the (markdown) description of the image from the EXIF data
+    ///
+    /// Conversion to HTML happens in the caller, after any alt text is extracted.
     fn get_description(exif: &exif::Exif) -> Result<Option<String>> {
         use exif::{In, Tag, Value};
 
@@ -1009,7 +3459,7 @@ impl PhotoExifInfo {
         //     ---------------------------|-------------------
         //     b"ASCII\x00\x00\x00"       | ASCII text
         //     b"JIS\x00\x00\x00\x00\x00" | JIS-encoded text
-        //     b"UNICODE\x00"             | UTF-16 (LE?)
+        //     b"UNICODE\x00"             | UTF-16, of either endianness
         //     [0, 0, 0, 0, 0, 0, 0, 0]   | <Undefined>
         //
         // https://www.exif.org/Exif2-2.PDF
@@ -1036,40 +3486,9 @@ impl PhotoExifInfo {
             Some(b"JIS\x00\x00\x00\x00\x00") => {
                 bail!("unsupported JIS encoding for UserComment tag")
             }
-            Some(b"UNICODE\x00") => {
-                // String::from_utf16 requires that we give it u16s, so we have to convert tothem
-                // first.
-                //
-                // On my little-endian system, exiftool outputs little-endian UTF-16, so we'll
-                // assume that's what we're looking for. If it's not little-endian, then oh well --
-                // we'll just give an error. I can fix it later pretty easily.
-                //
-                // See:
-                //
-                //   "It is also reliable to detect endianness by looking for null bytes, on the
-                //    assumption that characters less than U+0100 are very common. If more even
-                //    bytes (starting at 0) are null, then it is big-endian"
-                //
-                // https://en.wikipedia.org/wiki/UTF-16#Byte-order_encoding_schemes
-
-                let s = &desc[8..];
-                if s.len() % 2 != 0 {
-                    bail!("odd length on UserComment tag's UTF-16 content");
-                }
-
-                let u16_len = s.len() / 2;
-                let mut v = vec![0_u16; u16_len];
-
-                // Little-endian conversion (u8, u8) -> u16
-                for i in 0..u16_len {
-                    v[i] = s[i * 2] as u16;
-                    v[i] |= (s[i * 2 + 1] as u16) << 8;
-                }
-
-                String::from_utf16(&v)
-                    .map(Cow::Owned)
-                    .context("UserComment tag was not valid UTF-16 LE")?
-            }
+            Some(b"UNICODE\x00") => decode_utf16(&desc[8..])
+                .map(Cow::Owned)
+                .context("UserComment tag was not valid UTF-16")?,
             Some([0, 0, 0, 0, 0, 0, 0, 0]) => {
                 bail!("unsupported 'Undefined' encoding for UserComment tag")
             }
@@ -1083,7 +3502,7 @@ impl PhotoExifInfo {
             return Ok(None);
         }
 
-        Ok(Some(markdown_to_html(md.as_ref())))
+        Ok(Some(md.into_owned()))
     }
 
     fn get_gps_coords(exif: &exif::Exif) -> Result<Option<GPSCoords>> {
@@ -1123,6 +3542,101 @@ impl PhotoExifInfo {
         }
     }
 
+    /// Returns the altitude from the `GPSAltitude` and `GPSAltitudeRef` tags, or `None` if they
+    /// aren't present
+    ///
+    /// The altitude is only an extra detail, so malformed tags are ignored (with a warning)
+    /// instead of failing the whole photo.
+    fn get_gps_altitude(exif: &exif::Exif) -> Option<i32> {
+        Self::parse_gps_altitude(exif).unwrap_or_else(|e| {
+            warn!("ignoring GPS altitude: {:#}", e);
+            None
+        })
+    }
+
+    /// Fallible part of `get_gps_altitude`
+    ///
+    /// The EXIF specification defaults `GPSAltitudeRef` to "above sea level", so it can be missing
+    /// on its own -- but not the other way around.
+    fn parse_gps_altitude(exif: &exif::Exif) -> Result<Option<i32>> {
+        use exif::{In, Tag, Value};
+
+        let altitude = Self::gps_single_rational(exif, Tag::GPSAltitude)
+            .context("could not read GPSAltitude tag")?;
+
+        let below_sea_level = match exif.get_field(Tag::GPSAltitudeRef, In::PRIMARY) {
+            None => None,
+            Some(f) => match &f.value {
+                Value::Byte(vs) if vs.len() == 1 => match vs[0] {
+                    0 => Some(false),
+                    1 => Some(true),
+                    v => bail!(
+                        "could not read GPSAltitudeRef tag: expected 0 or 1, found {}",
+                        v
+                    ),
+                },
+                v => bail!(
+                    "could not read GPSAltitudeRef tag: expected a single byte, found {:?}",
+                    v
+                ),
+            },
+        };
+
+        match (altitude, below_sea_level) {
+            (Some(alt), Some(true)) => Ok(Some(-alt.round() as i32)),
+            (Some(alt), _) => Ok(Some(alt.round() as i32)),
+            (None, None) => Ok(None),
+            (None, Some(_)) => bail!("partial GPS tags: missing \"GPSAltitude\""),
+        }
+    }
+
+    /// Returns the direction from the `GPSImgDirection` tag, or `None` if it isn't present
+    ///
+    /// `GPSImgDirectionRef` (whether the direction is relative to true or magnetic north) is
+    /// ignored; the difference isn't really noticeable at the precision that we display. Like with
+    /// `get_gps_altitude`, a malformed tag is ignored with a warning.
+    fn get_gps_direction(exif: &exif::Exif) -> Option<u16> {
+        Self::parse_gps_direction(exif).unwrap_or_else(|e| {
+            warn!("ignoring GPS direction: {:#}", e);
+            None
+        })
+    }
+
+    /// Fallible part of `get_gps_direction`
+    fn parse_gps_direction(exif: &exif::Exif) -> Result<Option<u16>> {
+        let direction = match Self::gps_single_rational(exif, exif::Tag::GPSImgDirection)
+            .context("could not read GPSImgDirection tag")?
+        {
+            Some(d) => d,
+            None => return Ok(None),
+        };
+
+        if !(0.0..=360.0).contains(&direction) {
+            bail!("GPSImgDirection {} is out of range", direction);
+        }
+
+        Ok(Some(direction.round() as u16 % 360))
+    }
+
+    /// Returns the value of a GPS tag that should have a single rational value, or `Ok(None)` if
+    /// the tag isn't present
+    fn gps_single_rational(exif: &exif::Exif, tag: exif::Tag) -> Result<Option<f64>> {
+        use exif::{In, Value};
+
+        let value = match exif.get_field(tag, In::PRIMARY) {
+            Some(f) => &f.value,
+            None => return Ok(None),
+        };
+
+        match value {
+            Value::Rational(vs) if vs.len() == 1 && vs[0].denom == 0 => {
+                bail!("zero denominator in {}/{}", vs[0].num, vs[0].denom)
+            }
+            Value::Rational(vs) if vs.len() == 1 => Ok(Some(vs[0].num as f64 / vs[0].denom as f64)),
+            _ => bail!("expected a single Rational, found {:?}", value),
+        }
+    }
+
     /// On success, returns -1 or 1, corresponding to the indicated direction of the GPS tag
     ///
     /// Returns `Ok(None)` if the tag isn't present, or `Err(_)` if the tag is malformed (the
@@ -1432,7 +3946,7 @@ impl PhotoExifInfo {
         }
     }
 
-    fn get_exposure_time(exif: &exif::Exif) -> Result<String> {
+    fn get_exposure_time(exif: &exif::Exif) -> Result<exif::Rational> {
         use exif::{In, Tag, Value};
 
         let value = &exif
@@ -1440,22 +3954,80 @@ impl PhotoExifInfo {
             .ok_or_else(|| anyhow!("missing ExposureTime tag"))?
             .value;
 
-        let rat = match value {
-            Value::Rational(vs) if vs.len() == 1 => vs[0],
+        match value {
+            Value::Rational(vs) if vs.len() == 1 => Ok(vs[0]),
             _ => bail!(
                 "expected single rational value in FocalLength tag, found {:?}",
                 value
             ),
-        };
+        }
+    }
+}
+
+/// Decodes UTF-16 text of unknown endianness, like the `UNICODE` content of the `UserComment` tag
+///
+/// A byte order mark takes precedence if there is one. Otherwise, we guess from the position of
+/// the null bytes:
+///
+///   "It is also reliable to detect endianness by looking for null bytes, on the assumption that
+///    characters less than U+0100 are very common. If more even bytes (starting at 0) are null,
+///    then it is big-endian"
+///
+/// https://en.wikipedia.org/wiki/UTF-16#Byte-order_encoding_schemes
+///
+/// Ties -- including text without any null bytes -- are treated as little-endian, which is what
+/// exiftool writes on little-endian systems.
+fn decode_utf16(bytes: &[u8]) -> Result<String> {
+    if bytes.len() % 2 != 0 {
+        bail!("odd length on UTF-16 content ({} bytes)", bytes.len());
+    }
 
-        // If the numerator is 1, then we can do a fractional formatting, e.g. 1/10
-        if rat.num == 1 {
-            return Ok(format!("1/{}", rat.denom));
+    let (big_endian, bytes) = match bytes {
+        [0xFE, 0xFF, rest @ ..] => (true, rest),
+        [0xFF, 0xFE, rest @ ..] => (false, rest),
+        _ => {
+            let null_at = |parity| (bytes.iter().skip(parity).step_by(2)).filter(|&&b| b == 0);
+            (null_at(0).count() > null_at(1).count(), bytes)
         }
+    };
+
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|pair| match big_endian {
+            true => u16::from_be_bytes([pair[0], pair[1]]),
+            false => u16::from_le_bytes([pair[0], pair[1]]),
+        })
+        .collect();
 
-        // Otherwise, we should probably just represent the duration as a fraction directly:
-        Ok(rat.to_f64().to_string())
+    let endianness = if big_endian { "BE" } else { "LE" };
+    String::from_utf16(&units).with_context(|| format!("invalid UTF-16 {}", endianness))
+}
+
+/// Formats the exposure time (in seconds) for display, like "1/30" or "2.5"
+fn format_exposure_time(rat: exif::Rational) -> String {
+    // If the numerator is 1, then we can do a fractional formatting, e.g. 1/10
+    if rat.num == 1 {
+        return format!("1/{}", rat.denom);
+    }
+
+    // Otherwise, we should probably just represent the duration as a fraction directly:
+    rat.to_f64().to_string()
+}
+
+/// Returns the exposure value of the camera settings, normalized to ISO 100 and formatted to one
+/// decimal place; e.g. "9.9" for f/8, 1/30 s, and ISO 200
+///
+/// This is `log2(N^2 / t) - log2(S / 100)`, for f-number `N`, exposure time `t` (in seconds), and
+/// ISO `S`. Returns `None` if any of them are zero (or otherwise nonsensical), which some cameras
+/// record when they don't know the real value -- e.g. with a manual lens.
+fn exposure_value(f_stop: f64, exposure_secs: f64, iso: u16) -> Option<String> {
+    let valid = |x: f64| x.is_finite() && x > 0.0;
+    if !valid(f_stop) || !valid(exposure_secs) || iso == 0 {
+        return None;
     }
+
+    let ev = (f_stop * f_stop / exposure_secs).log2() - (iso as f64 / 100.0).log2();
+    Some(format!("{:.1}", ev))
 }
 
 struct PhotosState {
@@ -1463,12 +4035,30 @@ struct PhotosState {
     // as special cases during construction; they're accessed normally.
     albums: HashMap<String, Arc<Album>>,
     // Every *manually created* album, separated by type and in the order that they were given in
-    // the original file
+    // the original file. Hidden albums are only included in debug builds.
     albums_in_order: AlbumsInOrder,
     // "path name" -> image
     images: HashMap<String, Arc<PhotoInfo>>,
     // All images, sorted by the time they were taken
     images_by_time: Vec<Arc<PhotoInfo>>,
+    // Names of the images that aren't in any album from the albums info file, sorted
+    orphans: Vec<String>,
+    // Initial view for the map of every photo
+    global_map_view: MapView,
+    // Camera slug -> the photos taken with it
+    by_camera: HashMap<String, GearPhotos>,
+    // Lens slug -> the photos taken with it
+    by_lens: HashMap<String, GearPhotos>,
+    // When this state was created (or last had a photo reprocessed)
+    generated_at: DateTime<Utc>,
+}
+
+/// All of the photos taken with a particular piece of gear
+struct GearPhotos {
+    /// The displayed name of the gear, e.g. "Nikon Z 6"
+    name: String,
+    /// The photos, most recent first
+    photos: Vec<Arc<PhotoInfo>>,
 }
 
 #[derive(Clone, Default, Serialize)]
@@ -1476,17 +4066,45 @@ struct AlbumsInOrder {
     normal_albums: Vec<Arc<Album>>,
     days: Vec<Arc<Album>>,
     locations: Vec<Arc<Album>>,
+    /// The collections of albums, in order. Albums can be in any number of collections.
+    collections: Vec<Collection>,
+    /// The albums from `normal_albums` that aren't in any collection
+    ungrouped: Vec<Arc<Album>>,
+}
+
+/// A named group of albums. See `ParsedCollection`.
+#[derive(Clone, Serialize)]
+struct Collection {
+    name: String,
+    /// The HTML of the collection's description
+    description: String,
+    albums: Vec<Arc<Album>>,
 }
 
 #[derive(Serialize)]
 struct IndexContext {
-    favorites: Arc<Album>,
+    favorites: FeaturedPhotos,
+    all_album_path: String,
+    all_album_name: String,
     flex_grid_settings: FlexGridSettings,
 }
 
+/// A selection of photos from one of the configured albums, shown outside of the album's own page
+#[derive(Serialize)]
+struct FeaturedPhotos {
+    /// Path of the album that the photos are viewed as part of
+    album_path: String,
+    photos: Vec<Arc<PhotoInfo>>,
+    /// Whether the configured album was missing or empty, so these are the most recent photos
+    /// instead
+    is_fallback: bool,
+}
+
 #[derive(Serialize)]
 struct ImagePageContext {
     album: Option<String>,
+    /// Contents of the page's `<script type="application/ld+json">`
+    json_ld: String,
     img: Arc<PhotoInfo>,
     previous: Option<Arc<PhotoInfo>>,
     next: Option<Arc<PhotoInfo>>,
@@ -1494,7 +4112,7 @@ struct ImagePageContext {
 }
 
 /// The initial view of a photos map on a page
-#[derive(Serialize)]
+#[derive(Copy, Clone, Serialize)]
 struct MapView {
     #[serde(rename = "centeredAt")]
     centered_at: GPSCoords,
@@ -1502,6 +4120,45 @@ struct MapView {
     zoom_level: u8,
 }
 
+impl MapView {
+    /// Returns the view centered on the bounding box of the photos with locations, zoomed out far
+    /// enough to include all of them
+    ///
+    /// Returns `None` if none of the photos have a location.
+    fn fitting(photos: &[Arc<PhotoInfo>]) -> Option<MapView> {
+        let mut coords = photos.iter().filter_map(|p| p.exif_info.coords);
+        let first = coords.next()?;
+
+        let (mut min, mut max) = (first, first);
+        for c in coords {
+            min.lat = min.lat.min(c.lat);
+            min.lon = min.lon.min(c.lon);
+            max.lat = max.lat.max(c.lat);
+            max.lon = max.lon.max(c.lon);
+        }
+
+        // At zoom level 0, a single tile covers 360 degrees of longitude (and roughly half that of
+        // latitude); each level after halves it. We fit the box within one tile's worth, which
+        // leaves plenty of room on any reasonably sized map.
+        let span = (max.lon - min.lon).max(2.0 * (max.lat - min.lat));
+        let zoom_level = match span > 0.0 {
+            true => (360.0 / span)
+                .log2()
+                .floor()
+                .clamp(0.0, MAX_FITTED_MAP_ZOOM as f64) as u8,
+            false => MAX_FITTED_MAP_ZOOM,
+        };
+
+        Some(MapView {
+            centered_at: GPSCoords {
+                lat: (min.lat + max.lat) / 2.0,
+                lon: (min.lon + max.lon) / 2.0,
+            },
+            zoom_level,
+        })
+    }
+}
+
 #[derive(Serialize)]
 struct AlbumContext {
     #[serde(flatten)]
@@ -1509,24 +4166,173 @@ struct AlbumContext {
     flex_grid_settings: FlexGridSettings,
 }
 
+/// A single album in the response provided by the `api_albums` route
+#[derive(Serialize)]
+pub struct AlbumSummary {
+    path: String,
+    name: String,
+    /// One of "normal", "day", or "location"
+    kind: &'static str,
+    photo_count: usize,
+    /// Path of the album's cover image, versioned by its hash
+    cover_img_path: String,
+}
+
+/// A photo used as the cover of a blog post, supplied by `cover_image`
+#[derive(Debug, Clone, Serialize)]
+pub struct CoverImage {
+    /// The photo's file name, for linking to its page
+    name: String,
+    alt: String,
+    #[serde(flatten)]
+    img: LocalImage,
+}
+
+/// The response provided by the `meta` route
+#[derive(Serialize)]
+pub struct PhotoMeta {
+    #[serde(flatten)]
+    exif_info: PhotoExifInfo,
+    /// The date & time at which the photo was taken, in RFC 3339 format
+    datetime: String,
+}
+
+/// A single photo in the response provided by the `more` route
+#[derive(Serialize)]
+pub struct MorePhoto {
+    file_name: String,
+    title: String,
+    alt: String,
+    /// Path of the photo's smaller image, versioned by its hash
+    small_img_path: String,
+    /// Dimensions of the smaller image
+    width: u32,
+    height: u32,
+}
+
+#[derive(Serialize)]
+struct NearContext {
+    center: GPSCoords,
+    radius_km: f64,
+    /// The photos taken within `radius_km` of `center`, most recent first
+    photos: Vec<Arc<PhotoInfo>>,
+    flex_grid_settings: FlexGridSettings,
+}
+
+#[derive(Serialize)]
+struct OnThisDayContext {
+    /// The displayed day of the year, e.g. "March 14"
+    date: String,
+    /// Each year that has matching photos, most recent first
+    years: Vec<YearPhotos>,
+    /// All of the matching photos, in the same order as `years`
+    photos: Vec<Arc<PhotoInfo>>,
+    flex_grid_settings: FlexGridSettings,
+}
+
+#[derive(Serialize)]
+struct GearContext {
+    /// The displayed name of the camera or lens
+    name: String,
+    /// The photos taken with it, most recent first
+    photos: Vec<Arc<PhotoInfo>>,
+    flex_grid_settings: FlexGridSettings,
+}
+
+#[derive(Serialize)]
+struct YearPhotos {
+    year: i32,
+    photos: Vec<Arc<PhotoInfo>>,
+}
+
 #[derive(Serialize)]
 struct MapContext {
+    /// What the map is limited to, if it isn't every photo; e.g. "Favorites"
+    subset_name: Option<String>,
     photos: Vec<Arc<PhotoInfo>>,
     map_view: MapView,
 }
 
 impl PhotosState {
     fn index_context(&self) -> IndexContext {
+        let config = CONFIG.load();
         IndexContext {
-            favorites: self.albums[FAVORITES_ALBUM_NAME].clone(),
+            favorites: self.featured_photos(&config.favorites_album, usize::MAX),
+            all_album_path: config.all_album_path.clone(),
+            all_album_name: config.all_album_name.clone(),
             flex_grid_settings: FlexGridSettings::default(),
         }
     }
 
+    /// Returns up to `limit` photos from the album, in order
+    ///
+    /// If the album doesn't exist or is empty, the most recent photos are used instead, up to
+    /// `NUM_FALLBACK_PHOTOS`. That way, the site root and the photos index are never left empty
+    /// just because of a missing album.
+    fn featured_photos(&self, path: &str, limit: usize) -> FeaturedPhotos {
+        if let Some(album) = self.albums.get(path).filter(|a| !a.photos.is_empty()) {
+            return FeaturedPhotos {
+                album_path: album.path.clone(),
+                photos: album.photos.iter().take(limit).cloned().collect(),
+                is_fallback: false,
+            };
+        }
+
+        // The "all photos" album is already sorted with the most recent first
+        let all_album_path = CONFIG.load().all_album_path.clone();
+        let photos = match self.albums.get(&all_album_path) {
+            Some(all) => {
+                let n = limit.min(NUM_FALLBACK_PHOTOS);
+                all.photos.iter().take(n).cloned().collect()
+            }
+            None => Vec::new(),
+        };
+
+        FeaturedPhotos {
+            album_path: all_album_path,
+            photos,
+            is_fallback: true,
+        }
+    }
+
+    /// Returns the sorted names of the still photos that don't have explicit alt text, excluding
+    /// any in `PhotosConfig::alt_text_exempt`
+    ///
+    /// These still get an alt text from their title (see `alt_text`), but that rarely describes
+    /// what's actually in the photo. Clips can't have alt text at all, so they're never included.
+    fn missing_alt_text(&self, config: &PhotosConfig) -> Vec<String> {
+        let mut names: Vec<_> = (self.images.values())
+            .filter(|img| img.media_kind == MediaKind::Photo && img.exif_info.alt_text.is_none())
+            .map(|img| img.file_name.clone())
+            .filter(|name| !config.alt_text_exempt.contains(name))
+            .collect();
+        names.sort_unstable();
+        names
+    }
+
     fn albums_context(&self) -> AlbumsInOrder {
         self.albums_in_order.clone()
     }
 
+    /// Returns the name of a randomly selected photo, from the album if one is given
+    fn random_img(&self, album: Option<&str>) -> Result<String, http::Status> {
+        let photos = match album {
+            None => &self.images_by_time,
+            Some(name) => &self.albums.get(name).ok_or(http::Status::NotFound)?.photos,
+        };
+
+        if photos.is_empty() {
+            return Err(http::Status::NotFound);
+        }
+
+        // We don't need anything fancy here, so we just use the randomly-keyed hasher from the
+        // standard library instead of pulling in an RNG. Each `RandomState` has different keys.
+        let random = RandomState::new().build_hasher().finish();
+        let idx = (random % photos.len() as u64) as usize;
+
+        Ok(photos[idx].file_name.clone())
+    }
+
     fn img_page_context(
         &self,
         img: &str,
@@ -1545,7 +4351,7 @@ impl PhotosState {
             Some(name) => match self.albums.get(name) {
                 None => {
                     return Ok(MaybeRedirect::Redirect {
-                        new_url: uri!("/photos", img_page: Cow::Borrowed(img), ""),
+                        new_url: uri!("/photos", img_page: Cow::Borrowed(img), _),
                         is_permanent: false,
                     })
                 }
@@ -1569,16 +4375,14 @@ impl PhotosState {
         let previous = idx.checked_sub(1).map(|i| img_list[i].clone());
         let next = img_list.get(idx + 1).cloned();
 
-        let map_view = img_info.exif_info.coords.map(|c| {
-            MapView {
-                centered_at: c,
-                // Just picking some value for now; we might make this per-image later - who knows.
-                zoom_level: 12,
-            }
+        let map_view = img_info.exif_info.coords.map(|c| MapView {
+            centered_at: c,
+            zoom_level: img_info.map_zoom_level.unwrap_or(DEFAULT_PHOTO_MAP_ZOOM),
         });
 
         Ok(MaybeRedirect::Dont(ImagePageContext {
             album,
+            json_ld: Self::image_json_ld(&img_info),
             img: img_info,
             next,
             previous,
@@ -1586,18 +4390,144 @@ impl PhotosState {
         }))
     }
 
-    fn album_context(&self, name: &str) -> Option<AlbumContext> {
-        Some(AlbumContext {
-            album: self.albums.get(name)?.clone(),
+    /// Returns the structured data for the photo's page
+    fn image_json_ld(img: &PhotoInfo) -> String {
+        let location = img.location.as_ref().map(|l| l.name.as_str());
+        let geo = img.exif_info.coords.map(|c| GeoCoordinates {
+            latitude: c.lat,
+            longitude: c.lon,
+        });
+
+        // The last variant is the largest: the full image for photos, or the poster frame for clips
+        let full = img
+            .srcset
+            .last()
+            .expect("every photo has at least its small image");
+
+        ImageObject {
+            name: &img.exif_info.title,
+            caption: &img.alt,
+            content_url: format!("{}{}", SITE_URL, full.url),
+            thumbnail_url: format!("{}{}", SITE_URL, img.small_img_path()),
+            date_created: img.exif_info.actual_datetime,
+            content_location: (location.is_some() || geo.is_some()).then(|| Place {
+                name: location,
+                geo,
+            }),
+        }
+        .to_script()
+    }
+
+    fn album_context(&self, name: &str) -> Option<AlbumContext> {
+        let album = self.albums.get(name)?.clone();
+        Some(AlbumContext {
+            flex_grid_settings: album.flex_grid.apply(FlexGridSettings::default()),
+            album,
+        })
+    }
+
+    /// Produces the context for the photos taken with the gear with the given slug, from either
+    /// `by_camera` or `by_lens`
+    fn gear_context(&self, gear: &HashMap<String, GearPhotos>, slug: &str) -> Option<GearContext> {
+        let g = gear.get(slug)?;
+        Some(GearContext {
+            name: g.name.clone(),
+            photos: g.photos.clone(),
+            flex_grid_settings: FlexGridSettings::default(),
+        })
+    }
+
+    /// Produces the context for the photos taken within `radius_km` of `center`
+    fn near_context(&self, center: GPSCoords, radius_km: f64) -> NearContext {
+        let photos = (self.images_by_time.iter().rev())
+            .filter(|img| match &img.exif_info.coords {
+                Some(c) => c.distance_km(&center) <= radius_km,
+                None => false,
+            })
+            .cloned()
+            .collect();
+
+        NearContext {
+            center,
+            radius_km,
+            photos,
+            flex_grid_settings: FlexGridSettings::default(),
+        }
+    }
+
+    /// Produces the context for the photos taken on the given day of the year. `month` and `day`
+    /// must together be a valid date in a leap year.
+    fn on_this_day_context(&self, month: u32, day: u32) -> OnThisDayContext {
+        let mut years: Vec<YearPhotos> = Vec::new();
+
+        // `images_by_time` is sorted oldest first, so we go in reverse for the most recent years
+        // first. Within each year, photos stay in the order they were taken.
+        let matching = self.images_by_time.iter().rev().filter(|img| {
+            let dt = img.exif_info.actual_datetime;
+            dt.month() == month && dt.day() == day
+        });
+
+        for img in matching {
+            let year = img.exif_info.actual_datetime.year();
+            match years.last_mut() {
+                Some(y) if y.year == year => y.photos.insert(0, img.clone()),
+                _ => years.push(YearPhotos {
+                    year,
+                    photos: vec![img.clone()],
+                }),
+            }
+        }
+
+        let date = NaiveDate::from_ymd(2000, month, day)
+            .format("%B %-d")
+            .to_string();
+
+        OnThisDayContext {
+            date,
+            photos: years
+                .iter()
+                .flat_map(|y| y.photos.iter().cloned())
+                .collect(),
+            years,
             flex_grid_settings: FlexGridSettings::default(),
-        })
+        }
     }
 
-    fn map_context(&self) -> MapContext {
-        MapContext {
-            photos: self.images_by_time.clone(),
-            map_view: GLOBAL_MAP_VIEW,
-        }
+    /// Produces the context for the map, optionally limited to the favorites and/or the photos in
+    /// an album. Returns `None` if the album doesn't exist.
+    fn map_context(&self, favorites_only: bool, album: Option<&str>) -> Option<MapContext> {
+        let (photos, album_name) = match album {
+            None if !favorites_only => {
+                return Some(MapContext {
+                    subset_name: None,
+                    photos: self.images_by_time.clone(),
+                    map_view: self.global_map_view,
+                })
+            }
+            None => (&self.images_by_time, None),
+            Some(name) => {
+                let a = self.albums.get(name)?;
+                (&a.photos, Some(&a.name))
+            }
+        };
+
+        let photos: Vec<_> = photos
+            .iter()
+            .filter(|p| !favorites_only || p.is_favorite)
+            .cloned()
+            .collect();
+
+        let subset_name = match (album_name, favorites_only) {
+            (Some(name), true) => format!("Favorites from {}", name),
+            (Some(name), false) => name.clone(),
+            (None, _) => "Favorites".to_owned(),
+        };
+
+        Some(MapContext {
+            subset_name: Some(subset_name),
+            map_view: MapView::fitting(&photos).unwrap_or(WORLD_MAP_VIEW),
+            photos,
+        })
     }
 }
 
@@ -1610,13 +4540,82 @@ struct Album {
     path: String,
     /// A markdown description of the album
     description: String,
+    /// The HTML of the album's longer-form notes, if it has any
+    notes: Option<String>,
     /// The kind of album, if it's anything notable
     kind: Option<AlbumKind>,
     /// The image used to represent this album -- ideally unique, but not strictly required to be
     cover_img: Arc<PhotoInfo>,
+    /// The number of photos in the album
+    photo_count: usize,
+    /// The span of time over which the photos were taken, or `None` if the album is empty
+    date_range: Option<DateRange>,
     /// Ordered listing of all of the photos. `photos[0]` is displayed first, `photos[1]` second,
     /// etc.
     photos: Vec<Arc<PhotoInfo>>,
+    /// Whether the album is hidden from the listings. See `ParsedAlbum::hidden`.
+    hidden: bool,
+    /// Overrides for the default flex-grid settings. See `ParsedAlbum::flex_grid`.
+    #[serde(skip)]
+    flex_grid: FlexGridOverrides,
+    /// Square crop of the cover image for the album listings, if enabled by
+    /// `PhotosConfig::crop_album_covers`
+    cover_crop: Option<InMemImg>,
+    /// Whether the album can be downloaded as a ZIP file -- i.e. it doesn't have `disable_download`
+    /// set, and none of its photos are watermarked. See `album_download`.
+    downloadable: bool,
+}
+
+impl Album {
+    /// Returns whether an album with the given photos can be downloaded as a ZIP file, used for
+    /// `Album::downloadable`
+    ///
+    /// Downloading the original files would get around the watermark, so albums with any
+    /// watermarked photos never are.
+    fn is_downloadable(disable_download: bool, photos: &[Arc<PhotoInfo>]) -> bool {
+        !disable_download && !photos.iter().any(|p| p.watermarked)
+    }
+
+    /// Returns a key for the album's sprite sheet, which changes whenever the sheet would -- i.e.
+    /// when the album's photos (or their small images) change
+    fn sprite_key(&self) -> String {
+        let hashes: Vec<_> = self
+            .photos
+            .iter()
+            .map(|p| p.smaller_webp.hash.as_str())
+            .collect();
+
+        content_hash(hashes.join(",").as_bytes())
+    }
+}
+
+/// The span of time over which the photos in an album were taken
+#[derive(Debug, Clone, Serialize)]
+struct DateRange {
+    /// The date & time of the earliest photo, in RFC 3339 format
+    earliest: String,
+    /// The date & time of the latest photo, in RFC 3339 format
+    latest: String,
+    /// The range of dates for display; e.g. "Mar 3–9, 2023"
+    display: String,
+}
+
+impl DateRange {
+    /// Returns the range covering all of the photos, in any order
+    ///
+    /// Photos are compared by their local time, because that's what's displayed. With different
+    /// offsets, the earliest instant isn't necessarily the earliest date.
+    fn of_photos(photos: &[Arc<PhotoInfo>]) -> Option<Self> {
+        let times = photos.iter().map(|p| p.exif_info.actual_datetime);
+        let earliest = times.clone().min_by_key(|t| t.naive_local())?;
+        let latest = times.max_by_key(|t| t.naive_local())?;
+
+        Some(DateRange {
+            earliest: earliest.to_rfc3339(),
+            latest: latest.to_rfc3339(),
+            display: format_date_range(earliest, latest),
+        })
+    }
 }
 
 #[derive(Debug, Copy, Clone, Serialize)]
@@ -1646,15 +4645,26 @@ struct PhotoExifInfo {
 
     /// The alt text for the image, if provided -- it'll be parsed from the same EXIF field as the
     /// description.
+    ///
+    /// Pages should use `PhotoInfo::alt` instead, which has a fallback if this isn't provided.
     alt_text: Option<String>,
 
     coords: Option<GPSCoords>,
+    /// The altitude at which the photo was taken, in meters above sea level (negative if below),
+    /// rounded to the nearest meter
+    altitude: Option<i32>,
+    /// The compass direction that the camera was facing, in degrees clockwise from north, rounded
+    /// to the nearest degree
+    direction: Option<u16>,
 
     /// Metadata about the camera that took the photo
-    camera: CameraInfo,
+    ///
+    /// Only `None` for clips, which don't have EXIF data.
+    camera: Option<CameraInfo>,
 
     /// The actual date & time at which the photo was taken, preserved so that we can use it for
-    /// comparisons & date extraction later
+    /// comparisons & date extraction later. Its offset is the one that the photo is displayed at
+    /// (see `display_in_timezone`).
     #[serde(skip)]
     actual_datetime: DateTime<FixedOffset>,
 
@@ -1665,6 +4675,11 @@ struct PhotoExifInfo {
     /// The date on which the photo was taken; can be derived from `actual_datetime`, but stored
     /// here for convenience.
     date: String,
+
+    /// The film stock the photo was taken on, if it's known. Only ever set by `PhotoOverrides`.
+    film: Option<String>,
+    /// The HTML of any extra notes about the photo. Only ever set by `PhotoOverrides`.
+    notes: Option<String>,
 }
 
 /// Information about the camera (and its settings) for a particular photo
@@ -1686,6 +4701,11 @@ struct CameraInfo {
     /// The pair is `(LensMake, LensModel)`.
     lens_id: Option<(String, String)>,
 
+    /// URL-safe identifier for the camera, used for its page of photos
+    camera_slug: String,
+    /// URL-safe identifier for the lens (if there is one), used for its page of photos
+    lens_slug: Option<String>,
+
     /// Taken from the `PhotographicSensitivity` EXIF tag
     ///
     /// The naming of the tag is a little weird; it was previously called `ISOSpeedRatings` in EXIF
@@ -1693,35 +4713,175 @@ struct CameraInfo {
     /// source:
     ///
     /// https://github.com/exiftool/exiftool/blob/74dbab1d2766d6422bb05b033ac6634bf8d1f582/lib/Image/ExifTool/Exif.pm#L1943-L1947
-    iso: u16,
+    ///
+    /// Like the rest of the exposure settings, this is always present in the embedded metadata,
+    /// but can be removed by `PhotoOverrides` (or be missing, if the camera came from there).
+    iso: Option<u16>,
 
     /// Taken from the `FNumber` EXIF tag
-    f_stop: f64,
+    f_stop: Option<f64>,
 
     /// The focal length of the camera, *without* translating to 35mm film format
-    focal_length: f64,
+    focal_length: Option<f64>,
 
     /// The exposure time for the photo, in seconds; e.g. `1/30` or `10`.
-    exposure_time: String,
+    exposure_time: Option<String>,
+
+    /// The exposure value at ISO 100 from the other settings, formatted to one decimal place; e.g.
+    /// `9.9`. `None` if any of the settings are zero. See [`exposure_value`].
+    exposure_value: Option<String>,
+}
+
+impl CameraInfo {
+    /// Produces the information for a camera that we only know the `(make, model)` of
+    fn without_settings(id: (String, String)) -> Self {
+        CameraInfo {
+            camera_slug: gear_slug(&id.0, &id.1),
+            id,
+            lens_id: None,
+            lens_slug: None,
+            iso: None,
+            f_stop: None,
+            focal_length: None,
+            exposure_time: None,
+            exposure_value: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub struct PhotoInfo {
     file_name: String,
+    media_kind: MediaKind,
+    /// The original file on disk
+    #[serde(skip)]
+    path: PathBuf,
 
     #[serde(flatten)]
     exif_info: PhotoExifInfo,
+    /// The alt text to use for the photo, which is never empty: either the explicit `alt_text`
+    /// from `exif_info`, or a fallback. See `PhotosState::alt_text`.
+    alt: String,
 
     is_favorite: bool,
     albums: Vec<AlbumReference>,
     location: Option<AlbumReference>,
     day_album: AlbumReference,
+    /// Zoom level for the map on the photo's page, if one of its albums sets it
+    #[serde(skip)]
+    map_zoom_level: Option<u8>,
+    /// Whether the full-size image is served with a watermark. See `ParsedAlbum::watermark`.
+    watermarked: bool,
 
     #[serde(rename = "smaller")]
     smaller_webp: InMemImg,
 
     // The sha256 hash of the full image, base64 encoded
     full_img_hash: String,
+    /// The revision of the full image, for its URL and `ETag`: the same as `full_img_hash`, unless
+    /// the image is watermarked -- then it also includes the `Watermark::key`, so that a new
+    /// watermark gets a new URL.
+    #[serde(skip)]
+    full_img_rev: String,
+    /// The `(width, height)` of the full image, read when it's loaded. `None` for clips and
+    /// animations.
+    #[serde(skip)]
+    full_size: Option<(u32, u32)>,
+    /// The sizes that the image is available in, for `srcset` attributes. See
+    /// `PhotosState::img_variants`.
+    srcset: Vec<ImgVariant>,
+}
+
+/// One of the sizes that a photo is available in
+#[derive(Debug, Clone, Serialize)]
+struct ImgVariant {
+    /// The `size` parameter for the `img` route; e.g. "small"
+    size_name: &'static str,
+    /// URL of this size of the image, including its hash
+    url: String,
+    width: u32,
+}
+
+impl PhotoInfo {
+    /// Returns the name of the photo's file, which is also used for its page
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    /// Returns the title of the photo
+    pub fn title(&self) -> &str {
+        &self.exif_info.title
+    }
+
+    /// Returns the photo's description as HTML, if it has one
+    pub fn description(&self) -> Option<&str> {
+        self.exif_info.description.as_deref()
+    }
+
+    /// Returns the date & time at which the photo was taken
+    pub fn taken_at(&self) -> DateTime<FixedOffset> {
+        self.exif_info.actual_datetime
+    }
+
+    /// Returns the path of the photo's smaller image, versioned by its hash
+    pub fn small_img_path(&self) -> String {
+        format!(
+            "/photos/img-file/{}?size=small&rev={}",
+            self.file_name, self.smaller_webp.hash
+        )
+    }
+
+    /// Returns the paths of every album the photo is in, sorted
+    fn album_paths<'a>(&'a self, config: &'a PhotosConfig) -> Vec<&'a str> {
+        let mut paths: Vec<_> = self
+            .location
+            .iter()
+            .chain(Some(&self.day_album))
+            .chain(&self.albums)
+            .map(|r| r.path.as_str())
+            .chain(self.is_favorite.then_some(config.favorites_album.as_str()))
+            .collect();
+        paths.sort_unstable();
+        paths
+    }
+}
+
+/// The kind of file that a `PhotoInfo` is for
+///
+/// Everything other than photos has a poster frame for its smaller image, and can only be viewed
+/// in full through the `media` route.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum MediaKind {
+    /// A still photo -- JPEG, WEBP, or PNG
+    Photo,
+    /// An MP4 video clip
+    Video,
+    /// An animated GIF
+    Animation,
+}
+
+impl MediaKind {
+    /// Returns the kind of the file at the path, from its extension
+    fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "jpg" | "webp" | "png" | "tif" | "tiff" => Some(MediaKind::Photo),
+            "mp4" => Some(MediaKind::Video),
+            "gif" => Some(MediaKind::Animation),
+            _ => None,
+        }
+    }
+
+    /// Returns the content type for serving the file directly, which is only done for clips
+    ///
+    /// Photos are served with `NamedFile`, which picks the content type from the real extension.
+    fn content_type(self) -> http::ContentType {
+        match self {
+            MediaKind::Photo => http::ContentType::Binary,
+            MediaKind::Video => http::ContentType::new("video", "mp4"),
+            MediaKind::Animation => http::ContentType::GIF,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -1762,8 +4922,20 @@ struct GPSCoords {
     lon: f64,
 }
 
+impl GPSCoords {
+    /// Returns the great-circle distance to the other point, using the haversine formula
+    fn distance_km(&self, other: &GPSCoords) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let d_lat = lat2 - lat1;
+        let d_lon = (other.lon - self.lon).to_radians();
+
+        let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().min(1.0).asin()
+    }
+}
+
 impl<'r> Responder<'r> for InMemImg {
-    fn respond_to(self, _req: &Request) -> response::Result<'r> {
+    fn respond_to(self, req: &Request) -> response::Result<'r> {
         use http::{uncased::Uncased, ContentType};
         use rocket::Response;
 
@@ -1774,34 +4946,234 @@ impl<'r> Responder<'r> for InMemImg {
                 name: Uncased::new("Cache-Control"),
                 value: Cow::Borrowed(PHOTO_CACHE_POLICY),
             })
-            .sized_body(Cursor::new(self.img_data));
+            .header(http::Header::new("ETag", etag(&self.hash)));
+
+        let len = self.img_data.len();
+        set_ranged_body(&mut builder, req, Cursor::new(self.img_data), len)?;
+        Ok(builder.finalize())
+    }
+}
+
+/// Sets the body of the response to `body` (with length `len`) -- or just the part of it given by
+/// the request's `Range` header, if there is one
+///
+/// Only the requested part is read, so this works just as well for files as it does for data
+/// that's already in memory.
+fn set_ranged_body<'r, B: Read + Seek + 'r>(
+    builder: &mut rocket::response::ResponseBuilder<'r>,
+    req: &Request,
+    mut body: B,
+    len: usize,
+) -> Result<(), http::Status> {
+    builder.header(http::Header::new("Accept-Ranges", "bytes"));
+
+    let range = req
+        .headers()
+        .get_one("Range")
+        .map(|r| parse_byte_range(r, len));
+
+    match range {
+        // No range, or one that we couldn't parse: just give the full thing
+        None | Some(ByteRange::Invalid) => {
+            builder.sized_body(body);
+        }
+        Some(ByteRange::Unsatisfiable) => {
+            builder
+                .status(http::Status::RangeNotSatisfiable)
+                .header(http::Header::new(
+                    "Content-Range",
+                    format!("bytes */{}", len),
+                ));
+        }
+        Some(ByteRange::Satisfiable(range)) => {
+            body.seek(SeekFrom::Start(range.start as u64))
+                .map_err(|e| {
+                    error!("failed to seek for range request: {}", e);
+                    http::Status::InternalServerError
+                })?;
+
+            let size = range.len() as u64;
+            let content_range = format!("bytes {}-{}/{}", range.start, range.end - 1, len);
+            builder
+                .status(http::Status::PartialContent)
+                .header(http::Header::new("Content-Range", content_range))
+                .raw_body(Body::Sized(body.take(size), size));
+        }
+    }
+
+    Ok(())
+}
+
+/// An album's photos as a ZIP file, served by `album_download`
+pub struct AlbumZip {
+    /// The name the file should be saved as
+    file_name: String,
+    zip: ZipStream,
+}
+
+impl<'r> Responder<'r> for AlbumZip {
+    fn respond_to(self, _req: &Request) -> response::Result<'r> {
+        use rocket::Response;
+
+        // Album paths are restricted to URI-safe characters, so the file name doesn't need any
+        // quoting beyond this
+        let disposition = format!("attachment; filename=\"{}\"", self.file_name);
+
+        Response::build()
+            .header(http::ContentType::new("application", "zip"))
+            .header(http::Header::new("Content-Disposition", disposition))
+            .streamed_body(self.zip)
+            .ok()
+    }
+}
+
+/// The original file for a video clip or animation, served by `media`
+pub struct StoredClip {
+    kind: MediaKind,
+    /// The hash of the file's content, used for its `ETag`
+    hash: String,
+    /// The file and its length, or `None` if the client already has it -- which produces a
+    /// `304 Not Modified`
+    file: Option<(fs::File, usize)>,
+}
+
+impl<'r> Responder<'r> for StoredClip {
+    fn respond_to(self, req: &Request) -> response::Result<'r> {
+        use http::uncased::Uncased;
+        use rocket::Response;
+
+        let mut builder = Response::build();
+        builder
+            .header(http::Header {
+                name: Uncased::new("Cache-Control"),
+                value: Cow::Borrowed(PHOTO_CACHE_POLICY),
+            })
+            .header(http::Header::new("ETag", etag(&self.hash)));
+
+        match self.file {
+            Some((file, len)) => {
+                builder.header(self.kind.content_type());
+                set_ranged_body(&mut builder, req, file, len)?;
+            }
+            None => {
+                builder.status(http::Status::NotModified);
+            }
+        }
 
         Ok(builder.finalize())
     }
 }
 
+/// The result of parsing a `Range` header
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ByteRange {
+    /// A range of bytes that's within the content
+    Satisfiable(Range<usize>),
+    /// A valid range that doesn't overlap with the content at all
+    Unsatisfiable,
+    /// Anything that we couldn't parse -- or multiple ranges, which we don't support
+    Invalid,
+}
+
+/// Parses the value of a `Range` header for content of the given length
+///
+/// Only a single range is supported, in any of the forms "bytes=<start>-<end>",
+/// "bytes=<start>-", or "bytes=-<suffix length>".
+fn parse_byte_range(header: &str, len: usize) -> ByteRange {
+    let spec = match header.trim().strip_prefix("bytes=") {
+        Some(s) if !s.contains(',') => s.trim(),
+        _ => return ByteRange::Invalid,
+    };
+
+    let (start, end) = match spec.split_once('-') {
+        Some(pair) => pair,
+        None => return ByteRange::Invalid,
+    };
+
+    let parse = |s: &str| s.parse::<usize>().ok();
+
+    let range = match (start, end) {
+        ("", "") => return ByteRange::Invalid,
+        ("", suffix) => match parse(suffix) {
+            Some(0) => return ByteRange::Unsatisfiable,
+            Some(n) => len.saturating_sub(n)..len,
+            None => return ByteRange::Invalid,
+        },
+        (start, "") => match parse(start) {
+            Some(s) => s..len,
+            None => return ByteRange::Invalid,
+        },
+        (start, end) => match (parse(start), parse(end)) {
+            (Some(s), Some(e)) if s <= e => s..e.saturating_add(1).min(len),
+            _ => return ByteRange::Invalid,
+        },
+    };
+
+    // The end is never past the content, so this also covers starting after it. Empty ranges
+    // can't be given a `Content-Range`.
+    match range.is_empty() {
+        false => ByteRange::Satisfiable(range),
+        true => ByteRange::Unsatisfiable,
+    }
+}
+
 /// Wrapper around the `NamedFile` responder to set an appropriate cache policy
-pub struct StoredImage(NamedFile);
+pub struct StoredImage {
+    file: NamedFile,
+    /// The hash of the file's content, used for its `ETag`
+    hash: String,
+}
 
 impl<'r> Responder<'r> for StoredImage {
     fn respond_to(self, req: &Request) -> response::Result<'r> {
         use http::uncased::Uncased;
 
-        let mut resp = self.0.respond_to(req)?;
+        let mut resp = self.file.respond_to(req)?;
 
         resp.set_header(http::Header {
             name: Uncased::new("Cache-Control"),
             value: Cow::Borrowed(PHOTO_CACHE_POLICY),
         });
+        resp.set_header(http::Header::new("ETag", etag(&self.hash)));
 
         Ok(resp)
     }
 }
 
+/// A watermarked full-size image, as a JPEG
+pub struct WatermarkedImg {
+    data: Arc<[u8]>,
+    /// The `PhotoInfo::full_img_rev` of the image, used for its `ETag`
+    rev: String,
+}
+
+impl<'r> Responder<'r> for WatermarkedImg {
+    fn respond_to(self, req: &Request) -> response::Result<'r> {
+        use http::{uncased::Uncased, ContentType};
+        use rocket::Response;
+
+        let mut builder = Response::build();
+        builder
+            .header(ContentType::JPEG)
+            .header(http::Header {
+                name: Uncased::new("Cache-Control"),
+                value: Cow::Borrowed(PHOTO_CACHE_POLICY),
+            })
+            .header(http::Header::new("ETag", etag(&self.rev)));
+
+        let len = self.data.len();
+        set_ranged_body(&mut builder, req, Cursor::new(self.data), len)?;
+        Ok(builder.finalize())
+    }
+}
+
 /// Wrapper around the different storage (and responder) types
 pub enum ImageSource {
     InMem(InMemImg),
     File(StoredImage),
+    Watermarked(WatermarkedImg),
+    /// The client already has the image with this hash -- produces a `304 Not Modified`
+    NotModified(String),
 }
 
 impl<'r> Responder<'r> for ImageSource {
@@ -1809,6 +5181,495 @@ impl<'r> Responder<'r> for ImageSource {
         match self {
             ImageSource::InMem(img) => img.respond_to(req),
             ImageSource::File(f) => f.respond_to(req),
+            ImageSource::Watermarked(w) => w.respond_to(req),
+            ImageSource::NotModified(hash) => {
+                use http::uncased::Uncased;
+
+                rocket::Response::build()
+                    .status(http::Status::NotModified)
+                    .header(http::Header {
+                        name: Uncased::new("Cache-Control"),
+                        value: Cow::Borrowed(PHOTO_CACHE_POLICY),
+                    })
+                    .header(http::Header::new("ETag", etag(&hash)))
+                    .ok()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_exif_info(taken: DateTime<FixedOffset>) -> PhotoExifInfo {
+        PhotoExifInfo {
+            title: "test".to_owned(),
+            description: None,
+            alt_text: None,
+            coords: None,
+            altitude: None,
+            direction: None,
+            camera: None,
+            actual_datetime: taken,
+            local_time: format_datetime(taken, FormatLevel::LocalTime),
+            tz_offset: format_datetime(taken, FormatLevel::UtcOffset),
+            date: format_datetime(taken, FormatLevel::Date),
+            film: None,
+            notes: None,
+        }
+    }
+
+    fn test_photo(name: &str, taken: &str) -> Arc<PhotoInfo> {
+        let taken = DateTime::parse_from_rfc3339(taken).unwrap();
+        let day_album = AutoDateAlbumBuilder::new(taken.date()).reference();
+        Arc::new(PhotoInfo {
+            file_name: name.to_owned(),
+            media_kind: MediaKind::Photo,
+            path: PathBuf::from(format!("{}.jpg", name)),
+            exif_info: test_exif_info(taken),
+            alt: "test".to_owned(),
+            is_favorite: false,
+            albums: Vec::new(),
+            location: None,
+            day_album,
+            map_zoom_level: None,
+            watermarked: false,
+            smaller_webp: InMemImg {
+                height: 1,
+                width: 1,
+                hash: name.to_owned(),
+                img_data: Arc::from(Vec::new()),
+            },
+            full_img_hash: name.to_owned(),
+            full_img_rev: name.to_owned(),
+            full_size: Some((1, 1)),
+            srcset: Vec::new(),
+        })
+    }
+
+    fn test_state(albums: Vec<(&str, Vec<Arc<PhotoInfo>>)>) -> PhotosState {
+        let albums = albums
+            .into_iter()
+            .map(|(path, photos)| {
+                let album = Album {
+                    name: path.to_owned(),
+                    path: path.to_owned(),
+                    description: String::new(),
+                    notes: None,
+                    kind: None,
+                    cover_img: photos[0].clone(),
+                    photo_count: photos.len(),
+                    date_range: DateRange::of_photos(&photos),
+                    photos,
+                    hidden: false,
+                    flex_grid: FlexGridOverrides::default(),
+                    cover_crop: None,
+                    downloadable: false,
+                };
+                (path.to_owned(), Arc::new(album))
+            })
+            .collect();
+
+        PhotosState {
+            albums,
+            albums_in_order: AlbumsInOrder::default(),
+            images: HashMap::new(),
+            images_by_time: Vec::new(),
+            orphans: Vec::new(),
+            global_map_view: MapView {
+                centered_at: GPSCoords { lat: 0.0, lon: 0.0 },
+                zoom_level: 1,
+            },
+            by_camera: HashMap::new(),
+            by_lens: HashMap::new(),
+            generated_at: Utc::now(),
+        }
+    }
+
+    fn file_names(photos: &[Arc<PhotoInfo>]) -> Vec<&str> {
+        photos.iter().map(|p| p.file_name.as_str()).collect()
+    }
+
+    #[test]
+    fn byte_ranges() {
+        use ByteRange::*;
+
+        assert_eq!(parse_byte_range("bytes=0-99", 1000), Satisfiable(0..100));
+        assert_eq!(parse_byte_range("bytes=900-", 1000), Satisfiable(900..1000));
+        assert_eq!(parse_byte_range("bytes=-100", 1000), Satisfiable(900..1000));
+        assert_eq!(
+            parse_byte_range("bytes=990-2000", 1000),
+            Satisfiable(990..1000)
+        );
+        assert_eq!(parse_byte_range("bytes=1000-", 1000), Unsatisfiable);
+        assert_eq!(parse_byte_range("bytes=-0", 1000), Unsatisfiable);
+        assert_eq!(parse_byte_range("bytes=0-1,5-9", 1000), Invalid);
+        assert_eq!(parse_byte_range("bytes=9-0", 1000), Invalid);
+    }
+
+    #[test]
+    fn byte_range_overflow() {
+        let header = format!("bytes=0-{}", usize::MAX);
+        assert_eq!(
+            parse_byte_range(&header, 1000),
+            ByteRange::Satisfiable(0..1000)
+        );
+        assert_eq!(parse_byte_range(&header, 0), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn byte_range_empty_content() {
+        assert_eq!(parse_byte_range("bytes=-10", 0), ByteRange::Unsatisfiable);
+        assert_eq!(parse_byte_range("bytes=0-", 0), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn jpeg_scale_denoms() {
+        let max = 100;
+        assert_eq!(jpeg_scale_denom(101, max), Some(2));
+        assert_eq!(jpeg_scale_denom(400, max), Some(2));
+        assert_eq!(jpeg_scale_denom(401, max), Some(4));
+        assert_eq!(jpeg_scale_denom(1600, max), Some(4));
+        assert_eq!(jpeg_scale_denom(6400, max), Some(8));
+        assert_eq!(jpeg_scale_denom(6401, max), None);
+        assert_eq!(jpeg_scale_denom(u64::MAX, u64::MAX / 2), Some(2));
+    }
+
+    #[test]
+    fn gps_distance() {
+        let sfo = GPSCoords {
+            lat: 37.6213,
+            lon: -122.3790,
+        };
+        let lax = GPSCoords {
+            lat: 33.9416,
+            lon: -118.4085,
+        };
+
+        assert!((sfo.distance_km(&lax) - 543.7).abs() < 1.0);
+        assert!((sfo.distance_km(&lax) - lax.distance_km(&sfo)).abs() < 1e-9);
+        assert_eq!(sfo.distance_km(&sfo), 0.0);
+
+        // Across the antimeridian, the short way around
+        let east = GPSCoords {
+            lat: 0.0,
+            lon: 179.9,
+        };
+        let west = GPSCoords {
+            lat: 0.0,
+            lon: -179.9,
+        };
+        assert!((east.distance_km(&west) - 22.2).abs() < 0.1);
+    }
+
+    #[test]
+    fn auto_date_paths() {
+        // Reserved for the date albums
+        assert!(is_auto_date_path("2021-08-07"));
+        assert!(is_auto_date_path("2020-02-29"));
+
+        // Anything that isn't exactly how a date album's path is formatted is fine
+        assert!(!is_auto_date_path("2021-8-7"));
+        assert!(!is_auto_date_path("wander-2021-07-06"));
+        assert!(!is_auto_date_path("2021-07-06-wander"));
+        assert!(!is_auto_date_path("2021-13-01"));
+        assert!(!is_auto_date_path("2021-02-29"));
+    }
+
+    #[test]
+    fn date_range_uses_local_dates() {
+        // The second photo was taken earlier in UTC, but later by its local date
+        let photos = [
+            test_photo("a", "2023-03-03T23:00:00-08:00"),
+            test_photo("b", "2023-03-04T01:00:00+09:00"),
+        ];
+
+        let range = DateRange::of_photos(&photos).unwrap();
+        assert_eq!(range.earliest, "2023-03-03T23:00:00-08:00");
+        assert_eq!(range.latest, "2023-03-04T01:00:00+09:00");
+        assert_eq!(range.display, "Mar 3–4, 2023");
+    }
+
+    #[test]
+    fn display_offset_moves_date() {
+        // 23:30 in San Francisco is already the next day in UTC
+        let taken = DateTime::parse_from_rfc3339("2021-12-18T23:30:00-08:00").unwrap();
+        let mut info = test_exif_info(taken);
+
+        info.display_in_timezone(Tz::UTC);
+        assert_eq!(info.date, "Dec 19, 2021");
+        assert_eq!(info.actual_datetime, taken);
+        // The date album (and "on this day") use the same date as what's displayed
+        let date = info.actual_datetime.date();
+        assert_eq!(AutoDateAlbumBuilder::new(date).path, "2021-12-19");
+        assert_eq!((date.month(), date.day()), (12, 19));
+    }
+
+    #[test]
+    fn display_timezone_follows_dst() {
+        let display = |taken: &str| {
+            let taken = DateTime::parse_from_rfc3339(taken).unwrap();
+            let mut info = test_exif_info(taken);
+            info.display_in_timezone(Tz::America__Los_Angeles);
+            assert_eq!(info.actual_datetime, taken);
+            (info.local_time, info.tz_offset)
+        };
+
+        let summer = display("2021-07-04T12:00:00Z");
+        assert_eq!(summer, ("05:00:00".to_owned(), "UTC-07:00".to_owned()));
+        let winter = display("2021-12-18T12:00:00Z");
+        assert_eq!(winter, ("04:00:00".to_owned(), "UTC-08:00".to_owned()));
+    }
+
+    #[test]
+    fn featured_photos_without_album() {
+        // The "all photos" album is sorted most recent first
+        let all: Vec<_> = (0..NUM_FALLBACK_PHOTOS + 5)
+            .map(|i| test_photo(&format!("img-{}", i), "2021-12-18T12:00:00Z"))
+            .collect();
+        let state = test_state(vec![("all", all.clone()), ("trip", vec![all[3].clone()])]);
+
+        let featured = state.featured_photos("trip", 5);
+        assert!(!featured.is_fallback);
+        assert_eq!(featured.album_path, "trip");
+        assert_eq!(file_names(&featured.photos), ["img-3"]);
+
+        let featured = state.featured_photos("favorites", 2);
+        assert!(featured.is_fallback);
+        assert_eq!(featured.album_path, "all");
+        assert_eq!(file_names(&featured.photos), ["img-0", "img-1"]);
+
+        // Without a limit, it's still capped to the number of fallback photos
+        let featured = state.featured_photos("favorites", usize::MAX);
+        assert_eq!(
+            file_names(&featured.photos),
+            file_names(&all[..NUM_FALLBACK_PHOTOS])
+        );
+
+        // With no photos at all, there's nothing to fall back to
+        let featured = test_state(Vec::new()).featured_photos("favorites", usize::MAX);
+        assert!(featured.is_fallback);
+        assert!(featured.photos.is_empty());
+    }
+
+    #[test]
+    fn index_context_without_favorites() {
+        let all = vec![
+            test_photo("newer", "2021-12-19T12:00:00Z"),
+            test_photo("older", "2021-12-18T12:00:00Z"),
+        ];
+        let state = test_state(vec![("all", all)]);
+
+        // The default config has "favorites", which doesn't exist here
+        let ctx = state.index_context();
+        assert!(ctx.favorites.is_fallback);
+        assert_eq!(ctx.favorites.album_path, ctx.all_album_path);
+        assert_eq!(file_names(&ctx.favorites.photos), ["newer", "older"]);
+    }
+
+    #[test]
+    fn exposure_values() {
+        assert_eq!(exposure_value(8.0, 1.0 / 30.0, 200).as_deref(), Some("9.9"));
+        assert_eq!(exposure_value(1.0, 1.0, 100).as_deref(), Some("0.0"));
+        assert_eq!(exposure_value(2.0, 4.0, 100).as_deref(), Some("0.0"));
+
+        assert_eq!(exposure_value(0.0, 1.0 / 30.0, 200), None);
+        assert_eq!(exposure_value(8.0, 0.0, 200), None);
+        assert_eq!(exposure_value(8.0, 1.0 / 30.0, 0), None);
+
+        assert_eq!(exposure_value(-8.0, 1.0 / 30.0, 200), None);
+        assert_eq!(exposure_value(8.0, -1.0, 200), None);
+        assert_eq!(exposure_value(f64::NAN, 1.0 / 30.0, 200), None);
+        assert_eq!(exposure_value(8.0, f64::INFINITY, 200), None);
+    }
+
+    #[test]
+    fn overrides_text() {
+        let taken = DateTime::parse_from_rfc3339("2021-12-18T12:00:00Z").unwrap();
+        let location = AlbumReference {
+            path: "sf".to_owned(),
+            name: "San Francisco".to_owned(),
+        };
+
+        // Fields without an override are left as they were
+        let mut info = test_exif_info(taken);
+        info.alt_text = Some("Embedded alt text".to_owned());
+        let overrides: PhotoOverrides =
+            serde_json::from_str(r#"{ "title": "New title" }"#).unwrap();
+        overrides.apply(&mut info).unwrap();
+        assert_eq!(info.title, "New title");
+        assert_eq!(info.description, None);
+        assert_eq!(info.alt_text.as_deref(), Some("Embedded alt text"));
+
+        let mut info = test_exif_info(taken);
+        let overrides: PhotoOverrides = serde_json::from_str(
+            r#"{ "description": "Some *emphasis*", "alt": "A foggy bridge" }"#,
+        )
+        .unwrap();
+        overrides.apply(&mut info).unwrap();
+        assert_eq!(info.title, "test");
+        let description = info.description.as_deref().unwrap();
+        assert!(description.contains("<em>emphasis</em>"), "{}", description);
+        assert_eq!(
+            info.description,
+            Some(markdown_to_html("Some *emphasis*", MarkdownKind::Snippet))
+        );
+        // The explicit alt text takes precedence over the title and location
+        assert_eq!(info.alt_text.as_deref(), Some("A foggy bridge"));
+        assert_eq!(
+            PhotosState::alt_text(&info, Some(&location)),
+            "A foggy bridge"
+        );
+
+        info.alt_text = None;
+        assert_eq!(
+            PhotosState::alt_text(&info, Some(&location)),
+            "test, in San Francisco"
+        );
+    }
+
+    #[test]
+    fn overrides_without_camera() {
+        let taken = DateTime::parse_from_rfc3339("2021-12-18T12:00:00Z").unwrap();
+        let overrides = |json: &str| serde_json::from_str::<PhotoOverrides>(json).unwrap();
+
+        let mut info = test_exif_info(taken);
+        overrides(r#"{ "camera": ["Nikon", "FM2"], "lens": ["Nikon", "50mm f/1.8"] }"#)
+            .apply(&mut info)
+            .unwrap();
+        let camera = info.camera.unwrap();
+        assert_eq!(camera.id, ("Nikon".to_owned(), "FM2".to_owned()));
+        assert_eq!(camera.camera_slug, "nikon-fm2");
+        assert_eq!(camera.lens_slug.as_deref(), Some("nikon-50mm-f-1-8"));
+        assert_eq!(camera.iso, None);
+
+        let mut info = test_exif_info(taken);
+        let result = overrides(r#"{ "lens": ["Nikon", "50mm f/1.8"] }"#).apply(&mut info);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn overrides_clear_exposure() {
+        let taken = DateTime::parse_from_rfc3339("2021-12-18T12:00:00Z").unwrap();
+        let mut scanner = CameraInfo::without_settings(("Epson".to_owned(), "V600".to_owned()));
+        scanner.iso = Some(100);
+        scanner.f_stop = Some(8.0);
+        scanner.focal_length = Some(35.0);
+        scanner.exposure_time = Some("1/30".to_owned());
+        scanner.exposure_value = Some("11.0".to_owned());
+
+        let mut info = test_exif_info(taken);
+        info.camera = Some(scanner);
+        let overrides: PhotoOverrides =
+            serde_json::from_str(r#"{ "camera": ["Nikon", "FM2"], "clear_exposure": true }"#)
+                .unwrap();
+        overrides.apply(&mut info).unwrap();
+
+        let camera = info.camera.unwrap();
+        assert_eq!(camera.id, ("Nikon".to_owned(), "FM2".to_owned()));
+        assert_eq!(camera.iso, None);
+        assert_eq!(camera.f_stop, None);
+        assert_eq!(camera.focal_length, None);
+        assert_eq!(camera.exposure_time, None);
+        assert_eq!(camera.exposure_value, None);
+    }
+
+    #[test]
+    fn utf16_byte_order() {
+        let le = |s: &str| s.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let be = |s: &str| s.encode_utf16().flat_map(u16::to_be_bytes).collect();
+        let decode = |bom: &[u8], rest: Vec<u8>| decode_utf16(&[bom, &rest].concat()).unwrap();
+
+        // Byte order marks take precedence
+        assert_eq!(decode(&[0xFF, 0xFE], le("Hi")), "Hi");
+        assert_eq!(decode(&[0xFE, 0xFF], be("Hi")), "Hi");
+        // 'Ā' and 'Ȁ' are 0x0100 and 0x0200, which would look big-endian without the BOM
+        assert_eq!(decode(&[0xFF, 0xFE], le("ĀȀ")), "ĀȀ");
+
+        // Otherwise, whichever side has more null bytes is the high byte
+        assert_eq!(decode(&[], le("Hello")), "Hello");
+        assert_eq!(decode(&[], be("Hello")), "Hello");
+        // Ties are little-endian
+        assert_eq!(decode(&[], le("日本")), "日本");
+        assert_eq!(decode(&[], Vec::new()), "");
+    }
+
+    #[test]
+    fn utf16_odd_length() {
+        assert!(decode_utf16(&[b'H', 0, b'i']).is_err());
+        assert!(decode_utf16(&[0xFF, 0xFE, b'H']).is_err());
+    }
+
+    /// Parses EXIF data containing only the given fields -- plus `Software`, because it can't be
+    /// completely empty
+    fn test_exif(fields: &[(exif::Tag, exif::Value)]) -> exif::Exif {
+        let software = (
+            exif::Tag::Software,
+            exif::Value::Ascii(vec![b"test".to_vec()]),
+        );
+        let fields: Vec<_> = (fields.iter().cloned())
+            .chain([software])
+            .map(|(tag, value)| exif::Field {
+                tag,
+                ifd_num: exif::In::PRIMARY,
+                value,
+            })
+            .collect();
+
+        let mut writer = exif::experimental::Writer::new();
+        fields.iter().for_each(|f| writer.push_field(f));
+        let mut data = Cursor::new(Vec::new());
+        writer.write(&mut data, false).unwrap();
+        exif::Reader::new().read_raw(data.into_inner()).unwrap()
+    }
+
+    fn rational(num: u32, denom: u32) -> exif::Value {
+        exif::Value::Rational(vec![exif::Rational { num, denom }])
+    }
+
+    #[test]
+    fn gps_altitude() {
+        use exif::{Tag, Value};
+
+        let below = Value::Byte(vec![1]);
+        let exif = test_exif(&[(Tag::GPSAltitude, rational(123, 2))]);
+        assert_eq!(PhotoExifInfo::get_gps_altitude(&exif), Some(62));
+        let exif = test_exif(&[
+            (Tag::GPSAltitude, rational(5, 1)),
+            (Tag::GPSAltitudeRef, below),
+        ]);
+        assert_eq!(PhotoExifInfo::get_gps_altitude(&exif), Some(-5));
+        assert_eq!(PhotoExifInfo::get_gps_altitude(&test_exif(&[])), None);
+
+        // Malformed tags are ignored, instead of failing the photo
+        let malformed = [
+            vec![(Tag::GPSAltitude, rational(0, 0))],
+            vec![(Tag::GPSAltitudeRef, Value::Byte(vec![0]))],
+            vec![
+                (Tag::GPSAltitude, rational(5, 1)),
+                (Tag::GPSAltitudeRef, Value::Byte(vec![2])),
+            ],
+        ];
+        for fields in malformed {
+            let exif = test_exif(&fields);
+            assert!(PhotoExifInfo::parse_gps_altitude(&exif).is_err());
+            assert_eq!(PhotoExifInfo::get_gps_altitude(&exif), None);
         }
     }
+
+    #[test]
+    fn gps_direction() {
+        use exif::Tag;
+
+        let direction =
+            |value| PhotoExifInfo::get_gps_direction(&test_exif(&[(Tag::GPSImgDirection, value)]));
+        assert_eq!(direction(rational(905, 10)), Some(91));
+        // Rounding up to 360 wraps around to north
+        assert_eq!(direction(rational(3597, 10)), Some(0));
+        assert_eq!(PhotoExifInfo::get_gps_direction(&test_exif(&[])), None);
+
+        assert_eq!(direction(rational(0, 0)), None);
+        assert_eq!(direction(rational(400, 1)), None);
+    }
 }
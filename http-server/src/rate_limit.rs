@@ -0,0 +1,255 @@
+//! Wrapper module for the [`RateLimit`] fairing
+//!
+//! A few routes are much more expensive than the rest -- serving full-size images, or generating
+//! ZIPs or preview images -- so we limit how often each client can request them. The limits are
+//! tracked per IP address. Unlike the address that we log (see `util::client_ip`), clients can't
+//! pick it themselves: `X-Forwarded-For` is only used if the request came from one of the
+//! `trusted_proxies`, and then only the address that the proxy appended.
+//!
+//! Rocket 0.4 doesn't allow fairings to respond to a request directly, so a request over the limit
+//! is instead rewritten to `RATE_LIMITED_PATH`, which only ever responds with a 429 (Too Many
+//! Requests). That way, the expensive route is never run.
+
+use anyhow::{Context, Result};
+use glob::Pattern;
+use log::warn;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::{Method, Status};
+use rocket::request::FormItems;
+use rocket::{get, Data, Request};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::RateLimitConfig;
+
+/// Path that requests over the limit are rewritten to
+static RATE_LIMITED_PATH: &str = "/rate-limited";
+
+/// Minimum time between removing the buckets for clients that haven't made a limited request in a
+/// while
+const PRUNE_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Fairing that enforces the per-IP limits on the routes given by `RateLimitConfig`
+///
+/// The `rate_limited` route must be mounted at the site root for this to work.
+pub struct RateLimit {
+    routes: Vec<LimitedRoute>,
+    /// Maximum number of tokens in each bucket
+    burst: f64,
+    /// Number of tokens added to each bucket per second
+    refill_per_sec: f64,
+    trusted_proxies: Vec<IpAddr>,
+    state: Mutex<LimiterState>,
+}
+
+struct LimitedRoute {
+    path: Pattern,
+    query: Option<String>,
+}
+
+struct LimiterState {
+    /// Client IP -> their bucket
+    buckets: HashMap<IpAddr, Bucket>,
+    last_prune: Instant,
+}
+
+struct Bucket {
+    /// Number of requests that the client can currently make; may be fractional
+    tokens: f64,
+    /// When `tokens` was last updated
+    updated: Instant,
+}
+
+impl RateLimit {
+    pub fn new(config: &RateLimitConfig) -> Result<Self> {
+        let routes = config
+            .routes
+            .iter()
+            .map(|r| {
+                let path = Pattern::new(&r.path)
+                    .with_context(|| format!("bad rate limited path pattern {:?}", r.path))?;
+                Ok(LimitedRoute {
+                    path,
+                    query: r.query.clone(),
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(RateLimit {
+            routes: if config.enabled { routes } else { Vec::new() },
+            burst: config.burst as f64,
+            refill_per_sec: config.per_minute as f64 / 60.0,
+            trusted_proxies: config.trusted_proxies.clone(),
+            state: Mutex::new(LimiterState {
+                buckets: HashMap::new(),
+                last_prune: Instant::now(),
+            }),
+        })
+    }
+
+    fn is_limited(&self, request: &Request) -> bool {
+        let uri = request.uri();
+
+        self.routes.iter().any(|r| {
+            r.path.matches(uri.path())
+                && match &r.query {
+                    Some(q) => has_query_param(uri.query(), q),
+                    None => true,
+                }
+        })
+    }
+
+    /// Returns the address of the client to limit the request by, if we can find it
+    fn client_addr(&self, request: &Request) -> Option<IpAddr> {
+        let peer = request.remote()?.ip();
+        // The header can be repeated; the proxy appends to the last one
+        let forwarded_for = request.headers().get("X-Forwarded-For").last();
+        Some(limited_addr(peer, forwarded_for, &self.trusted_proxies))
+    }
+
+    /// Takes a token from the client's bucket, returning false if there weren't any left
+    fn try_take(&self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+
+        if now.duration_since(state.last_prune) >= PRUNE_INTERVAL {
+            // Any bucket that would be full by now is the same as not having one at all
+            let (burst, refill_per_sec) = (self.burst, self.refill_per_sec);
+            state
+                .buckets
+                .retain(|_, b| b.refilled(now, burst, refill_per_sec) < burst);
+            state.last_prune = now;
+        }
+
+        let bucket = state.buckets.entry(ip).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+
+        bucket.tokens = bucket.refilled(now, self.burst, self.refill_per_sec);
+        bucket.updated = now;
+
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+
+        bucket.tokens -= 1.0;
+        true
+    }
+}
+
+impl Bucket {
+    /// Returns the number of tokens the bucket would have at `now`
+    fn refilled(&self, now: Instant, burst: f64, refill_per_sec: f64) -> f64 {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * refill_per_sec).min(burst)
+    }
+}
+
+impl Fairing for RateLimit {
+    fn info(&self) -> Info {
+        Info {
+            name: "Rate Limit",
+            kind: Kind::Request,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        if !self.is_limited(request) {
+            return;
+        }
+
+        // Without an address, there's nothing to limit by
+        let ip = match self.client_addr(request) {
+            Some(ip) => ip,
+            None => return,
+        };
+
+        if self.try_take(ip) {
+            return;
+        }
+
+        // The rewritten request won't have the original URI, so this is the only place it's logged
+        warn!("rate limited request for {} (by {})", request.uri(), ip);
+        request.set_method(Method::Get);
+        request.set_uri(Origin::parse(RATE_LIMITED_PATH).unwrap());
+    }
+}
+
+/// Returns the address to limit a request from `peer` by, given the last `X-Forwarded-For` header
+///
+/// Everything before the right-most entry of the header could've been sent by the client, so that
+/// entry -- which our proxy appended -- is the only one we can trust. If the request didn't come
+/// from a trusted proxy, even that could be made up.
+fn limited_addr(peer: IpAddr, forwarded_for: Option<&str>, trusted_proxies: &[IpAddr]) -> IpAddr {
+    if !trusted_proxies.contains(&peer) {
+        return peer;
+    }
+
+    forwarded_for
+        .and_then(|h| h.rsplit(',').next())
+        .and_then(|hop| hop.trim().parse().ok())
+        .unwrap_or(peer)
+}
+
+/// Target of every request that's over the limit; see the module-level docs
+#[get("/rate-limited")]
+pub fn rate_limited() -> Status {
+    Status::TooManyRequests
+}
+
+/// Returns whether the query string has the parameter `param`, given as "key=value"
+///
+/// Both sides are compared after percent-decoding, the same way that the routes see them --
+/// otherwise, encoding any character of the parameter would get around the limit.
+fn has_query_param(query: Option<&str>, param: &str) -> bool {
+    let (key, value) = param.split_once('=').unwrap_or((param, ""));
+
+    query.is_some_and(|q| {
+        FormItems::from(q).any(|item| {
+            let (k, v) = item.key_value_decoded();
+            k == key && v == value
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limited_addrs() {
+        let proxy: IpAddr = "127.0.0.1".parse().unwrap();
+        let client: IpAddr = "203.0.113.7".parse().unwrap();
+        let trusted = [proxy];
+
+        // Only the right-most hop is used, whatever the client put before it
+        let spoofed = Some("198.51.100.1, 203.0.113.7");
+        assert_eq!(limited_addr(proxy, spoofed, &trusted), client);
+        assert_eq!(limited_addr(proxy, Some("203.0.113.7"), &trusted), client);
+
+        // Untrusted peers are limited by their own address
+        assert_eq!(limited_addr(client, Some("198.51.100.1"), &trusted), client);
+
+        // Without a usable header, the proxy's address is all we have
+        assert_eq!(limited_addr(proxy, None, &trusted), proxy);
+        assert_eq!(limited_addr(proxy, Some("unknown"), &trusted), proxy);
+    }
+
+    #[test]
+    fn query_params() {
+        assert!(has_query_param(Some("size=full"), "size=full"));
+        assert!(has_query_param(Some("rev=abc&size=full"), "size=full"));
+        assert!(!has_query_param(Some("size=small"), "size=full"));
+        assert!(!has_query_param(Some("size=fuller"), "size=full"));
+        assert!(!has_query_param(None, "size=full"));
+
+        // Percent-encoded characters are the same as the characters themselves
+        assert!(has_query_param(Some("size=ful%6C"), "size=full"));
+        assert!(has_query_param(Some("size=%66ull"), "size=full"));
+        assert!(has_query_param(Some("%73ize=full&rev=abc"), "size=full"));
+    }
+}
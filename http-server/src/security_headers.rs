@@ -0,0 +1,58 @@
+//! Wrapper module for the [`SecurityHeaders`] fairing
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::{Request, Response};
+
+use crate::config::SecurityHeadersConfig;
+
+/// Response fairing that sets the security-related headers
+///
+/// `X-Content-Type-Options` is set on every response, because it's cheap and applies to anything.
+/// The rest only matter for pages, so they're only set on HTML responses -- which leaves out images
+/// and the other static assets.
+pub struct SecurityHeaders {
+    csp: Option<String>,
+    referrer_policy: String,
+    hsts: String,
+}
+
+impl SecurityHeaders {
+    pub fn new(config: &SecurityHeadersConfig) -> Self {
+        let csp = &config.content_security_policy;
+        SecurityHeaders {
+            csp: (!csp.is_empty()).then(|| csp.clone()),
+            referrer_policy: config.referrer_policy.clone(),
+            hsts: format!("max-age={}", config.hsts_max_age_secs),
+        }
+    }
+}
+
+impl Fairing for SecurityHeaders {
+    fn info(&self) -> Info {
+        Info {
+            name: "Security Headers",
+            kind: Kind::Response,
+        }
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        response.set_header(Header::new("X-Content-Type-Options", "nosniff"));
+
+        if !response.content_type().map_or(false, |ct| ct.is_html()) {
+            return;
+        }
+
+        if let Some(csp) = &self.csp {
+            response.set_header(Header::new("Content-Security-Policy", csp.clone()));
+        }
+        response.set_header(Header::new("Referrer-Policy", self.referrer_policy.clone()));
+
+        // We're always behind a reverse proxy that handles TLS, so this is the only way to know
+        // whether the request was over HTTPS. Browsers ignore the header over plain HTTP anyways,
+        // but there's no reason to send it.
+        if request.headers().get_one("X-Forwarded-Proto") == Some("https") {
+            response.set_header(Header::new("Strict-Transport-Security", self.hsts.clone()));
+        }
+    }
+}
@@ -0,0 +1,197 @@
+//! Graceful shutdown on SIGTERM or SIGINT, and the [`TrackRequests`] fairing that makes it possible
+//!
+//! Rocket 0.4 has no way to stop the server once it's launched, so we can't stop accepting
+//! connections. Instead, once a signal is received, we wait for the requests that are in progress
+//! to finish -- including sending their response bodies -- and then exit the process. Responses
+//! sent while shutting down have `Connection: close`, so that clients (or the proxy in front of us)
+//! don't keep reusing the connection. A second signal exits immediately.
+//!
+//! Signals are blocked in every thread and received with `sigwait(3)` on a dedicated thread, so
+//! [`install`] must be called before any other threads are spawned; threads inherit the signal mask
+//! of the thread that created them. This is all POSIX, which is fine given that we only support
+//! Linux.
+
+use log::{error, info, warn};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::{Data, Request, Response};
+use std::io::{self, Read};
+use std::mem::MaybeUninit;
+use std::process::exit;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Maximum amount of time to wait for in-progress requests to finish before exiting anyways
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often to check whether all of the in-progress requests have finished
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Set once we've received a signal to shut down
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+/// Number of requests that have started, but haven't finished sending their response
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns whether we're in the process of shutting down
+pub fn is_shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::SeqCst)
+}
+
+/// Blocks SIGTERM and SIGINT, and spawns the thread that waits for them to begin the shutdown
+///
+/// `on_shutdown` is called once the first signal is received, before waiting for the in-progress
+/// requests.
+pub fn install(on_shutdown: impl FnOnce() + Send + 'static) {
+    let set = shutdown_signals();
+
+    // SAFETY: `set` was initialized by `shutdown_signals`, and we're only changing the signal mask
+    // of the current thread.
+    let err = unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut()) };
+    if err != 0 {
+        error!(
+            "failed to block shutdown signals: {}",
+            io::Error::from_raw_os_error(err)
+        );
+        exit(1);
+    }
+
+    thread::spawn(move || wait_for_signals(set, on_shutdown));
+}
+
+/// Returns the set of signals that trigger a shutdown
+fn shutdown_signals() -> libc::sigset_t {
+    let mut set = MaybeUninit::uninit();
+
+    // SAFETY: `sigemptyset` initializes the set, and `sigaddset` can only fail with an invalid
+    // signal number.
+    unsafe {
+        libc::sigemptyset(set.as_mut_ptr());
+        libc::sigaddset(set.as_mut_ptr(), libc::SIGTERM);
+        libc::sigaddset(set.as_mut_ptr(), libc::SIGINT);
+        set.assume_init()
+    }
+}
+
+fn wait_for_signals(set: libc::sigset_t, on_shutdown: impl FnOnce() + Send + 'static) {
+    let mut on_shutdown = Some(on_shutdown);
+
+    loop {
+        let mut signal = 0;
+        // SAFETY: `set` is initialized, and the signals in it are blocked in every thread
+        let err = unsafe { libc::sigwait(&set, &mut signal) };
+        if err != 0 {
+            error!(
+                "failed to wait for signals: {}",
+                io::Error::from_raw_os_error(err)
+            );
+            return;
+        }
+
+        let name = match signal {
+            libc::SIGTERM => "SIGTERM",
+            libc::SIGINT => "SIGINT",
+            _ => "unknown signal",
+        };
+
+        match on_shutdown.take() {
+            Some(f) => {
+                info!("received {}, shutting down", name);
+                SHUTTING_DOWN.store(true, Ordering::SeqCst);
+                f();
+                thread::spawn(finish_in_flight_requests);
+            }
+            None => {
+                warn!("received {} while shutting down, exiting immediately", name);
+                exit(1);
+            }
+        }
+    }
+}
+
+/// Waits for all of the in-progress requests to finish (up to `SHUTDOWN_TIMEOUT`), then exits
+fn finish_in_flight_requests() {
+    let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+
+    loop {
+        let in_flight = IN_FLIGHT.load(Ordering::SeqCst);
+        if in_flight == 0 {
+            info!("all requests finished; exiting");
+            exit(0);
+        } else if Instant::now() >= deadline {
+            warn!(
+                "timed out with {} request(s) still in progress; exiting",
+                in_flight
+            );
+            exit(0);
+        }
+
+        thread::sleep(SHUTDOWN_POLL_INTERVAL);
+    }
+}
+
+/// Request & response fairing that counts the number of requests in progress, so that shutting
+/// down can wait for them
+///
+/// A request is in progress from when it's received until its response body is dropped, which
+/// happens once the body has been fully written (or the connection is closed). Because of that,
+/// this must be attached *after* any other fairings that replace the response body.
+pub struct TrackRequests;
+
+/// Marker stored in each request's local cache to record whether it was counted by `on_request`
+struct Counted(bool);
+
+/// Decrements the count of in-progress requests when dropped
+struct InFlightGuard;
+
+/// Wrapper around a response body, so that the request is counted until the body is dropped
+struct TrackedBody<R> {
+    inner: R,
+    _guard: InFlightGuard,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl<R: Read> Read for TrackedBody<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Fairing for TrackRequests {
+    fn info(&self) -> Info {
+        Info {
+            name: "Track in-flight requests",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+        request.local_cache(|| Counted(true));
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        if is_shutting_down() {
+            response.set_header(Header::new("Connection", "close"));
+        }
+
+        // If `on_request` didn't run for some reason, there's nothing to decrement.
+        if !request.local_cache(|| Counted(false)).0 {
+            return;
+        }
+
+        let guard = InFlightGuard;
+        if let Some(body) = response.take_body() {
+            let tracked = body.map(|inner| TrackedBody {
+                inner,
+                _guard: guard,
+            });
+            response.set_raw_body(tracked);
+        }
+        // Otherwise, `guard` is dropped here, because there's nothing left to send.
+    }
+}
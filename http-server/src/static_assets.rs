@@ -0,0 +1,280 @@
+//! Content-hashed names for the static assets, so that they can be cached indefinitely
+//!
+//! Every file in the static directory is hashed, and gets an alternate name with (part of) the hash
+//! inserted before its extension -- e.g. `style/photos.css` can also be requested as
+//! `style/photos.<hash>.css`. Templates refer to the hashed names with the `static_url` function,
+//! so the URL changes whenever the content does.
+//!
+//! The unhashed names still work, but are only cached for a short time (see
+//! `crate::cache_control`). So do hashed names from before the file last changed, with the same
+//! short cache lifetime.
+
+use anyhow::{bail, Context, Result};
+use arc_swap::ArcSwap;
+use lazy_static::lazy_static;
+use log::error;
+use rocket::http::{Header, Status};
+use rocket::request::Request;
+use rocket::response::{self, NamedFile, Responder, Response};
+use rocket_contrib::templates::tera::{self, Tera, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::process::exit;
+use std::sync::Arc;
+
+use crate::cache_control::IMMUTABLE_POLICY;
+use crate::util::{content_hash, etag, http_date};
+
+/// Number of characters of the content hash to include in the hashed names
+const HASH_LEN: usize = 12;
+
+lazy_static! {
+    /// Directory containing the static assets, set by `initialize`
+    static ref STATIC_DIR: ArcSwap<PathBuf> = ArcSwap::from(Arc::new(PathBuf::new()));
+
+    /// Hashed names of all the static assets
+    static ref STATE: ArcSwap<AssetHashes> = match AssetHashes::new(&STATIC_DIR.load()) {
+        Ok(s) => ArcSwap::new(Arc::new(s)),
+        Err(e) => {
+            error!("failed to hash static assets: {:#}", e);
+            exit(1)
+        }
+    };
+}
+
+/// The mapping between static assets and their hashed names
+///
+/// All paths are relative to the static directory, using `/` as the separator.
+struct AssetHashes {
+    /// Map of original path -> hashed path
+    hashed: HashMap<String, String>,
+    /// Map of hashed path -> original path
+    originals: HashMap<String, String>,
+    /// Map of original path -> full content hash, for the `ETag`
+    content_hashes: HashMap<String, String>,
+}
+
+/// Sets the static directory and hashes everything in it, causing any failures to happen
+/// immediately
+///
+/// Any failures encountered will result in an immediate exit.
+pub fn initialize(static_dir: &Path) {
+    STATIC_DIR.store(Arc::new(static_dir.to_owned()));
+    lazy_static::initialize(&STATE);
+}
+
+/// Re-hashes the static assets to incorporate any recent file changes
+pub fn update() -> Result<()> {
+    let new_state = AssetHashes::new(&STATIC_DIR.load())?;
+    STATE.store(Arc::new(new_state));
+    Ok(())
+}
+
+/// Adds the `static_url` function to the templates, which gives the URL of the hashed version of a
+/// static asset
+///
+/// It's called like `static_url(path="style/photos.css")`. If the asset doesn't exist, the plain
+/// URL is returned instead (and the error is logged) so that a typo doesn't break the entire page.
+pub fn register_template_functions(tera: &mut Tera) {
+    tera.register_function("static_url", |args: &HashMap<String, Value>| {
+        let path = match args.get("path") {
+            Some(Value::String(p)) => p.trim_start_matches('/'),
+            _ => {
+                return Err(tera::Error::msg(
+                    "`static_url` requires a string `path` argument",
+                ))
+            }
+        };
+
+        let state = STATE.load();
+        let url = match state.hashed.get(path) {
+            Some(hashed) => format!("/{}", hashed),
+            None => {
+                error!("no static asset at {:?}", path);
+                format!("/{}", path)
+            }
+        };
+
+        Ok(Value::String(url))
+    });
+}
+
+/// Checks that `path` refers to an existing static asset, for references to them from outside the
+/// templates (e.g. from blog posts)
+///
+/// For the same reasons as in `static_asset`, the path must be relative and can't have any `..`
+/// components, or anything else that could lead outside of the static directory.
+pub fn validate_path(path: &str) -> Result<()> {
+    let within_dir = Path::new(path)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)));
+    if path.is_empty() || !within_dir {
+        bail!(
+            "bad static asset path {:?}: must be relative, without '.' or '..'",
+            path
+        );
+    }
+
+    if !STATE.load().hashed.contains_key(path) {
+        bail!("no static asset at {:?}", path);
+    }
+
+    Ok(())
+}
+
+/// Returns the original path of the static asset if `path` is a hashed name, and whether the hash
+/// is the current one
+///
+/// Pages rendered before the last update (or cached elsewhere) can still refer to the previous
+/// hashed names, so those are served with the current content -- just without the immutable cache
+/// policy, because the content doesn't match the hash.
+pub fn original_path(path: &Path) -> Option<(PathBuf, bool)> {
+    let path = path.to_str()?;
+    let state = STATE.load();
+
+    if let Some(original) = state.originals.get(path) {
+        return Some((PathBuf::from(original), true));
+    } else if state.hashed.contains_key(path) {
+        // An actual file, which just happens to look like a hashed name
+        return None;
+    }
+
+    let (dir, name) = match path.rsplit_once('/') {
+        Some((dir, name)) => (&path[..dir.len() + 1], name),
+        None => ("", path),
+    };
+
+    let is_hash = |s: &str| {
+        s.len() == HASH_LEN
+            && s.bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    };
+
+    // Either "<stem>.<hash>.<ext>" or "<name>.<hash>", matching `add_dir`
+    let (rest, last) = name.rsplit_once('.')?;
+    let with_ext = rest
+        .rsplit_once('.')
+        .filter(|(_, hash)| is_hash(hash))
+        .map(|(stem, _)| format!("{}{}.{}", dir, stem, last));
+    let without_ext = Some(format!("{}{}", dir, rest)).filter(|_| is_hash(last));
+
+    with_ext
+        .into_iter()
+        .chain(without_ext)
+        .find(|original| state.hashed.contains_key(original))
+        .map(|original| (PathBuf::from(original), false))
+}
+
+/// Returns the content hash of the static asset at `path` (by its original name), as of the last
+/// update
+pub fn asset_hash(path: &Path) -> Option<String> {
+    let state = STATE.load();
+    state.content_hashes.get(path.to_str()?).cloned()
+}
+
+/// Returns whether `rev` is the current content hash of the static asset at `path` -- either in
+/// full, or as it appears in the hashed name
+pub fn is_current_rev(path: &Path, rev: &str) -> bool {
+    match asset_hash(path) {
+        Some(hash) => rev == hash || rev == &hash[..HASH_LEN],
+        None => false,
+    }
+}
+
+impl AssetHashes {
+    fn new(static_dir: &Path) -> Result<Self> {
+        let mut hashes = AssetHashes {
+            hashed: HashMap::new(),
+            originals: HashMap::new(),
+            content_hashes: HashMap::new(),
+        };
+
+        hashes.add_dir(static_dir, "")?;
+        Ok(hashes)
+    }
+
+    /// Adds every file in `dir` (recursively), where `prefix` is the path of `dir` relative to the
+    /// static directory
+    fn add_dir(&mut self, dir: &Path, prefix: &str) -> Result<()> {
+        let entries =
+            fs::read_dir(dir).with_context(|| format!("failed to read directory {:?}", dir))?;
+
+        for entry in entries {
+            let entry = entry.with_context(|| format!("failed to read entry in {:?}", dir))?;
+            let path = entry.path();
+
+            let name = match entry.file_name().into_string() {
+                Ok(n) => n,
+                Err(n) => {
+                    error!("skipping static asset with non-UTF-8 name {:?}", n);
+                    continue;
+                }
+            };
+
+            let file_type = entry
+                .file_type()
+                .with_context(|| format!("failed to get file type of {:?}", path))?;
+
+            if file_type.is_dir() {
+                self.add_dir(&path, &format!("{}{}/", prefix, name))?;
+                continue;
+            }
+
+            let content = fs::read(&path).with_context(|| format!("failed to read {:?}", path))?;
+            let full_hash = content_hash(&content);
+            let hash = &full_hash[..HASH_LEN];
+
+            // "photos.css" -> "photos.<hash>.css"; anything without an extension just gets the hash
+            // added on the end
+            let hashed_name = match name.rsplit_once('.') {
+                Some((stem, ext)) if !stem.is_empty() => format!("{}.{}.{}", stem, hash, ext),
+                _ => format!("{}.{}", name, hash),
+            };
+
+            let original = format!("{}{}", prefix, name);
+            let hashed = format!("{}{}", prefix, hashed_name);
+
+            self.originals.insert(hashed.clone(), original.clone());
+            self.hashed.insert(original.clone(), hashed);
+            self.content_hashes.insert(original, full_hash);
+        }
+
+        Ok(())
+    }
+}
+
+/// A static asset file, possibly requested by its hashed name
+pub struct StaticFile {
+    /// The file to send, or `None` if the client already has the current version -- which
+    /// produces a `304 Not Modified`
+    pub file: Option<NamedFile>,
+    /// Hash of the file's content, for the `ETag`
+    pub hash: Option<String>,
+    /// Unix timestamp for the `Last-Modified` header, if there should be one
+    pub last_modified: Option<i64>,
+    /// Whether the file was requested by its hashed name, meaning that it can be cached
+    /// indefinitely
+    pub immutable: bool,
+}
+
+impl<'r> Responder<'r> for StaticFile {
+    fn respond_to(self, req: &Request) -> response::Result<'r> {
+        let mut response = match self.file {
+            Some(f) => f.respond_to(req)?,
+            None => Response::build().status(Status::NotModified).finalize(),
+        };
+
+        if self.immutable {
+            response.set_header(Header::new("Cache-Control", IMMUTABLE_POLICY));
+        }
+        if let Some(hash) = &self.hash {
+            response.set_header(Header::new("ETag", etag(hash)));
+        }
+        if let Some(t) = self.last_modified {
+            response.set_header(Header::new("Last-Modified", http_date(t)));
+        }
+
+        Ok(response)
+    }
+}
@@ -0,0 +1,102 @@
+//! The `/status` route, reporting what content is currently loaded
+//!
+//! This is mostly for checking that an update actually took effect: each section has the time that
+//! its state was last generated, alongside a few counts.
+//!
+//! There's also the `/debug/state` route, which gives much more detail. It's only mounted in debug
+//! builds.
+
+use rocket::{get, State};
+use rocket_contrib::json::Json;
+use serde::Serialize;
+use std::path::PathBuf;
+
+use crate::config::Config;
+use crate::{blog, photos};
+
+#[derive(Serialize)]
+pub struct Status {
+    blog: BlogStatus,
+    photos: PhotosStatus,
+}
+
+#[derive(Serialize)]
+struct BlogStatus {
+    /// When the posts were last loaded, in RFC 3339 format
+    generated_at: String,
+    posts: usize,
+}
+
+#[derive(Serialize)]
+struct PhotosStatus {
+    /// When the photos were last loaded, in RFC 3339 format
+    generated_at: String,
+    photos: usize,
+    albums: usize,
+}
+
+#[derive(Serialize)]
+pub struct DebugState {
+    blog: BlogDebugState,
+    photos: PhotosDebugState,
+    /// The content directories, as configured
+    dirs: ContentDirs,
+}
+
+#[derive(Serialize)]
+struct BlogDebugState {
+    posts: usize,
+    /// Every tag, most common first
+    tags: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct PhotosDebugState {
+    photos: usize,
+    /// Album path -> number of photos in it, including the automatically generated albums
+    albums: Vec<(String, usize)>,
+}
+
+#[derive(Serialize)]
+struct ContentDirs {
+    static_dir: PathBuf,
+    posts_dir: PathBuf,
+    photos_dir: PathBuf,
+}
+
+#[get("/status")]
+pub fn status() -> Json<Status> {
+    Json(Status {
+        blog: BlogStatus {
+            generated_at: blog::generated_at().to_rfc3339(),
+            posts: blog::num_posts(),
+        },
+        photos: PhotosStatus {
+            generated_at: photos::generated_at().to_rfc3339(),
+            photos: photos::num_photos(),
+            albums: photos::num_albums(),
+        },
+    })
+}
+
+/// Summary of everything that's loaded, for figuring out why something isn't showing up
+///
+/// This isn't in the list of routes; it's only mounted in debug builds.
+#[get("/state")]
+pub fn debug_state(config: State<Config>) -> Json<DebugState> {
+    Json(DebugState {
+        blog: BlogDebugState {
+            posts: blog::num_posts(),
+            tags: blog::tag_names(),
+        },
+        photos: PhotosDebugState {
+            photos: photos::num_photos(),
+            albums: photos::album_photo_counts(),
+        },
+        dirs: ContentDirs {
+            static_dir: config.static_dir.clone(),
+            posts_dir: config.blog.posts_dir.clone(),
+            photos_dir: config.photos.imgs_dir.clone(),
+        },
+    })
+}
@@ -0,0 +1,97 @@
+//! Helpers for video clips and animations, using `ffmpeg` and `ffprobe`
+//!
+//! The `image` crate can't decode these (at least with the features we have enabled), so we
+//! shell out instead. Both programs are expected to be on the `PATH`.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, FixedOffset};
+use serde::Deserialize;
+use std::path::Path;
+use std::process::Command;
+
+/// The metadata we're able to get from a clip's container
+///
+/// Everything here is best-effort; most files won't have all of it.
+#[derive(Debug, Default)]
+pub struct ClipMeta {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub creation_time: Option<DateTime<FixedOffset>>,
+}
+
+/// Runs the command, returning its output if it succeeded
+fn run(cmd: &mut Command) -> Result<Vec<u8>> {
+    let output = cmd
+        .output()
+        .with_context(|| format!("failed to run {:?}", cmd))?;
+
+    if !output.status.success() {
+        bail!(
+            "{:?} exited with {}: {}",
+            cmd,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(output.stdout)
+}
+
+/// Produces a JPEG-encoded still of the first frame of the clip, for use as its poster
+pub fn poster_frame(path: &Path) -> Result<Vec<u8>> {
+    let jpeg = run(Command::new("ffmpeg")
+        .args(["-v", "error", "-i"])
+        .arg(path)
+        .args(["-frames:v", "1", "-q:v", "2"])
+        .args(["-f", "image2pipe", "-c:v", "mjpeg", "-"]))?;
+
+    if jpeg.is_empty() {
+        bail!("no frames in {:?}", path);
+    }
+
+    Ok(jpeg)
+}
+
+/// Reads the title, description, and creation time from the clip's container tags
+pub fn probe(path: &Path) -> Result<ClipMeta> {
+    #[derive(Deserialize)]
+    struct Output {
+        #[serde(default)]
+        format: Format,
+    }
+
+    #[derive(Default, Deserialize)]
+    struct Format {
+        #[serde(default)]
+        tags: Tags,
+    }
+
+    #[derive(Default, Deserialize)]
+    struct Tags {
+        title: Option<String>,
+        description: Option<String>,
+        comment: Option<String>,
+        creation_time: Option<String>,
+    }
+
+    let json = run(Command::new("ffprobe")
+        .args(["-v", "error", "-print_format", "json", "-show_entries"])
+        .arg("format_tags=title,description,comment,creation_time")
+        .arg(path))?;
+
+    let tags = serde_json::from_slice::<Output>(&json)
+        .context("failed to parse ffprobe output")?
+        .format
+        .tags;
+
+    let non_empty = |s: Option<String>| s.filter(|s| !s.trim().is_empty());
+
+    Ok(ClipMeta {
+        title: non_empty(tags.title),
+        description: non_empty(tags.description).or_else(|| non_empty(tags.comment)),
+        // Usually given in UTC, e.g. "2021-12-18T10:15:30.000000Z"
+        creation_time: tags
+            .creation_time
+            .and_then(|t| DateTime::parse_from_rfc3339(&t).ok()),
+    })
+}
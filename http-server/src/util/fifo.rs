@@ -46,4 +46,12 @@ impl FifoFile {
 
         Ok(FifoFile { tail_cmd })
     }
+
+    /// Returns the PID of the underlying `tail` process
+    ///
+    /// Killing the process causes the next read to return EOF, which is the only way to interrupt
+    /// a read that's blocked on another thread.
+    pub fn id(&self) -> u32 {
+        self.tail_cmd.id()
+    }
 }
@@ -0,0 +1,243 @@
+//! Wrapper module for [`reorder_footnotes`] and [`remove_footnotes`], post-processing passes over
+//! markdown events
+
+use pulldown_cmark::escape::escape_html;
+use pulldown_cmark::{CowStr, Event, Tag};
+use std::collections::HashMap;
+
+/// Rewrites the footnotes in a stream of markdown events so that they're numbered in the order
+/// they're first referenced and all collected at the end of the document
+///
+/// `pulldown_cmark` renders each footnote definition wherever it happens to be in the source, and
+/// numbers footnotes by the first time they show up as either a reference *or* a definition. This
+/// instead produces the references and definitions ourselves, as HTML events:
+///
+///  * Each reference links to its definition, and the first reference for each footnote gets an
+///    id so that the definition can link back to it.
+///  * Definitions are placed together in a single `<section class="footnotes">` at the end, in
+///    order of their number, each with a "↩" link back to the first reference.
+///  * Definitions that are never referenced are still included, after all of the others.
+pub fn reorder_footnotes(events: Vec<Event>) -> Vec<Event> {
+    let mut body = Vec::with_capacity(events.len());
+    // Definitions, in the order that they were defined
+    let mut definitions: Vec<(CowStr, Vec<Event>)> = Vec::new();
+    let mut current_def: Option<(CowStr, Vec<Event>)> = None;
+    // The footnote number assigned to each label, in order of first reference
+    let mut numbers: HashMap<CowStr, usize> = HashMap::new();
+
+    for event in events {
+        match event {
+            Event::Start(Tag::FootnoteDefinition(label)) => current_def = Some((label, Vec::new())),
+            Event::End(Tag::FootnoteDefinition(_)) => definitions.extend(current_def.take()),
+            Event::FootnoteReference(label) => {
+                let next_number = numbers.len() + 1;
+                let (number, is_first) = match numbers.get(&label) {
+                    Some(&n) => (n, false),
+                    None => {
+                        numbers.insert(label.clone(), next_number);
+                        (next_number, true)
+                    }
+                };
+
+                let html = reference_html(&label, number, is_first);
+                match current_def.as_mut() {
+                    Some((_, def_events)) => def_events.push(html),
+                    None => body.push(html),
+                }
+            }
+            e => match current_def.as_mut() {
+                Some((_, def_events)) => def_events.push(e),
+                None => body.push(e),
+            },
+        }
+    }
+
+    if definitions.is_empty() {
+        return body;
+    }
+
+    // Referenced footnotes come first, by number. Anything unreferenced keeps its definition order
+    // after that (the sort is stable).
+    definitions.sort_by_key(|(label, _)| numbers.get(label).copied().unwrap_or(usize::MAX));
+
+    body.push(html_event(r#"<section class="footnotes">"#.to_owned()));
+
+    let mut next_unreferenced = numbers.len() + 1;
+    for (label, mut def_events) in definitions {
+        let (number, is_referenced) = match numbers.get(&label) {
+            Some(&n) => (n, true),
+            None => {
+                next_unreferenced += 1;
+                (next_unreferenced - 1, false)
+            }
+        };
+
+        let mut start = String::from(r#"<div class="footnote-definition" id="fn-"#);
+        let _ = escape_html(&mut start, &label);
+        start.push_str(&format!(
+            r#""><sup class="footnote-definition-label">{}</sup>"#,
+            number
+        ));
+        body.push(html_event(start));
+
+        let backref = match is_referenced {
+            true => Some(backref_html(&label)),
+            false => None,
+        };
+
+        // Put the back-reference inside the final paragraph, if there is one, so that it doesn't
+        // end up on its own line.
+        let ends_with_paragraph = matches!(def_events.last(), Some(Event::End(Tag::Paragraph)));
+        match ends_with_paragraph {
+            true => {
+                let end = def_events.pop().unwrap();
+                body.extend(def_events);
+                body.extend(backref);
+                body.push(end);
+            }
+            false => {
+                body.extend(def_events);
+                body.extend(backref);
+            }
+        }
+
+        body.push(html_event("</div>".to_owned()));
+    }
+
+    body.push(html_event("</section>".to_owned()));
+    body
+}
+
+/// Removes every footnote reference and definition from a stream of markdown events
+pub fn remove_footnotes(events: Vec<Event>) -> Vec<Event> {
+    let mut in_definition = false;
+
+    events
+        .into_iter()
+        .filter(|event| match event {
+            Event::Start(Tag::FootnoteDefinition(_)) => {
+                in_definition = true;
+                false
+            }
+            Event::End(Tag::FootnoteDefinition(_)) => {
+                in_definition = false;
+                false
+            }
+            Event::FootnoteReference(_) => false,
+            _ => !in_definition,
+        })
+        .collect()
+}
+
+fn html_event(html: String) -> Event<'static> {
+    Event::Html(CowStr::Boxed(html.into_boxed_str()))
+}
+
+/// Produces the HTML for a reference to a footnote
+fn reference_html(label: &str, number: usize, is_first: bool) -> Event<'static> {
+    // Writing to a `String` can't fail, so the results here can be ignored.
+    let mut html = String::from(r#"<sup class="footnote-reference""#);
+    if is_first {
+        html.push_str(r#" id="fnref-"#);
+        let _ = escape_html(&mut html, label);
+        html.push('"');
+    }
+
+    html.push_str(r##"><a href="#fn-"##);
+    let _ = escape_html(&mut html, label);
+    html.push_str(&format!(r#"">{}</a></sup>"#, number));
+
+    html_event(html)
+}
+
+/// Produces the HTML for the link from a footnote definition back to its first reference
+fn backref_html(label: &str) -> Event<'static> {
+    let mut html = String::from(r##" <a class="footnote-backref" href="#fnref-"##);
+    let _ = escape_html(&mut html, label);
+    html.push_str(r#"" aria-label="Back to reference">↩</a>"#);
+
+    html_event(html)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{html::push_html, Options, Parser};
+
+    fn render(md: &str) -> String {
+        let events = Parser::new_ext(md, Options::ENABLE_FOOTNOTES).collect();
+        let mut html = String::new();
+        push_html(&mut html, reorder_footnotes(events).into_iter());
+        html
+    }
+
+    /// Returns the position of `needle` in `html`, panicking if it isn't there
+    fn position(html: &str, needle: &str) -> usize {
+        match html.find(needle) {
+            Some(i) => i,
+            None => panic!("expected {:?} in {:?}", needle, html),
+        }
+    }
+
+    #[test]
+    fn multiple_footnotes() {
+        let html = render("One[^a] and two[^b].\n\n[^a]: First note.\n\n[^b]: Second note.\n");
+
+        let ref_a = position(&html, r##"id="fnref-a"><a href="#fn-a">1</a>"##);
+        let ref_b = position(&html, r##"id="fnref-b"><a href="#fn-b">2</a>"##);
+        let section = position(&html, r#"<section class="footnotes">"#);
+        let def_a = position(
+            &html,
+            r#"id="fn-a"><sup class="footnote-definition-label">1</sup>"#,
+        );
+        let def_b = position(
+            &html,
+            r#"id="fn-b"><sup class="footnote-definition-label">2</sup>"#,
+        );
+
+        assert!(ref_a < ref_b && ref_b < section && section < def_a && def_a < def_b);
+        assert!(html.contains(r##"href="#fnref-a""##) && html.contains(r##"href="#fnref-b""##));
+    }
+
+    #[test]
+    fn out_of_order_footnotes() {
+        // Defined as "a" then "b", but referenced as "b" then "a"
+        let html = render("One[^b] and two[^a].\n\n[^a]: Alpha.\n\n[^b]: Beta.\n");
+
+        position(&html, r##"id="fnref-b"><a href="#fn-b">1</a>"##);
+        position(&html, r##"id="fnref-a"><a href="#fn-a">2</a>"##);
+        assert!(position(&html, "Beta.") < position(&html, "Alpha."));
+    }
+
+    #[test]
+    fn definition_before_reference() {
+        let html = render("[^early]: Defined first.\n\nThen referenced[^early].\n");
+
+        // The definition is moved after the reference, into the footnotes section
+        let reference = position(&html, r#"id="fnref-early""#);
+        let section = position(&html, r#"<section class="footnotes">"#);
+        assert!(reference < section && section < position(&html, "Defined first."));
+    }
+
+    #[test]
+    fn repeated_reference() {
+        let html = render("One[^a], again[^a].\n\n[^a]: Note.\n");
+
+        // Only the first reference gets the id that the back-link goes to
+        assert_eq!(html.matches(r#"id="fnref-a""#).count(), 1);
+        assert_eq!(html.matches(r##"<a href="#fn-a">1</a>"##).count(), 2);
+    }
+
+    #[test]
+    fn unreferenced_footnote() {
+        let html = render("Unused[^b] aside.\n\n[^a]: Never referenced.\n\n[^b]: Used.\n");
+
+        position(
+            &html,
+            r#"id="fn-a"><sup class="footnote-definition-label">2</sup>"#,
+        );
+        assert!(position(&html, "Used.") < position(&html, "Never referenced."));
+        // Without a reference, there's nothing to link back to
+        assert!(!html.contains(r##"href="#fnref-a""##));
+    }
+}
@@ -2,6 +2,8 @@
 
 use anyhow::{anyhow, Context, Result};
 use lazy_static::lazy_static;
+use log::warn;
+use pulldown_cmark::escape::{escape_href, escape_html};
 use pulldown_cmark::html::push_html;
 use pulldown_cmark::{CodeBlockKind, CowStr, Event, Options, Parser, Tag};
 use regex::Regex;
@@ -10,26 +12,129 @@ use std::borrow::Cow;
 use std::io::{Read, Write};
 use std::net::TcpStream;
 
-/// Converts the markdown string to HTML
-pub fn markdown_to_html(md: &str) -> String {
-    let options = Options::ENABLE_STRIKETHROUGH
-        | Options::ENABLE_FOOTNOTES
-        | Options::ENABLE_TABLES
-        | Options::ENABLE_TASKLISTS;
+use super::footnotes::{remove_footnotes, reorder_footnotes};
+
+/// The kind of content that some markdown represents, which determines the markdown extensions
+/// that are enabled for it
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MarkdownKind {
+    /// Full documents -- i.e. the body of a blog post. Every extension is enabled.
+    Document,
+    /// Short bits of text, like descriptions or photo captions. Footnotes and task lists are
+    /// disabled, because they'd only ever show up by accident.
+    Snippet,
+    /// Part of a document shown on its own, like the sneak peek of a blog post. This is the same as
+    /// `Document`, except that footnotes are removed entirely -- their definitions usually aren't
+    /// in the excerpt, and the references would point to the wrong page anyways.
+    Excerpt,
+}
+
+impl MarkdownKind {
+    fn options(self) -> Options {
+        match self {
+            MarkdownKind::Document | MarkdownKind::Excerpt => {
+                Options::ENABLE_STRIKETHROUGH
+                    | Options::ENABLE_FOOTNOTES
+                    | Options::ENABLE_TABLES
+                    | Options::ENABLE_TASKLISTS
+            }
+            MarkdownKind::Snippet => Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TABLES,
+        }
+    }
+}
+
+/// Marker in a document that ends its excerpt, like the sneak peek of a blog post
+///
+/// Like any other HTML comment, it's removed from the rendered output.
+pub static MORE_MARKER: &str = "<!--more-->";
+
+/// Information about one of our own images, referenced from some markdown
+#[derive(Debug, Clone, Serialize)]
+pub struct LocalImage {
+    pub width: u32,
+    pub height: u32,
+    /// URL of the smaller WEBP version of the image
+    pub webp_url: String,
+    /// URL of the full-size JPEG version of the image, for browsers that don't support WEBP
+    pub jpeg_url: String,
+}
+
+/// Converts the markdown string to HTML, using the extensions enabled for its kind
+pub fn markdown_to_html(md: &str, kind: MarkdownKind) -> String {
+    markdown_to_html_with_images(md, kind, &|_| None)
+}
+
+/// Like [`markdown_to_html`], but uses `lookup_image` to find information about any images that
+/// are on this site
+///
+/// `lookup_image` is given the path of the image (e.g. "/photos/img-file/..."), regardless of
+/// whether the markdown used a relative or absolute URL.
+pub fn markdown_to_html_with_images(
+    md: &str,
+    kind: MarkdownKind,
+    lookup_image: &dyn Fn(&str) -> Option<LocalImage>,
+) -> String {
+    let options = kind.options();
 
     // Errors aren't possible in the parser; it always falls back to some other kind of display.
     let mut html_str = String::new();
     let mut code_state = CodeState::NotStarted;
+    let mut image_state = ImageState::Outside;
+    let mut comment_state = CommentState::default();
+
+    let mut events: Vec<_> = Parser::new_ext(md, options)
+        .map(|e| comment_state.map_event(e))
+        .map(proper_text_dashes)
+        .map(external_links)
+        .map(|e| image_state.map_event(e, lookup_image))
+        .collect();
+
+    // Footnotes are only enabled for full documents anyways, but we may as well skip the extra
+    // pass when we know it won't do anything.
+    match kind {
+        MarkdownKind::Document => events = reorder_footnotes(events),
+        MarkdownKind::Excerpt => events = remove_footnotes(events),
+        MarkdownKind::Snippet => (),
+    }
 
     push_html(
         &mut html_str,
-        Parser::new_ext(md, options)
-            .map(proper_text_dashes)
-            .map(|e| code_state.map_event(e)),
+        events.into_iter().map(|e| code_state.map_event(e)),
     );
     html_str
 }
 
+/// Returns the byte offset of the first [`MORE_MARKER`] in the markdown, if there is one
+///
+/// Markers inside code (or anywhere else that they'd be displayed as text) are ignored.
+pub fn find_more_marker(md: &str, kind: MarkdownKind) -> Option<usize> {
+    Parser::new_ext(md, kind.options())
+        .into_offset_iter()
+        .find(|(event, _)| matches!(event, Event::Html(h) if h.trim() == MORE_MARKER))
+        .map(|(_, range)| range.start)
+}
+
+/// Converts the markdown string to plain text, dropping all of the formatting
+///
+/// Block-level elements are separated by newlines. Code blocks are included as-is, without any
+/// highlighting.
+pub fn markdown_to_plain_text(md: &str, kind: MarkdownKind) -> String {
+    let mut text = String::new();
+
+    for event in Parser::new_ext(md, kind.options()).map(proper_text_dashes) {
+        match event {
+            Event::Text(t) | Event::Code(t) => text.push_str(&t),
+            Event::SoftBreak | Event::HardBreak => text.push(' '),
+            Event::End(Tag::Paragraph | Tag::Heading(_) | Tag::CodeBlock(_) | Tag::Item) => {
+                text.push('\n')
+            }
+            _ => (),
+        }
+    }
+
+    text
+}
+
 /// Helper function to substitute in en- and em-dashes for two and three hyphens in text,
 /// respectively
 ///
@@ -64,6 +169,237 @@ fn proper_text_dashes(event: Event) -> Event {
     Event::Text(text)
 }
 
+/// Hosts that links are considered "internal" for
+static SITE_HOSTS: &[&str] = &["sharnoff.io", "www.sharnoff.io"];
+
+/// Helper function to make links to other sites open in a new tab, with `rel="noopener
+/// noreferrer"`
+///
+/// Only absolute links (i.e. "http://", "https://", or protocol-relative "//") to a host other than
+/// our own are changed. Relative links, anchors, "mailto:", and everything else are left as-is.
+fn external_links(event: Event) -> Event {
+    let (dest, title) = match &event {
+        Event::Start(Tag::Link(_, dest, title)) if is_external_link(dest) => (dest, title),
+        _ => return event,
+    };
+
+    // We write the opening tag ourselves; the closing tag from the `End` event is unchanged.
+    //
+    // Writing to a `String` can't fail, so the results here can be ignored.
+    let mut html = String::from("<a href=\"");
+    let _ = escape_href(&mut html, dest);
+    if !title.is_empty() {
+        html.push_str("\" title=\"");
+        let _ = escape_html(&mut html, title);
+    }
+    html.push_str("\" target=\"_blank\" rel=\"noopener noreferrer\">");
+
+    Event::Html(CowStr::Boxed(html.into_boxed_str()))
+}
+
+/// Returns whether the link destination points to a different site
+fn is_external_link(dest: &str) -> bool {
+    match split_host(dest) {
+        Some((host, _)) => !SITE_HOSTS.iter().any(|h| h.eq_ignore_ascii_case(host)),
+        None => false,
+    }
+}
+
+/// Returns the path on this site that the URL refers to, if it refers to this site at all
+pub fn local_path(dest: &str) -> Option<&str> {
+    match split_host(dest) {
+        Some((host, path)) if SITE_HOSTS.iter().any(|h| h.eq_ignore_ascii_case(host)) => Some(path),
+        Some(_) => None,
+        None if dest.starts_with('/') => Some(dest),
+        None => None,
+    }
+}
+
+/// Returns whether the URL is absolute -- i.e. it has an "http://" or "https://" scheme and a host
+pub fn is_absolute_url(url: &str) -> bool {
+    let has_scheme = url.starts_with("http://") || url.starts_with("https://");
+    has_scheme && matches!(split_host(url), Some((host, _)) if !host.is_empty())
+}
+
+/// For absolute URLs (with "http://", "https://", or protocol-relative "//"), splits the URL into
+/// the host and everything after it
+fn split_host(dest: &str) -> Option<(&str, &str)> {
+    let rest = match dest.split_once("//") {
+        Some(("", rest) | ("http:", rest) | ("https:", rest)) => rest,
+        _ => return None,
+    };
+
+    let host_end = rest
+        .find(|c| matches!(c, '/' | '?' | '#'))
+        .unwrap_or(rest.len());
+    let (host_and_port, path) = rest.split_at(host_end);
+    let host = host_and_port.split(':').next().unwrap_or("");
+
+    Some((host, path))
+}
+
+/// Tracks whether we're inside an HTML comment, so that comments can be removed from the output
+///
+/// Comments only ever show up in `Html` events -- anything in a code block is `Text` instead -- so
+/// comments that are part of code are left alone. A single comment can span multiple events (e.g.
+/// one for each line of an HTML block), hence the state.
+#[derive(Debug, Default)]
+struct CommentState {
+    in_comment: bool,
+}
+
+impl CommentState {
+    fn map_event<'md>(&mut self, event: Event<'md>) -> Event<'md> {
+        let html = match event {
+            Event::Html(h) => h,
+            e => return e,
+        };
+
+        if !self.in_comment && !html.contains("<!--") {
+            return Event::Html(html);
+        }
+
+        let mut output = String::new();
+        let mut rest: &str = &html;
+
+        loop {
+            if self.in_comment {
+                match rest.find("-->") {
+                    Some(i) => {
+                        rest = &rest[i + "-->".len()..];
+                        self.in_comment = false;
+                    }
+                    None => break,
+                }
+            } else {
+                match rest.find("<!--") {
+                    Some(i) => {
+                        output.push_str(&rest[..i]);
+                        rest = &rest[i + "<!--".len()..];
+                        self.in_comment = true;
+                    }
+                    None => {
+                        output.push_str(rest);
+                        break;
+                    }
+                }
+            }
+        }
+
+        Event::Html(output.into())
+    }
+}
+
+/// Simple object to group the events making up an image together, so that we can write the
+/// `<img>` tag ourselves
+///
+/// All images are given `loading="lazy"` and `decoding="async"`. Images on this site that we have
+/// information about are instead written as a `<picture>` with both the WEBP and JPEG versions,
+/// and get their width and height so that the browser can reserve space for them before they're
+/// loaded.
+#[derive(Debug)]
+enum ImageState<'md> {
+    Outside,
+    Inside {
+        dest: CowStr<'md>,
+        title: CowStr<'md>,
+        /// The alt text, collected from all of the text inside the image
+        alt: String,
+        /// Number of nested images we're currently in. They aren't displayed, but their text is
+        /// still included in the alt text.
+        nested: usize,
+    },
+}
+
+impl<'md> ImageState<'md> {
+    fn map_event(
+        &mut self,
+        event: Event<'md>,
+        lookup_image: &dyn Fn(&str) -> Option<LocalImage>,
+    ) -> Event<'md> {
+        let (dest, title, alt, nested) = match self {
+            ImageState::Outside => match event {
+                Event::Start(Tag::Image(_, dest, title)) => {
+                    *self = ImageState::Inside {
+                        dest,
+                        title,
+                        alt: String::new(),
+                        nested: 0,
+                    };
+                    return Event::Html(CowStr::Borrowed(""));
+                }
+                e => return e,
+            },
+            ImageState::Inside {
+                dest,
+                title,
+                alt,
+                nested,
+            } => (dest, title, alt, nested),
+        };
+
+        match event {
+            Event::Text(t) | Event::Code(t) => alt.push_str(&t),
+            Event::SoftBreak | Event::HardBreak => alt.push(' '),
+            Event::Start(Tag::Image(..)) => *nested += 1,
+            Event::End(Tag::Image(..)) if *nested > 0 => *nested -= 1,
+            Event::End(Tag::Image(..)) => {
+                let info = local_path(dest).and_then(lookup_image);
+                let html = img_tag(dest, title, alt, info);
+                *self = ImageState::Outside;
+                return Event::Html(CowStr::Boxed(html.into_boxed_str()));
+            }
+            _ => (),
+        }
+
+        Event::Html(CowStr::Borrowed(""))
+    }
+}
+
+/// Produces the HTML for an image from markdown -- either a plain `<img>` tag or, for our own
+/// images, a `<picture>`
+fn img_tag(dest: &str, title: &str, alt: &str, info: Option<LocalImage>) -> String {
+    // Writing to a `String` can't fail, so the results here can be ignored.
+    let mut html = String::new();
+
+    let src = match &info {
+        Some(info) => {
+            html.push_str(r#"<picture><source type="image/webp" srcset=""#);
+            let _ = escape_href(&mut html, &info.webp_url);
+            html.push_str(r#"">"#);
+            info.jpeg_url.as_str()
+        }
+        None => dest,
+    };
+
+    html.push_str("<img src=\"");
+    let _ = escape_href(&mut html, src);
+    html.push_str("\" alt=\"");
+    let _ = escape_html(&mut html, alt);
+    html.push('"');
+
+    if !title.is_empty() {
+        html.push_str(" title=\"");
+        let _ = escape_html(&mut html, title);
+        html.push('"');
+    }
+
+    if let Some(info) = &info {
+        html.push_str(&format!(
+            r#" width="{}" height="{}""#,
+            info.width, info.height
+        ));
+    }
+
+    html.push_str(r#" loading="lazy" decoding="async" />"#);
+
+    if info.is_some() {
+        html.push_str("</picture>");
+    }
+
+    html
+}
+
 /// The address of the server we connect to for syntax highlighting
 static HIGHLIGHT_SERVER_ADDR: &str = "localhost:8001";
 
@@ -169,15 +505,19 @@ impl<'md> CodeState<'md> {
 ///
 /// Internally, this attempts to connect to a running highlighter server. Highlighting can fail for
 /// a number of reasons -- on failure, we output the code as if no language was selected.
+///
+/// Code without highlighting is escaped, so that it's always displayed exactly as written (even if
+/// it looks like HTML, e.g. a comment).
 fn code_block_to_html(code: &str, language: Option<&str>) -> String {
     let new_code = match highlight(code, language) {
-        Ok(c) => c,
+        Ok(Some(c)) => c,
+        Ok(None) => escape_code(code),
         Err(e) => {
-            eprintln!(
-                "Could not highlight code for language {:?}: {:#}",
+            warn!(
+                "could not highlight code for language {:?}: {:#}",
                 language, e
             );
-            Cow::Borrowed(code)
+            escape_code(code)
         }
     };
 
@@ -188,10 +528,18 @@ fn code_block_to_html(code: &str, language: Option<&str>) -> String {
     format!("<pre><code{}>\n{}\n</code></pre>", language_class, new_code)
 }
 
-fn highlight<'md>(code: &'md str, language: Option<&str>) -> Result<Cow<'md, str>> {
+fn escape_code(code: &str) -> String {
+    // Writing to a `String` can't fail
+    let mut escaped = String::with_capacity(code.len());
+    let _ = escape_html(&mut escaped, code);
+    escaped
+}
+
+/// Highlights the code with the highlighting server, returning `None` if there's no language
+fn highlight(code: &str, language: Option<&str>) -> Result<Option<String>> {
     let language = match language {
         // If there is no language, then we can skip highlighting:
-        None => return Ok(Cow::Borrowed(code)),
+        None => return Ok(None),
         Some(l) => l,
     };
 
@@ -223,9 +571,92 @@ fn highlight<'md>(code: &'md str, language: Option<&str>) -> Result<Cow<'md, str
         .context("failed to read response from highlighting server")?;
 
     match resp {
-        HighlightResponse::Success(new_code) => Ok(Cow::Owned(new_code)),
+        HighlightResponse::Success(new_code) => Ok(Some(new_code)),
         HighlightResponse::Failure(err_msg) => {
             Err(anyhow!("server failed to highlight code: {}", err_msg))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inline_comment_removed() {
+        let html = markdown_to_html("Before <!-- hidden --> after", MarkdownKind::Document);
+        assert!(!html.contains("hidden"), "comment in output: {}", html);
+        assert!(html.contains("Before") && html.contains("after"));
+    }
+
+    #[test]
+    fn multi_line_comment_removed() {
+        let md = "<!--\nhidden\nacross lines\n-->\n\nVisible text";
+        let html = markdown_to_html(md, MarkdownKind::Document);
+        assert!(
+            !html.contains("hidden") && !html.contains("-->"),
+            "comment in output: {}",
+            html
+        );
+        assert!(html.contains("Visible text"));
+    }
+
+    #[test]
+    fn more_marker_removed() {
+        let md = format!("First paragraph\n\n{}\n\nSecond paragraph", MORE_MARKER);
+        let html = markdown_to_html(&md, MarkdownKind::Document);
+        assert!(!html.contains("more"), "marker in output: {}", html);
+        assert!(html.contains("Second paragraph"));
+    }
+
+    #[test]
+    fn excerpt_footnotes_removed() {
+        let md = "Some text[^a] with a footnote.\n\n[^a]: The footnote.";
+        let html = markdown_to_html(md, MarkdownKind::Excerpt);
+        assert!(
+            !html.contains("footnote-") && !html.contains("The footnote"),
+            "{}",
+            html
+        );
+        assert!(html.contains("Some text with a footnote."));
+    }
+
+    #[test]
+    fn comment_in_code_block_kept() {
+        let md = "```\nlet x = 1; <!-- not a comment -->\n```";
+        let html = markdown_to_html(md, MarkdownKind::Document);
+        assert!(
+            html.contains("&lt;!-- not a comment --&gt;"),
+            "comment missing: {}",
+            html
+        );
+    }
+
+    #[test]
+    fn comment_in_inline_code_kept() {
+        let html = markdown_to_html("Write `<!-- this -->` for comments", MarkdownKind::Snippet);
+        assert!(
+            html.contains("&lt;!-- this --&gt;"),
+            "comment missing: {}",
+            html
+        );
+    }
+
+    #[test]
+    fn absolute_urls() {
+        assert!(is_absolute_url("https://example.com"));
+        assert!(is_absolute_url("http://example.com:8080/path?q#frag"));
+
+        // Protocol-relative URLs depend on the page they're on
+        assert!(!is_absolute_url("//example.com/path"));
+        // Other schemes aren't what we're looking for, and neither are URLs without a host
+        assert!(!is_absolute_url("mailto:someone@example.com"));
+        assert!(!is_absolute_url("https:///path"));
+        assert!(!is_absolute_url("https://:443/path"));
+        // Relative paths
+        assert!(!is_absolute_url("/blog/post"));
+        assert!(!is_absolute_url("post#section"));
+        assert!(!is_absolute_url("../https://example.com"));
+        assert!(!is_absolute_url(""));
+    }
+}
@@ -0,0 +1,392 @@
+//! Minimal reading of the IPTC and XMP metadata embedded in a JPEG's APP segments
+//!
+//! We only care about a couple of text fields (the title and the caption), so this isn't a general
+//! parser -- anything we don't understand is just skipped. The EXIF data is handled separately by
+//! the `exif` crate; this only exists as a fallback for files that put their text elsewhere.
+//!
+//! See:
+//!  * IPTC IIM: https://www.iptc.org/std/IIM/4.2/specification/IIMV4.2.pdf
+//!  * Photoshop image resources: https://www.adobe.com/devnet-apps/photoshop/fileformatashtml/
+//!  * XMP: https://www.adobe.com/devnet/xmp.html
+
+/// Prefix of the APP1 segment containing an XMP packet
+const XMP_SEGMENT_PREFIX: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+/// Prefix of the APP13 segment containing Photoshop image resources (which hold the IPTC data)
+const PHOTOSHOP_SEGMENT_PREFIX: &[u8] = b"Photoshop 3.0\0";
+/// Photoshop image resource ID for the IPTC-NAA record
+const IPTC_RESOURCE_ID: u16 = 0x0404;
+
+// IPTC "application record" dataset numbers that we use
+const IPTC_OBJECT_NAME: u8 = 5;
+const IPTC_CAPTION: u8 = 120;
+
+/// The text fields found in the IPTC and XMP metadata of an image
+///
+/// Fields that are missing or empty are `None`.
+#[derive(Debug, Default)]
+pub struct TextFields {
+    /// From the IPTC `ObjectName` dataset
+    pub iptc_title: Option<String>,
+    /// From the IPTC `Caption/Abstract` dataset
+    pub iptc_caption: Option<String>,
+    /// From the XMP `dc:title` property
+    pub xmp_title: Option<String>,
+    /// From the XMP `dc:description` property
+    pub xmp_description: Option<String>,
+}
+
+impl TextFields {
+    /// Extracts the IPTC and XMP text fields from the JPEG data
+    ///
+    /// Malformed segments are ignored, so this never fails; at worst, all of the fields are `None`.
+    pub fn read(jpeg: &[u8]) -> Self {
+        let mut fields = TextFields::default();
+
+        for (marker, data) in app_segments(jpeg) {
+            if marker == 0xE1 && data.starts_with(XMP_SEGMENT_PREFIX) {
+                let xmp = String::from_utf8_lossy(&data[XMP_SEGMENT_PREFIX.len()..]);
+                fields.xmp_title = fields.xmp_title.or_else(|| xmp_lang_alt(&xmp, "dc:title"));
+                fields.xmp_description = fields
+                    .xmp_description
+                    .or_else(|| xmp_lang_alt(&xmp, "dc:description"));
+            } else if marker == 0xED && data.starts_with(PHOTOSHOP_SEGMENT_PREFIX) {
+                let iptc = match iptc_resource(&data[PHOTOSHOP_SEGMENT_PREFIX.len()..]) {
+                    Some(d) => d,
+                    None => continue,
+                };
+
+                for (dataset, value) in iptc_datasets(iptc) {
+                    let slot = match dataset {
+                        IPTC_OBJECT_NAME => &mut fields.iptc_title,
+                        IPTC_CAPTION => &mut fields.iptc_caption,
+                        _ => continue,
+                    };
+
+                    if slot.is_none() {
+                        *slot = non_empty(decode_iptc_text(value));
+                    }
+                }
+            }
+        }
+
+        fields
+    }
+}
+
+/// Returns the marker and contents of each APPn segment in the JPEG, stopping at the start of the
+/// image data
+fn app_segments(jpeg: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut segments = Vec::new();
+
+    // Every JPEG starts with the SOI marker
+    if !jpeg.starts_with(&[0xFF, 0xD8]) {
+        return segments;
+    }
+
+    let mut pos = 2;
+    while pos + 4 <= jpeg.len() && jpeg[pos] == 0xFF {
+        let marker = jpeg[pos + 1];
+        match marker {
+            // Fill bytes before a marker
+            0xFF => {
+                pos += 1;
+                continue;
+            }
+            // Standalone markers, without a length
+            0x01 | 0xD0..=0xD7 => {
+                pos += 2;
+                continue;
+            }
+            // Start of scan or end of image; the metadata is all before this
+            0xDA | 0xD9 => break,
+            _ => (),
+        }
+
+        // The length includes the two bytes for itself
+        let len = u16::from_be_bytes([jpeg[pos + 2], jpeg[pos + 3]]) as usize;
+        let end = pos + 2 + len;
+        if len < 2 || end > jpeg.len() {
+            break;
+        }
+
+        if (0xE0..=0xEF).contains(&marker) {
+            segments.push((marker, &jpeg[pos + 4..end]));
+        }
+
+        pos = end;
+    }
+
+    segments
+}
+
+/// Finds the IPTC-NAA record within the Photoshop image resource blocks
+fn iptc_resource(mut resources: &[u8]) -> Option<&[u8]> {
+    // Each block is:
+    //   "8BIM" | u16 ID | Pascal string name, padded to even length | u32 size | data, padded to
+    //   even length
+    while resources.len() >= 12 && resources.starts_with(b"8BIM") {
+        let id = u16::from_be_bytes([resources[4], resources[5]]);
+
+        let name_len = resources[6] as usize;
+        // The length byte is included in the padding
+        let name_end = 6 + ((name_len + 2) & !1);
+
+        let size_bytes = resources.get(name_end..name_end + 4)?;
+        let size = u32::from_be_bytes([size_bytes[0], size_bytes[1], size_bytes[2], size_bytes[3]])
+            as usize;
+        let data_start = name_end + 4;
+        let data = resources.get(data_start..data_start + size)?;
+
+        if id == IPTC_RESOURCE_ID {
+            return Some(data);
+        }
+
+        let next = data_start + ((size + 1) & !1);
+        resources = resources.get(next..)?;
+    }
+
+    None
+}
+
+/// Returns the dataset number and value of each dataset in the IPTC application record (record 2)
+fn iptc_datasets(mut iptc: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut datasets = Vec::new();
+
+    // Each dataset is: 0x1C | u8 record | u8 dataset | u16 size | data
+    //
+    // Sizes with the high bit set use the "extended" format, which is only for values over 32KiB.
+    // We don't need anything that large, and we can't skip over them without parsing it, so we just
+    // stop there.
+    while iptc.len() >= 5 && iptc[0] == 0x1C {
+        let (record, dataset) = (iptc[1], iptc[2]);
+        let size = u16::from_be_bytes([iptc[3], iptc[4]]);
+        if size & 0x8000 != 0 {
+            break;
+        }
+
+        let value = match iptc.get(5..5 + size as usize) {
+            Some(v) => v,
+            None => break,
+        };
+
+        if record == 2 {
+            datasets.push((dataset, value));
+        }
+
+        iptc = &iptc[5 + size as usize..];
+    }
+
+    datasets
+}
+
+/// Decodes IPTC text, which is *usually* UTF-8 nowadays. Older files may have Latin-1 instead.
+fn decode_iptc_text(value: &[u8]) -> String {
+    match std::str::from_utf8(value) {
+        Ok(s) => s.to_owned(),
+        // Latin-1 maps directly onto the first 256 code points
+        Err(_) => value.iter().map(|&b| b as char).collect(),
+    }
+}
+
+/// Extracts the default value of a "Lang Alt" XMP property, like `dc:title`
+///
+/// These are typically given as:
+///
+/// ```xml
+/// <dc:title>
+///   <rdf:Alt>
+///     <rdf:li xml:lang="x-default">The title</rdf:li>
+///   </rdf:Alt>
+/// </dc:title>
+/// ```
+///
+/// We just take the first `rdf:li`, which is the default language in practice.
+fn xmp_lang_alt(xmp: &str, property: &str) -> Option<String> {
+    let open = format!("<{}>", property);
+    let close = format!("</{}>", property);
+
+    let start = xmp.find(&open)? + open.len();
+    let end = start + xmp[start..].find(&close)?;
+    let inner = &xmp[start..end];
+
+    let li_start = inner.find("<rdf:li")?;
+    let value_start = li_start + inner[li_start..].find('>')? + 1;
+    let value_end = value_start + inner[value_start..].find("</rdf:li>")?;
+
+    non_empty(unescape_xml(&inner[value_start..value_end]))
+}
+
+/// Replaces the predefined XML entities and character references with the characters they refer
+/// to
+///
+/// Anything that isn't a valid reference is left as-is.
+fn unescape_xml(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+
+        let semi = match rest.find(';') {
+            Some(i) => i,
+            None => break,
+        };
+
+        let replacement = match &rest[1..semi] {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            r => r
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| r.strip_prefix('#').map(|dec| dec.parse()))
+                .and_then(|n| n.ok())
+                .and_then(char::from_u32),
+        };
+
+        match replacement {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[semi + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+fn non_empty(s: String) -> Option<String> {
+    match s.trim() {
+        "" => None,
+        _ => Some(s),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a segment with the given marker, adding the length
+    fn segment(marker: u8, data: &[u8]) -> Vec<u8> {
+        let len = (data.len() + 2) as u16;
+        let mut seg = vec![0xFF, marker];
+        seg.extend_from_slice(&len.to_be_bytes());
+        seg.extend_from_slice(data);
+        seg
+    }
+
+    /// A JPEG with an IPTC title & caption and an XMP title & description, followed by the start
+    /// of the image data
+    fn test_jpeg() -> Vec<u8> {
+        let mut iptc = Vec::new();
+        for (dataset, value) in [(IPTC_OBJECT_NAME, "IPTC title"), (IPTC_CAPTION, "Caption")] {
+            iptc.extend_from_slice(&[0x1C, 2, dataset]);
+            iptc.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            iptc.extend_from_slice(value.as_bytes());
+        }
+
+        let mut photoshop = PHOTOSHOP_SEGMENT_PREFIX.to_vec();
+        photoshop.extend_from_slice(b"8BIM");
+        photoshop.extend_from_slice(&IPTC_RESOURCE_ID.to_be_bytes());
+        // Empty name, padded to even length
+        photoshop.extend_from_slice(&[0, 0]);
+        photoshop.extend_from_slice(&(iptc.len() as u32).to_be_bytes());
+        photoshop.extend_from_slice(&iptc);
+
+        let mut xmp = XMP_SEGMENT_PREFIX.to_vec();
+        xmp.extend_from_slice(
+            concat!(
+                r#"<dc:title><rdf:Alt><rdf:li xml:lang="x-default">XMP &amp; title</rdf:li>"#,
+                r#"</rdf:Alt></dc:title><dc:description><rdf:Alt><rdf:li>Description</rdf:li>"#,
+                r#"</rdf:Alt></dc:description>"#,
+            )
+            .as_bytes(),
+        );
+
+        let mut jpeg = vec![0xFF, 0xD8];
+        jpeg.extend(segment(0xE0, b"JFIF\0\x01\x01"));
+        jpeg.extend(segment(0xED, &photoshop));
+        jpeg.extend(segment(0xE1, &xmp));
+        jpeg.extend(segment(0xDA, &[0; 10]));
+        jpeg.extend_from_slice(&[0x12, 0x34, 0xFF, 0xD9]);
+        jpeg
+    }
+
+    fn is_empty(fields: &TextFields) -> bool {
+        fields.iptc_title.is_none()
+            && fields.iptc_caption.is_none()
+            && fields.xmp_title.is_none()
+            && fields.xmp_description.is_none()
+    }
+
+    #[test]
+    fn minimal_jpeg() {
+        let fields = TextFields::read(&test_jpeg());
+
+        assert_eq!(fields.iptc_title.as_deref(), Some("IPTC title"));
+        assert_eq!(fields.iptc_caption.as_deref(), Some("Caption"));
+        assert_eq!(fields.xmp_title.as_deref(), Some("XMP & title"));
+        assert_eq!(fields.xmp_description.as_deref(), Some("Description"));
+    }
+
+    #[test]
+    fn truncated_segments() {
+        let jpeg = test_jpeg();
+
+        // Every prefix of the file should be handled, with only the complete segments read
+        for len in 0..jpeg.len() {
+            let _ = TextFields::read(&jpeg[..len]);
+        }
+
+        // Cutting off the end of the XMP segment drops it, but keeps the IPTC data before it
+        let xmp_start = jpeg
+            .windows(XMP_SEGMENT_PREFIX.len())
+            .position(|w| w == XMP_SEGMENT_PREFIX)
+            .unwrap();
+        let fields = TextFields::read(&jpeg[..xmp_start + 10]);
+        assert_eq!(fields.iptc_title.as_deref(), Some("IPTC title"));
+        assert_eq!(fields.xmp_title, None);
+    }
+
+    #[test]
+    fn bad_lengths() {
+        // Lengths of 0 and 1 can't include themselves; these would never advance if accepted
+        for len in [0_u8, 1] {
+            let jpeg = [0xFF, 0xD8, 0xFF, 0xE1, 0, len, 0xFF, 0xE1, 0, len];
+            assert!(app_segments(&jpeg).is_empty());
+        }
+
+        // A length that runs past the end of the file
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1, 0xFF, 0xFF];
+        jpeg.extend_from_slice(XMP_SEGMENT_PREFIX);
+        assert!(app_segments(&jpeg).is_empty());
+        assert!(is_empty(&TextFields::read(&jpeg)));
+
+        // Photoshop resources & IPTC datasets with sizes past the end of their segment
+        let mut photoshop = PHOTOSHOP_SEGMENT_PREFIX.to_vec();
+        photoshop.extend_from_slice(b"8BIM\x04\x04\0\0\xFF\xFF\xFF\xFF\x1C\x02\x05");
+        let mut jpeg = vec![0xFF, 0xD8];
+        jpeg.extend(segment(0xED, &photoshop));
+        assert!(is_empty(&TextFields::read(&jpeg)));
+
+        assert!(iptc_datasets(&[0x1C, 2, IPTC_CAPTION, 0x00, 0xFF, b'a']).is_empty());
+        assert!(iptc_datasets(&[0x1C, 2, IPTC_CAPTION, 0x80, 0x04, 0, 0, 0, 1, b'a']).is_empty());
+    }
+
+    #[test]
+    fn missing_soi() {
+        let jpeg = test_jpeg();
+
+        assert!(is_empty(&TextFields::read(&jpeg[2..])));
+        assert!(is_empty(&TextFields::read(&[])));
+        assert!(is_empty(&TextFields::read(&[0xFF])));
+    }
+}
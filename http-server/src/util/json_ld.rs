@@ -0,0 +1,108 @@
+//! Structured data for blog posts and photos, as schema.org objects in JSON-LD
+//!
+//! Each page includes its object in a `<script type="application/ld+json">`, so that search engines
+//! can show more about it. Fields that we don't have are left out entirely, rather than `null`.
+
+use chrono::{DateTime, FixedOffset};
+use serde::Serialize;
+
+/// The author of all of the photos on the site. Blog posts give their own.
+static PHOTOS_AUTHOR_NAME: &str = "Max Sharnoff";
+
+/// A blog post, as a schema.org `BlogPosting`
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlogPosting<'a> {
+    pub headline: &'a str,
+    /// Plain text, not HTML
+    pub description: &'a str,
+    pub url: String,
+    pub date_published: DateTime<FixedOffset>,
+    /// Only set if the post has been updated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date_modified: Option<DateTime<FixedOffset>>,
+    pub keywords: &'a [String],
+    #[serde(skip)]
+    pub author: &'a str,
+}
+
+/// A photo, as a schema.org `ImageObject`
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageObject<'a> {
+    pub name: &'a str,
+    pub caption: &'a str,
+    pub content_url: String,
+    pub thumbnail_url: String,
+    pub date_created: DateTime<FixedOffset>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_location: Option<Place<'a>>,
+}
+
+/// Where a photo was taken, as a schema.org `Place`
+#[derive(Serialize)]
+pub struct Place<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub geo: Option<GeoCoordinates>,
+}
+
+/// A schema.org `GeoCoordinates`
+#[derive(Serialize)]
+pub struct GeoCoordinates {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+#[derive(Serialize)]
+struct Person<'a> {
+    #[serde(rename = "@type")]
+    kind: &'static str,
+    name: &'a str,
+}
+
+/// Wrapper to add the fields that every top-level object needs
+#[derive(Serialize)]
+struct Document<'a, T> {
+    #[serde(rename = "@context")]
+    context: &'static str,
+    #[serde(rename = "@type")]
+    kind: &'static str,
+    #[serde(flatten)]
+    object: &'a T,
+    author: Person<'a>,
+}
+
+impl BlogPosting<'_> {
+    /// Returns the contents of the `<script>` tag for the post
+    pub fn to_script(&self) -> String {
+        to_script("BlogPosting", self, self.author)
+    }
+}
+
+impl ImageObject<'_> {
+    /// Returns the contents of the `<script>` tag for the photo
+    pub fn to_script(&self) -> String {
+        to_script("ImageObject", self, PHOTOS_AUTHOR_NAME)
+    }
+}
+
+fn to_script<T: Serialize>(kind: &'static str, object: &T, author: &str) -> String {
+    let doc = Document {
+        context: "https://schema.org",
+        kind,
+        object,
+        author: Person {
+            kind: "Person",
+            name: author,
+        },
+    };
+
+    // Serializing plain structs can't fail. Any "</" has to be escaped so that the script can't be
+    // closed early by something like a title containing "</script>"; JSON allows "\/" for exactly
+    // this reason.
+    serde_json::to_string(&doc)
+        .expect("failed to serialize JSON-LD")
+        .replace("</", "<\\/")
+}
@@ -1,15 +1,35 @@
 //! Crate-wide utilities
 
-use chrono::{DateTime, FixedOffset};
+use chrono::{DateTime, Datelike, FixedOffset, TimeZone, Utc};
+use rocket::request::{self, FromRequest};
 use rocket::response::{self, Responder};
-use rocket::{http, Request};
+use rocket::{http, Outcome, Request};
 use std::ops::RangeInclusive;
 
+pub mod clips;
 mod fifo;
+mod footnotes;
 mod html;
+pub mod jpeg_meta;
+pub mod json_ld;
+mod og_image;
+pub mod progress;
+mod search;
+pub mod sprite;
+pub mod tiles;
+pub mod watermark;
+pub mod zip;
 
 pub use fifo::FifoFile;
-pub use html::markdown_to_html;
+pub use html::{
+    find_more_marker, is_absolute_url, local_path, markdown_to_html, markdown_to_html_with_images,
+    markdown_to_plain_text, LocalImage, MarkdownKind,
+};
+pub use og_image::render_og_image;
+pub use search::SearchIndex;
+
+/// Base URL of the site, for places where links need to be absolute
+pub static SITE_URL: &str = "https://sharnoff.io";
 
 /// The character ranges that get mapped to the same value when URI encoded
 ///
@@ -29,12 +49,131 @@ static URI_ENCODE_AS_IS_RANGES: &[RangeInclusive<char>] = &[
     '_'..='_',
 ];
 
+/// Returns the base64-encoded sha256 hash of the data
+///
+/// The hashing function is subject to change, though sha256 seems to be the best version for us
+/// right now.
+///
+/// The returned string is URL-safe.
+pub fn content_hash(data: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+
+    base64::encode_config(hasher.finalize(), base64::URL_SAFE_NO_PAD)
+}
+
 /// Returns false if the string has any characters that aren't URI encoded to themselves
 pub fn is_uri_idempotent(s: &str) -> bool {
     s.chars()
         .all(|c| URI_ENCODE_AS_IS_RANGES.iter().any(|r| r.contains(&c)))
 }
 
+/// Produces a lowercase, URL-safe identifier from the string, with each run of anything other than
+/// ASCII letters and digits replaced by a single '-'; e.g. "nikon-z-6" for "Nikon Z 6"
+pub fn slugify(s: &str) -> String {
+    let mut slug = String::new();
+    for c in s.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    slug.trim_end_matches('-').to_owned()
+}
+
+/// Returns the IP address of the client that made the request, if we can find it
+///
+/// Because we're typically behind a reverse proxy, the headers it sets take priority over the
+/// address of the connection itself.
+pub fn client_ip(request: &Request) -> Option<String> {
+    let headers = request.headers();
+
+    headers
+        .get_one("X-Forwarded-For") // Set by caddy
+        .map(str::to_owned)
+        .or_else(|| Some(headers.get_one("X-Client-IP")?.to_string())) // Set by other proxies
+        .or_else(|| Some(request.client_ip()?.to_string()))
+}
+
+/// Request guard for the value of the `If-None-Match` header, if there is one
+pub struct IfNoneMatch(Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for IfNoneMatch {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        let value = request.headers().get_one("If-None-Match").map(String::from);
+        Outcome::Success(IfNoneMatch(value))
+    }
+}
+
+impl IfNoneMatch {
+    /// Returns true if the request had an `If-None-Match` header
+    pub fn is_present(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Returns whether the header includes the ETag for the given hash
+    ///
+    /// Weak comparison is used, as required for `If-None-Match` -- so a "W/" prefix is ignored.
+    pub fn matches(&self, hash: &str) -> bool {
+        let header = match &self.0 {
+            Some(h) => h,
+            None => return false,
+        };
+
+        header
+            .split(',')
+            .map(str::trim)
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/").trim_matches('"') == hash)
+    }
+}
+
+/// Request guard for the value of the `If-Modified-Since` header, if there is one and it's a valid
+/// date
+pub struct IfModifiedSince(Option<DateTime<FixedOffset>>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for IfModifiedSince {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        // HTTP dates (e.g. "Sun, 06 Nov 1994 08:49:37 GMT") are a subset of RFC 2822. Invalid
+        // dates are ignored, as the spec requires.
+        let value = request
+            .headers()
+            .get_one("If-Modified-Since")
+            .and_then(|v| DateTime::parse_from_rfc2822(v).ok());
+        Outcome::Success(IfModifiedSince(value))
+    }
+}
+
+impl IfModifiedSince {
+    /// Returns whether the resource is unchanged since the date in the header, given the Unix
+    /// timestamp at which it was last modified
+    pub fn is_unmodified(&self, last_modified_unix_time: i64) -> bool {
+        match self.0 {
+            Some(since) => last_modified_unix_time <= since.timestamp(),
+            None => false,
+        }
+    }
+}
+
+/// Formats the hash as the value of an `ETag` header
+pub fn etag(hash: &str) -> String {
+    format!("\"{}\"", hash)
+}
+
+/// Formats the Unix timestamp as an HTTP date, for use in headers like `Last-Modified`
+pub fn http_date(unix_time: i64) -> String {
+    Utc.timestamp(unix_time, 0)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
 /// Selector for which `DateTime` formatter to use
 pub enum FormatLevel {
     /// Mon(th) Day, Year; e.g. "Nov 7, 2021"
@@ -43,8 +182,8 @@ pub enum FormatLevel {
     DateTime,
     /// Hour:Minute:Second Offset; e.g. "13:27:45"
     LocalTime,
-    /// Offset; e.g. "-08:00"
-    Offset,
+    /// Offset, explicitly relative to UTC; e.g. "UTC-08:00"
+    UtcOffset,
 }
 
 /// Standard formatting for the provided `DateTime`, given the level of detail with which to format
@@ -53,12 +192,73 @@ pub fn format_datetime(datetime: DateTime<FixedOffset>, selector: FormatLevel) -
         FormatLevel::Date => "%b %-d, %Y",
         FormatLevel::DateTime => "%H:%M:%S %b %d %Y %Z",
         FormatLevel::LocalTime => "%H:%M:%S",
-        FormatLevel::Offset => "%Z",
+        FormatLevel::UtcOffset => "UTC%:z",
     };
 
     datetime.format(fmt_str).to_string()
 }
 
+/// Formats the range of dates between `start` and `end` (inclusive), leaving out the parts they
+/// have in common; e.g. "Mar 3–9, 2023" or "Dec 30, 2022 – Jan 2, 2023"
+///
+/// Only the local dates are used, so photos taken in different time zones are compared by the date
+/// where they were taken.
+pub fn format_date_range(start: DateTime<FixedOffset>, end: DateTime<FixedOffset>) -> String {
+    let (start, end) = (start.naive_local().date(), end.naive_local().date());
+
+    if start == end {
+        start.format("%b %-d, %Y").to_string()
+    } else if (start.year(), start.month()) == (end.year(), end.month()) {
+        format!("{}–{}", start.format("%b %-d"), end.format("%-d, %Y"))
+    } else if start.year() == end.year() {
+        format!("{} – {}", start.format("%b %-d"), end.format("%b %-d, %Y"))
+    } else {
+        format!(
+            "{} – {}",
+            start.format("%b %-d, %Y"),
+            end.format("%b %-d, %Y")
+        )
+    }
+}
+
+/// Formats the `DateTime` relative to `now`; e.g. "3 days ago"
+///
+/// Anything within the last minute (or in the future) is "just now". Past a day, the counting is
+/// done in calendar months, so that the same day of the next month is "1 month ago", regardless
+/// of how many days the month had.
+pub fn format_relative_datetime(datetime: DateTime<FixedOffset>, now: DateTime<Utc>) -> String {
+    fn plural(n: i64, unit: &str) -> String {
+        match n {
+            1 => format!("1 {} ago", unit),
+            _ => format!("{} {}s ago", n, unit),
+        }
+    }
+
+    let now = now.with_timezone(datetime.offset());
+    let elapsed = now - datetime;
+
+    if elapsed.num_minutes() < 1 {
+        return "just now".to_owned();
+    } else if elapsed.num_hours() < 1 {
+        return plural(elapsed.num_minutes(), "minute");
+    } else if elapsed.num_days() < 1 {
+        return plural(elapsed.num_hours(), "hour");
+    }
+
+    let mut months = (now.year() as i64 * 12 + now.month0() as i64)
+        - (datetime.year() as i64 * 12 + datetime.month0() as i64);
+    // Don't count the current month if we haven't reached the same point in it yet
+    if (now.day(), now.time()) < (datetime.day(), datetime.time()) {
+        months -= 1;
+    }
+
+    match months {
+        0 => plural(elapsed.num_days(), "day"),
+        1..=11 => plural(months, "month"),
+        _ => plural(months / 12, "year"),
+    }
+}
+
 /// Wrapper between a responder `R` and a possible indication that the requested URL has been
 /// permanently moved
 ///
@@ -92,3 +292,57 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn datetime(s: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(s).unwrap()
+    }
+
+    /// Formats `then` relative to `now`, both given as RFC 3339 strings
+    fn relative(then: &str, now: &str) -> String {
+        format_relative_datetime(datetime(then), datetime(now).with_timezone(&Utc))
+    }
+
+    #[test]
+    fn relative_datetime_minutes() {
+        let then = "2022-01-01T12:00:00-08:00";
+        assert_eq!(relative(then, "2022-01-01T11:00:00-08:00"), "just now");
+        assert_eq!(relative(then, "2022-01-01T12:00:59-08:00"), "just now");
+        assert_eq!(relative(then, "2022-01-01T12:01:00-08:00"), "1 minute ago");
+        assert_eq!(
+            relative(then, "2022-01-01T12:59:59-08:00"),
+            "59 minutes ago"
+        );
+    }
+
+    #[test]
+    fn relative_datetime_hours() {
+        let then = "2022-01-01T12:00:00-08:00";
+        assert_eq!(relative(then, "2022-01-01T13:00:00-08:00"), "1 hour ago");
+        assert_eq!(relative(then, "2022-01-02T11:59:59-08:00"), "23 hours ago");
+        // The offsets don't need to match
+        assert_eq!(relative(then, "2022-01-01T22:00:00+00:00"), "2 hours ago");
+    }
+
+    #[test]
+    fn relative_datetime_days() {
+        let then = "2022-01-31T12:00:00-08:00";
+        assert_eq!(relative(then, "2022-02-01T12:00:00-08:00"), "1 day ago");
+        // February is shorter, so the same point in the next month is never reached
+        assert_eq!(relative(then, "2022-02-28T12:00:00-08:00"), "28 days ago");
+        assert_eq!(relative(then, "2022-03-31T11:59:59-08:00"), "1 month ago");
+    }
+
+    #[test]
+    fn relative_datetime_months_and_years() {
+        let then = "2022-01-15T12:00:00-08:00";
+        assert_eq!(relative(then, "2022-02-15T11:59:59-08:00"), "30 days ago");
+        assert_eq!(relative(then, "2022-02-15T12:00:00-08:00"), "1 month ago");
+        assert_eq!(relative(then, "2023-01-15T11:59:59-08:00"), "11 months ago");
+        assert_eq!(relative(then, "2023-01-15T12:00:00-08:00"), "1 year ago");
+        assert_eq!(relative(then, "2025-01-16T00:00:00-08:00"), "3 years ago");
+    }
+}
@@ -0,0 +1,144 @@
+//! Wrapper module for the [`render_og_image`] function, which produces Open Graph preview images
+
+use anyhow::{anyhow, Context, Result};
+use image::{DynamicImage, ImageOutputFormat, Rgb, RgbImage};
+use imageproc::drawing::draw_text_mut;
+use rusttype::{Font, Scale};
+use std::fs;
+use std::path::Path;
+
+/// Width of the generated image, in pixels. 1200x630 is the size recommended by most sites that
+/// display Open Graph images.
+const OG_IMAGE_WIDTH: u32 = 1200;
+/// Height of the generated image, in pixels
+const OG_IMAGE_HEIGHT: u32 = 630;
+/// Padding around the edges of the image, in pixels
+const OG_IMAGE_PADDING: u32 = 80;
+
+const BACKGROUND_COLOR: Rgb<u8> = Rgb([0x1d, 0x1f, 0x21]);
+const TITLE_COLOR: Rgb<u8> = Rgb([0xff, 0xff, 0xff]);
+const DESCRIPTION_COLOR: Rgb<u8> = Rgb([0xb4, 0xb7, 0xb4]);
+
+const TITLE_SCALE: f32 = 72.0;
+const TITLE_MAX_LINES: usize = 3;
+const DESCRIPTION_SCALE: f32 = 40.0;
+const DESCRIPTION_MAX_LINES: usize = 3;
+
+/// Approximate width of a single character, as a fraction of the font scale
+///
+/// Measuring the rendered text exactly would be more accurate, but for wrapping, this is close
+/// enough with most proportional fonts.
+const APPROX_CHAR_WIDTH: f32 = 0.5;
+
+/// Renders a PNG image with the title and (plain text) description over a solid background,
+/// using the font at `font_path`
+///
+/// Long titles and descriptions are wrapped, and then truncated with an ellipsis if they'd take up
+/// too many lines.
+pub fn render_og_image(font_path: &Path, title: &str, description: &str) -> Result<Vec<u8>> {
+    let font_data =
+        fs::read(font_path).with_context(|| format!("failed to read font {:?}", font_path))?;
+    let font = Font::try_from_vec(font_data)
+        .ok_or_else(|| anyhow!("failed to parse font {:?}", font_path))?;
+
+    let mut img = RgbImage::from_pixel(OG_IMAGE_WIDTH, OG_IMAGE_HEIGHT, BACKGROUND_COLOR);
+    let text_width = OG_IMAGE_WIDTH - 2 * OG_IMAGE_PADDING;
+
+    let mut y = OG_IMAGE_PADDING;
+
+    let title_lines = wrap_text(title, text_width, TITLE_SCALE, TITLE_MAX_LINES);
+    for line in &title_lines {
+        let scale = Scale::uniform(TITLE_SCALE);
+        draw_text_mut(
+            &mut img,
+            TITLE_COLOR,
+            OG_IMAGE_PADDING,
+            y,
+            scale,
+            &font,
+            line,
+        );
+        y += (TITLE_SCALE * 1.2) as u32;
+    }
+
+    // Extra gap between the title and description
+    y += (DESCRIPTION_SCALE * 0.8) as u32;
+
+    let desc_lines = wrap_text(
+        description,
+        text_width,
+        DESCRIPTION_SCALE,
+        DESCRIPTION_MAX_LINES,
+    );
+    for line in &desc_lines {
+        if y + DESCRIPTION_SCALE as u32 > OG_IMAGE_HEIGHT - OG_IMAGE_PADDING {
+            break;
+        }
+
+        let scale = Scale::uniform(DESCRIPTION_SCALE);
+        draw_text_mut(
+            &mut img,
+            DESCRIPTION_COLOR,
+            OG_IMAGE_PADDING,
+            y,
+            scale,
+            &font,
+            line,
+        );
+        y += (DESCRIPTION_SCALE * 1.3) as u32;
+    }
+
+    let mut png = Vec::new();
+    DynamicImage::ImageRgb8(img)
+        .write_to(&mut png, ImageOutputFormat::Png)
+        .context("failed to encode PNG")?;
+
+    Ok(png)
+}
+
+/// Splits the text into lines that will (approximately) fit within `width` pixels at the given
+/// font scale, truncating with an ellipsis if there would be more than `max_lines`
+fn wrap_text(text: &str, width: u32, scale: f32, max_lines: usize) -> Vec<String> {
+    let max_chars = (width as f32 / (scale * APPROX_CHAR_WIDTH)) as usize;
+
+    let mut lines = Vec::new();
+    let mut current = String::new();
+
+    for word in text.split_whitespace() {
+        let needed = match current.is_empty() {
+            true => word.chars().count(),
+            false => current.chars().count() + 1 + word.chars().count(),
+        };
+
+        if needed > max_chars && !current.is_empty() {
+            lines.push(std::mem::take(&mut current));
+        }
+
+        if !current.is_empty() {
+            current.push(' ');
+        }
+
+        // Single words that are too long for a line get cut
+        if word.chars().count() > max_chars {
+            current.extend(word.chars().take(max_chars.saturating_sub(1)));
+            current.push('…');
+        } else {
+            current.push_str(word);
+        }
+    }
+
+    if !current.is_empty() {
+        lines.push(current);
+    }
+
+    if lines.len() > max_lines {
+        lines.truncate(max_lines);
+        let last = lines.last_mut().unwrap();
+        while last.chars().count() + 1 > max_chars {
+            last.pop();
+        }
+        last.push('…');
+    }
+
+    lines
+}
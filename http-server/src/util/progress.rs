@@ -0,0 +1,95 @@
+//! Progress output for long-running work, like processing all of the photos
+//!
+//! What's useful depends on where the output is going: a line that's rewritten in place is nice in
+//! a terminal, but turns into garbage in the logs from systemd or CI. By default, we pick based on
+//! whether stdout is a terminal. The `PROGRESS_OUTPUT` environment variable overrides that, with
+//! one of "tty", "lines", or "silent".
+
+use log::{info, warn};
+use std::env;
+use std::io::{self, Write};
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+/// The environment variable that sets the `ProgressMode`
+static MODE_ENV_VAR: &str = "PROGRESS_OUTPUT";
+
+/// Minimum time between progress lines in `ProgressMode::Lines`
+const LINE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How to display progress
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ProgressMode {
+    /// A single line on stdout, rewritten in place with a carriage return
+    Tty,
+    /// Periodic log lines, at most once every `LINE_INTERVAL`
+    Lines,
+    /// Nothing at all
+    Silent,
+}
+
+impl ProgressMode {
+    /// Returns the mode given by the environment variable, or the default for stdout if it's unset
+    pub fn from_env() -> Self {
+        let value = match env::var(MODE_ENV_VAR) {
+            Ok(v) => v,
+            Err(_) => return Self::default_for_stdout(),
+        };
+
+        match value.as_str() {
+            "tty" => ProgressMode::Tty,
+            "lines" => ProgressMode::Lines,
+            "silent" => ProgressMode::Silent,
+            _ => {
+                warn!(
+                    "unknown {} {:?}, expected one of \"tty\", \"lines\", or \"silent\"",
+                    MODE_ENV_VAR, value
+                );
+                Self::default_for_stdout()
+            }
+        }
+    }
+
+    fn default_for_stdout() -> Self {
+        // SAFETY: `isatty` only inspects the file descriptor, and stdout is always valid to check
+        let is_tty = unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1;
+
+        match is_tty {
+            true => ProgressMode::Tty,
+            false => ProgressMode::Lines,
+        }
+    }
+}
+
+/// Displays the progress on `total` items until the channel is closed, where each message on `rx`
+/// marks another item as done
+///
+/// `what` describes the work being done, like "processing images". Returns the number of items that
+/// were done.
+pub fn report(mode: ProgressMode, what: &str, total: usize, rx: Receiver<()>) -> usize {
+    let mut done = 0;
+    let mut last_line = Instant::now();
+
+    while let Ok(()) = rx.recv() {
+        done += 1;
+
+        match mode {
+            ProgressMode::Tty => {
+                print!("\r{}... {}/{} done", what, done, total);
+                let _ = io::stdout().flush();
+            }
+            ProgressMode::Lines if last_line.elapsed() >= LINE_INTERVAL => {
+                info!("{}... {}/{} done", what, done, total);
+                last_line = Instant::now();
+            }
+            ProgressMode::Lines | ProgressMode::Silent => (),
+        }
+    }
+
+    // Finish the line that we've been rewriting, so that the next output doesn't get appended
+    if mode == ProgressMode::Tty && done != 0 {
+        println!();
+    }
+
+    done
+}
@@ -0,0 +1,252 @@
+//! Wrapper module for [`SearchIndex`], a small in-memory full-text index
+
+use std::collections::{BTreeMap, HashMap};
+
+/// Terms shorter than this (in characters) are ignored, both in documents and in queries
+///
+/// Single letters match far too much to be useful, and they'd make the prefix matching below
+/// awfully slow.
+const MIN_TERM_LEN: usize = 2;
+
+/// Approximate number of bytes of context to include on either side of a match in a snippet
+const SNIPPET_RADIUS: usize = 80;
+
+/// An inverted index over a set of documents, each identified by a key of type `K`
+///
+/// Each document is made up of a number of weighted fields (e.g. a title might be weighted higher
+/// than the body), plus the text that snippets are taken from. Query terms match any indexed
+/// term that they're a prefix of, so that searching for "photo" also finds "photos".
+#[derive(Debug)]
+pub struct SearchIndex<K> {
+    /// For each term, the documents (by index into `docs`) containing it and the total weight of
+    /// all of its occurrences in that document
+    terms: BTreeMap<String, HashMap<usize, u32>>,
+    docs: Vec<Document<K>>,
+}
+
+#[derive(Debug)]
+struct Document<K> {
+    key: K,
+    /// The text that snippets are taken from
+    snippet_text: String,
+}
+
+/// A single result from [`SearchIndex::search`]
+#[derive(Debug)]
+pub struct SearchResult<'a, K> {
+    pub key: &'a K,
+    /// Portion of the document's snippet text around the first match, or the start of it if
+    /// there isn't a match in the snippet text
+    pub snippet: String,
+}
+
+impl<K> Default for SearchIndex<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K> SearchIndex<K> {
+    pub fn new() -> Self {
+        SearchIndex {
+            terms: BTreeMap::new(),
+            docs: Vec::new(),
+        }
+    }
+
+    /// Adds a document to the index, with each field given as `(text, weight)`
+    ///
+    /// `snippet_text` isn't indexed on its own; if it should be searchable, it must also be
+    /// provided as one of the fields.
+    pub fn add(&mut self, key: K, fields: &[(&str, u32)], snippet_text: String) {
+        let doc_idx = self.docs.len();
+        self.docs.push(Document { key, snippet_text });
+
+        for &(text, weight) in fields {
+            for (_, term) in terms(text) {
+                *self
+                    .terms
+                    .entry(term)
+                    .or_default()
+                    .entry(doc_idx)
+                    .or_default() += weight;
+            }
+        }
+    }
+
+    /// Returns up to `limit` documents matching the query, best match first
+    ///
+    /// Documents matching more of the query terms always rank above those matching fewer; within
+    /// that, they're ordered by the total weight of the matches. Queries without any usable terms
+    /// give no results.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<SearchResult<K>> {
+        let query_terms: Vec<_> = terms(query).map(|(_, t)| t).collect();
+
+        // doc index -> (number of query terms matched, total score)
+        let mut scores: HashMap<usize, (usize, u32)> = HashMap::new();
+
+        for term in &query_terms {
+            let mut term_scores: HashMap<usize, u32> = HashMap::new();
+
+            let matching = self
+                .terms
+                .range(term.clone()..)
+                .take_while(|(t, _)| t.starts_with(term.as_str()));
+
+            for (_, docs) in matching {
+                for (&doc, &weight) in docs {
+                    *term_scores.entry(doc).or_default() += weight;
+                }
+            }
+
+            for (doc, weight) in term_scores {
+                let entry = scores.entry(doc).or_default();
+                entry.0 += 1;
+                entry.1 += weight;
+            }
+        }
+
+        let mut ranked: Vec<_> = scores.into_iter().collect();
+        ranked.sort_by(|(x_doc, x_score), (y_doc, y_score)| {
+            y_score.cmp(x_score).then_with(|| x_doc.cmp(y_doc))
+        });
+
+        ranked
+            .into_iter()
+            .take(limit)
+            .map(|(doc_idx, _)| {
+                let doc = &self.docs[doc_idx];
+                SearchResult {
+                    key: &doc.key,
+                    snippet: snippet(&doc.snippet_text, &query_terms),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Splits the text into lowercase alphanumeric terms, alongside the byte offset at which each one
+/// starts, skipping any that are too short
+fn terms(text: &str) -> impl '_ + Iterator<Item = (usize, String)> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() >= MIN_TERM_LEN)
+        .map(move |word| {
+            let offset = word.as_ptr() as usize - text.as_ptr() as usize;
+            (offset, word.to_lowercase())
+        })
+}
+
+/// Produces a snippet of the text surrounding the first term that matches any of `query_terms`
+fn snippet(text: &str, query_terms: &[String]) -> String {
+    let match_offset = terms(text)
+        .find(|(_, term)| query_terms.iter().any(|q| term.starts_with(q.as_str())))
+        .map(|(offset, _)| offset)
+        .unwrap_or(0);
+
+    let mut start = match_offset.saturating_sub(SNIPPET_RADIUS);
+    let mut end = (match_offset + SNIPPET_RADIUS).min(text.len());
+
+    while !text.is_char_boundary(start) {
+        start -= 1;
+    }
+    while !text.is_char_boundary(end) {
+        end += 1;
+    }
+
+    // Expand outwards to the nearest whitespace, so we don't cut words in half
+    while start > 0 && !text[start..].starts_with(char::is_whitespace) {
+        start -= 1;
+        while !text.is_char_boundary(start) {
+            start -= 1;
+        }
+    }
+    while end < text.len() && !text[end..].starts_with(char::is_whitespace) {
+        end += 1;
+        while !text.is_char_boundary(end) {
+            end += 1;
+        }
+    }
+
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+    snippet.push_str(text[start..end].trim());
+    if end < text.len() {
+        snippet.push('…');
+    }
+
+    snippet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(results: &[SearchResult<&'static str>]) -> Vec<&'static str> {
+        results.iter().map(|r| *r.key).collect()
+    }
+
+    fn index() -> SearchIndex<&'static str> {
+        let mut index = SearchIndex::new();
+        index.add(
+            "rust",
+            &[("Writing Rust", 3), ("A post about rust and photos", 1)],
+            "A post about rust and photos".to_owned(),
+        );
+        index.add(
+            "photos",
+            &[("Photos", 3), ("Some photography", 1)],
+            "Some photography".to_owned(),
+        );
+        index.add("other", &[("Nothing here", 1)], String::new());
+        index
+    }
+
+    #[test]
+    fn tokenizing() {
+        let terms: Vec<_> = terms("Hello, wörld! A b2 x").collect();
+        assert_eq!(
+            terms,
+            [
+                (0, "hello".to_owned()),
+                (7, "wörld".to_owned()),
+                (17, "b2".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn ranking() {
+        let index = index();
+
+        // Prefix matches count, and the heavier field ranks higher
+        assert_eq!(keys(&index.search("photo", 10)), ["photos", "rust"]);
+        // Matching more terms beats a higher weight
+        assert_eq!(keys(&index.search("photos rust", 10)), ["rust", "photos"]);
+        // Case doesn't matter
+        assert_eq!(keys(&index.search("RUST", 10)), ["rust"]);
+        assert_eq!(keys(&index.search("photo", 1)), ["photos"]);
+    }
+
+    #[test]
+    fn empty_and_unmatched_queries() {
+        let index = index();
+        assert!(index.search("", 10).is_empty());
+        assert!(index.search("a ! ?", 10).is_empty());
+        assert!(index.search("zebra", 10).is_empty());
+        assert!(SearchIndex::<&str>::new().search("rust", 10).is_empty());
+    }
+
+    #[test]
+    fn snippets() {
+        let index = index();
+        let results = index.search("rust", 10);
+        assert_eq!(results[0].snippet, "A post about rust and photos");
+
+        let text = format!("{} needle {}", "word ".repeat(40), "word ".repeat(40));
+        let snippet = snippet(&text, &["needle".to_owned()]);
+        assert!(snippet.starts_with('…') && snippet.ends_with('…'));
+        assert!(snippet.contains("needle"));
+    }
+}
@@ -0,0 +1,119 @@
+//! Packing many small images into a single "sprite sheet" image
+//!
+//! Every image is scaled to the same height, and they're laid out left-to-right in rows, starting
+//! a new row whenever the next image wouldn't fit within the maximum width. This wastes a little
+//! space at the end of each row, but it keeps the images in their original order, which is nice
+//! for anything stepping through them.
+
+use anyhow::{anyhow, Result};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, RgbaImage};
+use serde::Serialize;
+
+/// Height of every image in the sheet, in pixels
+pub const ROW_HEIGHT: u32 = 240;
+/// Maximum width of the sheet, in pixels
+const MAX_WIDTH: u32 = 4096;
+/// Maximum height of the sheet, in pixels -- the largest dimension allowed by WEBP
+const MAX_HEIGHT: u32 = 16383;
+
+/// The location of a single image within the sheet
+#[derive(Debug, Copy, Clone, Serialize)]
+pub struct SpriteRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The positions of each image in a sheet, along with the size of the sheet itself
+#[derive(Debug, Clone)]
+pub struct Layout {
+    pub width: u32,
+    pub height: u32,
+    pub rects: Vec<SpriteRect>,
+}
+
+/// Lays out images with the given dimensions (as `(width, height)`), in order
+///
+/// Returns `None` if there's too many images to fit in a single sheet, or no images at all -- an
+/// empty sheet can't be encoded.
+pub fn layout(dimensions: &[(u32, u32)]) -> Option<Layout> {
+    if dimensions.is_empty() {
+        return None;
+    }
+
+    let mut rects = Vec::with_capacity(dimensions.len());
+    let (mut x, mut y) = (0, 0);
+    let mut width = 0;
+
+    for &(w, h) in dimensions {
+        let scaled_width =
+            ((w as u64 * ROW_HEIGHT as u64) / h.max(1) as u64).clamp(1, MAX_WIDTH as u64) as u32;
+
+        if x + scaled_width > MAX_WIDTH {
+            x = 0;
+            y += ROW_HEIGHT;
+        }
+
+        rects.push(SpriteRect {
+            x,
+            y,
+            width: scaled_width,
+            height: ROW_HEIGHT,
+        });
+
+        x += scaled_width;
+        width = width.max(x);
+    }
+
+    let height = y + ROW_HEIGHT;
+
+    if height > MAX_HEIGHT {
+        return None;
+    }
+
+    Some(Layout {
+        width,
+        height,
+        rects,
+    })
+}
+
+/// Draws each image into its place in the layout, producing the WEBP-encoded sheet
+///
+/// `imgs` must be in the same order as the dimensions given to [`layout`].
+pub fn render(layout: &Layout, imgs: &[DynamicImage], quality: f32) -> Result<Vec<u8>> {
+    let mut sheet = RgbaImage::new(layout.width, layout.height);
+
+    for (img, rect) in imgs.iter().zip(&layout.rects) {
+        let scaled = img.resize_exact(rect.width, rect.height, FilterType::Triangle);
+        imageops::replace(&mut sheet, &scaled.to_rgba8(), rect.x, rect.y);
+    }
+
+    let sheet = DynamicImage::ImageRgba8(sheet);
+    let webp = webp::Encoder::from_image(&sheet)
+        .map_err(|e| anyhow!("failed to encode WEBP image: {}", e))?
+        .encode(quality);
+
+    Ok(webp.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_layout() {
+        assert!(layout(&[]).is_none());
+    }
+
+    #[test]
+    fn rows_wrap_at_max_width() {
+        // Each image is scaled to 3 * ROW_HEIGHT wide, so five of them fit in a row
+        let l = layout(&[(300, 100); 6]).unwrap();
+        assert_eq!(l.rects[4].y, 0);
+        assert_eq!((l.rects[5].x, l.rects[5].y), (0, ROW_HEIGHT));
+        assert_eq!((l.width, l.height), (5 * 3 * ROW_HEIGHT, 2 * ROW_HEIGHT));
+    }
+}
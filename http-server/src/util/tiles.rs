@@ -0,0 +1,84 @@
+//! Deep Zoom Image (DZI) tiling, for viewing large images at full resolution
+//!
+//! Level 0 of the pyramid is a single pixel, and each level after that doubles in size, up to the
+//! full image at [`max_level`]. Each level is split into square tiles of `TILE_SIZE` pixels (the
+//! tiles on the right and bottom edges may be smaller). There's no overlap between tiles.
+//!
+//! See: https://docs.microsoft.com/en-us/previous-versions/windows/silverlight/dotnet-windows-silverlight/cc645077(v=vs.95)
+
+use anyhow::{Context, Result};
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageOutputFormat};
+
+/// Width and height of each tile, in pixels
+pub const TILE_SIZE: u32 = 256;
+/// JPEG quality used to encode the tiles
+const TILE_QUALITY: u8 = 85;
+
+/// Returns the highest level of the pyramid for an image with the given dimensions -- i.e. the
+/// level at which the image is displayed at full size
+pub fn max_level(width: u32, height: u32) -> u32 {
+    let largest = width.max(height).max(1);
+    // ceil(log2(largest))
+    u32::BITS - (largest - 1).leading_zeros()
+}
+
+/// Returns the dimensions of the image at the given level of the pyramid
+fn level_dimensions(width: u32, height: u32, level: u32) -> (u32, u32) {
+    let scale = 1_u64 << (max_level(width, height) - level);
+    let scaled = |d: u32| ((d as u64 + scale - 1) / scale) as u32;
+    (scaled(width), scaled(height))
+}
+
+/// Produces the DZI XML descriptor for an image with the given dimensions
+pub fn dzi_descriptor(width: u32, height: u32) -> String {
+    format!(
+        concat!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+            r#"<Image xmlns="http://schemas.microsoft.com/deepzoom/2008""#,
+            r#" Format="jpg" Overlap="0" TileSize="{}">"#,
+            r#"<Size Width="{}" Height="{}"/>"#,
+            r#"</Image>"#,
+        ),
+        TILE_SIZE, width, height
+    )
+}
+
+/// Renders the tile at column `x` and row `y` of the given level, JPEG-encoded
+///
+/// Returns `Ok(None)` if the tile is out of range for the image.
+pub fn render_tile(img: &DynamicImage, level: u32, x: u32, y: u32) -> Result<Option<Vec<u8>>> {
+    let (width, height) = img.dimensions();
+    if level > max_level(width, height) {
+        return Ok(None);
+    }
+
+    let (level_width, level_height) = level_dimensions(width, height, level);
+    let (tile_x, tile_y) = (x as u64 * TILE_SIZE as u64, y as u64 * TILE_SIZE as u64);
+    if tile_x >= level_width as u64 || tile_y >= level_height as u64 {
+        return Ok(None);
+    }
+
+    // Size of this tile at this level -- smaller than `TILE_SIZE` at the edges
+    let tile_width = (level_width - tile_x as u32).min(TILE_SIZE);
+    let tile_height = (level_height - tile_y as u32).min(TILE_SIZE);
+
+    // Rather than resizing the entire image, we only take the part of the full image that this
+    // tile covers, and then scale that down.
+    let scale = 1_u64 << (max_level(width, height) - level);
+    let crop_x = (tile_x * scale).min(width as u64) as u32;
+    let crop_y = (tile_y * scale).min(height as u64) as u32;
+    let crop_width = ((tile_width as u64 * scale) as u32).min(width - crop_x);
+    let crop_height = ((tile_height as u64 * scale) as u32).min(height - crop_y);
+
+    let mut tile = img.crop_imm(crop_x, crop_y, crop_width, crop_height);
+    if scale > 1 {
+        tile = tile.resize_exact(tile_width, tile_height, FilterType::Triangle);
+    }
+
+    let mut data = Vec::new();
+    tile.write_to(&mut data, ImageOutputFormat::Jpeg(TILE_QUALITY))
+        .context("failed to encode tile")?;
+
+    Ok(Some(data))
+}
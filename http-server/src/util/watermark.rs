@@ -0,0 +1,126 @@
+//! Drawing a text watermark over full-size photos
+//!
+//! The text is drawn in the bottom-right corner, scaled relative to the size of the image so that
+//! it looks the same regardless of resolution. It's partially transparent, with a dark shadow so
+//! that it's still visible on light backgrounds.
+
+use anyhow::{anyhow, Context, Result};
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, Rgb, RgbImage};
+use imageproc::drawing::draw_text_mut;
+use rusttype::{point, Font, Scale};
+use std::fs;
+use std::path::Path;
+
+use super::content_hash;
+
+/// Height of the text, as a fraction of the image's smaller dimension
+const TEXT_SCALE: f32 = 0.04;
+/// Distance of the text from the edges of the image, as a multiple of its height
+const MARGIN: f32 = 0.5;
+/// Offset of the shadow from the text, as a fraction of its height
+const SHADOW_OFFSET: f32 = 0.06;
+/// Opacity of the watermark, from 0 (invisible) to 1 (fully opaque)
+const OPACITY: f32 = 0.6;
+/// JPEG quality to encode the watermarked image with
+const JPEG_QUALITY: u8 = 90;
+
+const TEXT_COLOR: Rgb<u8> = Rgb([0xff, 0xff, 0xff]);
+const SHADOW_COLOR: Rgb<u8> = Rgb([0x00, 0x00, 0x00]);
+
+/// A loaded font and the text to draw with it
+pub struct Watermark {
+    font: Font<'static>,
+    text: String,
+    /// Hash of the font and text; see `Watermark::key`
+    key: String,
+}
+
+impl Watermark {
+    /// Loads the font at `font_path`, for drawing `text`
+    pub fn load(font_path: &Path, text: &str) -> Result<Self> {
+        let font_data =
+            fs::read(font_path).with_context(|| format!("failed to read font {:?}", font_path))?;
+        let key = content_hash(&[font_data.as_slice(), b"\0", text.as_bytes()].concat());
+        let font = Font::try_from_vec(font_data)
+            .ok_or_else(|| anyhow!("failed to parse font {:?}", font_path))?;
+
+        Ok(Watermark {
+            font,
+            text: text.to_owned(),
+            key,
+        })
+    }
+
+    /// Returns a hash identifying the watermark, which changes whenever the font or text does
+    ///
+    /// This is included in the revisions of watermarked images, so that changing the watermark
+    /// changes their URLs.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Draws the watermark on the image, and encodes the result as a JPEG
+    ///
+    /// Decoding is left to the caller, so that it can limit the size of what's decoded.
+    pub fn apply(&self, img: DynamicImage) -> Result<Vec<u8>> {
+        let original = img.into_rgb8();
+        let (width, height) = original.dimensions();
+
+        let text_height = (width.min(height) as f32 * TEXT_SCALE).max(12.0);
+        let scale = Scale::uniform(text_height);
+        let text_width = self.text_width(scale);
+
+        let margin = (text_height * MARGIN) as i32;
+        let x = width as i32 - text_width as i32 - margin;
+        let y = height as i32 - text_height as i32 - margin;
+        if x < 0 || y < 0 {
+            // Only possible for tiny images, which aren't worth protecting anyways
+            return Self::encode(original);
+        }
+
+        // Draw everything fully opaque on a copy, and then blend it back in with the original, so
+        // that the overlapping parts of the text and shadow aren't darker than the rest
+        let mut marked = original.clone();
+        let shadow = (text_height * SHADOW_OFFSET).max(1.0) as i32;
+        let (x, y) = (x as u32, y as u32);
+        draw_text_mut(
+            &mut marked,
+            SHADOW_COLOR,
+            x + shadow as u32,
+            y + shadow as u32,
+            scale,
+            &self.font,
+            &self.text,
+        );
+        draw_text_mut(&mut marked, TEXT_COLOR, x, y, scale, &self.font, &self.text);
+
+        let blended = RgbImage::from_fn(width, height, |px, py| {
+            let (Rgb(o), Rgb(m)) = (original.get_pixel(px, py), marked.get_pixel(px, py));
+            let mix = |i: usize| (o[i] as f32 + (m[i] as f32 - o[i] as f32) * OPACITY) as u8;
+            Rgb([mix(0), mix(1), mix(2)])
+        });
+
+        Self::encode(blended)
+    }
+
+    fn encode(img: RgbImage) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY)
+            .encode_image(&DynamicImage::ImageRgb8(img))
+            .context("failed to encode JPEG")?;
+
+        Ok(out)
+    }
+
+    /// Returns the width of the rendered text at the given scale, in pixels
+    fn text_width(&self, scale: Scale) -> u32 {
+        self.font
+            .layout(&self.text, scale, point(0.0, 0.0))
+            .filter_map(|g| g.pixel_bounding_box())
+            .map(|bb| bb.max.x)
+            .max()
+            .unwrap_or(0)
+            .max(0) as u32
+    }
+}
@@ -0,0 +1,471 @@
+//! Streaming ZIP archives of files on disk, for downloading whole albums at once
+//!
+//! The archive is produced on the fly as it's read, so only one file is open (and only a single
+//! read buffer's worth of it is in memory) at a time. Entries are stored without compression --
+//! everything we put in here is already compressed image data -- and their sizes & CRCs are given
+//! in a data descriptor after each one, so that nothing needs to be read ahead of time.
+//!
+//! ZIP64 isn't supported, so archives are limited to 65535 entries and just under 4 GiB.
+
+use anyhow::{bail, Context, Result};
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x08074b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIR_SIGNATURE: u32 = 0x06054b50;
+
+/// Version 2.0: the minimum for data descriptors
+const ZIP_VERSION: u16 = 20;
+/// General purpose flags for every entry: bit 3 (sizes & CRC are in the data descriptor) and bit
+/// 11 (the name is UTF-8)
+const ENTRY_FLAGS: u16 = (1 << 3) | (1 << 11);
+
+/// Size of each part of the archive that isn't file content, excluding the entry names
+const LOCAL_HEADER_SIZE: u64 = 30;
+const DATA_DESCRIPTOR_SIZE: u64 = 16;
+const CENTRAL_HEADER_SIZE: u64 = 46;
+const END_OF_CENTRAL_DIR_SIZE: u64 = 22;
+
+/// A single file to include in the archive
+pub struct ZipEntry {
+    /// The name of the file within the archive
+    pub name: String,
+    /// The file on disk
+    pub path: PathBuf,
+    /// The modification time to record for the file
+    pub modified: NaiveDateTime,
+}
+
+/// A ZIP archive that's written as it's read. See the module-level docs for more.
+pub struct ZipStream {
+    entries: Vec<ZipEntry>,
+    /// Index in `entries` of the next entry to start
+    next_entry: usize,
+    /// The entry whose content is currently being read
+    current: Option<CurrentEntry>,
+    /// Bytes that have been generated but not yet read
+    pending: Vec<u8>,
+    /// How much of `pending` has already been read
+    pending_pos: usize,
+    /// The central directory, built up as each entry is finished
+    central_dir: Vec<u8>,
+    /// Total number of bytes produced so far
+    offset: u64,
+    /// Whether the central directory has been added to `pending`, which marks the end
+    finished: bool,
+}
+
+struct CurrentEntry {
+    file: File,
+    /// Offset of the entry's local header from the start of the archive
+    header_offset: u64,
+    crc: Crc32,
+    size: u64,
+}
+
+impl ZipStream {
+    /// Creates the archive of all the entries, checking that it'll fit within the limits of the
+    /// format
+    ///
+    /// The files aren't opened until they're needed, so any problems with them after this point
+    /// surface as errors from `read`.
+    pub fn new(entries: Vec<ZipEntry>) -> Result<Self> {
+        if entries.len() > u16::MAX as usize {
+            bail!("too many files for a ZIP archive ({})", entries.len());
+        }
+
+        let mut total_size = END_OF_CENTRAL_DIR_SIZE;
+        for e in &entries {
+            let file_size = fs::metadata(&e.path)
+                .with_context(|| format!("failed to get metadata for {:?}", e.path))?
+                .len();
+            let overhead = LOCAL_HEADER_SIZE + DATA_DESCRIPTOR_SIZE + CENTRAL_HEADER_SIZE;
+            total_size += file_size + overhead + 2 * e.name.len() as u64;
+        }
+
+        if total_size > u32::MAX as u64 {
+            bail!(
+                "files are too large for a ZIP archive ({} bytes)",
+                total_size
+            );
+        }
+
+        Ok(ZipStream {
+            entries,
+            next_entry: 0,
+            current: None,
+            pending: Vec::new(),
+            pending_pos: 0,
+            central_dir: Vec::new(),
+            offset: 0,
+            finished: false,
+        })
+    }
+
+    /// Opens the next entry's file and adds its local header to `pending`
+    fn start_entry(&mut self) -> io::Result<()> {
+        let entry = &self.entries[self.next_entry];
+        let file = File::open(&entry.path)?;
+        let (time, date) = dos_datetime(entry.modified);
+
+        let buf = &mut self.pending;
+        put_u32(buf, LOCAL_HEADER_SIGNATURE);
+        put_u16(buf, ZIP_VERSION);
+        put_u16(buf, ENTRY_FLAGS);
+        put_u16(buf, 0); // compression method: stored
+        put_u16(buf, time);
+        put_u16(buf, date);
+        put_u32(buf, 0); // CRC, compressed size, and uncompressed size are all in the descriptor
+        put_u32(buf, 0);
+        put_u32(buf, 0);
+        put_u16(buf, entry.name.len() as u16);
+        put_u16(buf, 0); // extra field length
+        buf.extend_from_slice(entry.name.as_bytes());
+
+        self.current = Some(CurrentEntry {
+            file,
+            header_offset: self.offset,
+            crc: Crc32::new(),
+            size: 0,
+        });
+        self.next_entry += 1;
+        Ok(())
+    }
+
+    /// Adds the data descriptor for the finished entry to `pending`, and its header to the central
+    /// directory
+    fn finish_entry(&mut self, finished: CurrentEntry) -> io::Result<()> {
+        let entry = &self.entries[self.next_entry - 1];
+        let (time, date) = dos_datetime(entry.modified);
+        let crc = finished.crc.finish();
+        let size = to_u32(finished.size)?;
+        let header_offset = to_u32(finished.header_offset)?;
+
+        let buf = &mut self.pending;
+        put_u32(buf, DATA_DESCRIPTOR_SIGNATURE);
+        put_u32(buf, crc);
+        put_u32(buf, size); // compressed size
+        put_u32(buf, size); // uncompressed size
+
+        let buf = &mut self.central_dir;
+        put_u32(buf, CENTRAL_HEADER_SIGNATURE);
+        put_u16(buf, ZIP_VERSION); // version made by
+        put_u16(buf, ZIP_VERSION); // version needed to extract
+        put_u16(buf, ENTRY_FLAGS);
+        put_u16(buf, 0); // compression method: stored
+        put_u16(buf, time);
+        put_u16(buf, date);
+        put_u32(buf, crc);
+        put_u32(buf, size);
+        put_u32(buf, size);
+        put_u16(buf, entry.name.len() as u16);
+        put_u16(buf, 0); // extra field length
+        put_u16(buf, 0); // comment length
+        put_u16(buf, 0); // disk number
+        put_u16(buf, 0); // internal attributes
+        put_u32(buf, 0); // external attributes
+        put_u32(buf, header_offset);
+        buf.extend_from_slice(entry.name.as_bytes());
+
+        Ok(())
+    }
+
+    /// Adds the central directory and the record marking the end of it to `pending`
+    fn finish_archive(&mut self) -> io::Result<()> {
+        let central_dir_offset = to_u32(self.offset)?;
+        let central_dir_size = to_u32(self.central_dir.len() as u64)?;
+        let num_entries = self.entries.len() as u16;
+
+        let buf = &mut self.pending;
+        buf.append(&mut self.central_dir);
+        put_u32(buf, END_OF_CENTRAL_DIR_SIGNATURE);
+        put_u16(buf, 0); // this disk number
+        put_u16(buf, 0); // disk with the central directory
+        put_u16(buf, num_entries); // entries on this disk
+        put_u16(buf, num_entries); // total entries
+        put_u32(buf, central_dir_size);
+        put_u32(buf, central_dir_offset);
+        put_u16(buf, 0); // comment length
+
+        self.finished = true;
+        Ok(())
+    }
+}
+
+impl Read for ZipStream {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.pending_pos < self.pending.len() {
+                let remaining = &self.pending[self.pending_pos..];
+                let n = remaining.len().min(out.len());
+                out[..n].copy_from_slice(&remaining[..n]);
+                self.pending_pos += n;
+                self.offset += n as u64;
+                return Ok(n);
+            }
+
+            self.pending.clear();
+            self.pending_pos = 0;
+
+            if let Some(current) = &mut self.current {
+                let n = current.file.read(out)?;
+                if n != 0 {
+                    current.crc.update(&out[..n]);
+                    current.size += n as u64;
+                    self.offset += n as u64;
+                    return Ok(n);
+                }
+
+                let finished = self.current.take().unwrap();
+                self.finish_entry(finished)?;
+            } else if self.next_entry < self.entries.len() {
+                self.start_entry()?;
+            } else if !self.finished {
+                self.finish_archive()?;
+            } else {
+                return Ok(0);
+            }
+        }
+    }
+}
+
+/// Converts a size or offset to the 32 bits available in the archive, producing an error if the
+/// files grew after we checked them in `ZipStream::new`
+fn to_u32(x: u64) -> io::Result<u32> {
+    u32::try_from(x).map_err(|_| io::Error::new(io::ErrorKind::Other, "ZIP archive is too large"))
+}
+
+/// Returns the MS-DOS `(time, date)` representation of the date & time, as used by ZIP
+///
+/// The representation can't go before 1980, so anything earlier is clamped to the start of it.
+fn dos_datetime(dt: NaiveDateTime) -> (u16, u16) {
+    if dt.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+
+    let time = (dt.hour() << 11) | (dt.minute() << 5) | (dt.second() / 2);
+    let date = (((dt.year() - 1980) as u32) << 9) | (dt.month() << 5) | dt.day();
+    (time as u16, date as u16)
+}
+
+fn put_u16(buf: &mut Vec<u8>, x: u16) {
+    buf.extend_from_slice(&x.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, x: u32) {
+    buf.extend_from_slice(&x.to_le_bytes());
+}
+
+/// Running CRC-32 (the IEEE polynomial, as used by ZIP)
+struct Crc32(u32);
+
+/// Lookup table for `Crc32`, indexed by the low byte of the running value XORed with the next
+/// input byte
+static CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = match c & 1 {
+                1 => 0xedb88320 ^ (c >> 1),
+                _ => c >> 1,
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+impl Crc32 {
+    fn new() -> Self {
+        Crc32(!0)
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for &b in data {
+            self.0 = CRC32_TABLE[((self.0 ^ b as u32) & 0xff) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn u16_at(data: &[u8], pos: usize) -> u16 {
+        u16::from_le_bytes([data[pos], data[pos + 1]])
+    }
+
+    fn u32_at(data: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+    }
+
+    /// Writes the files into a fresh directory for the test, returning the entries for them
+    fn write_entries(test_name: &str, files: &[(&str, &[u8])]) -> Vec<ZipEntry> {
+        let dir = std::env::temp_dir().join(format!("zip-{}-{}", test_name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let modified = NaiveDate::from_ymd(2021, 8, 7).and_hms(13, 27, 46);
+        (files.iter().enumerate())
+            .map(|(i, (name, content))| {
+                let path = dir.join(i.to_string());
+                fs::write(&path, content).unwrap();
+                ZipEntry {
+                    name: name.to_string(),
+                    path,
+                    modified,
+                }
+            })
+            .collect()
+    }
+
+    /// Reads the whole archive, a few bytes at a time so that every part is split across reads
+    fn read_all(entries: Vec<ZipEntry>) -> Vec<u8> {
+        let mut zip = ZipStream::new(entries).unwrap();
+        let (mut data, mut buf) = (Vec::new(), [0; 7]);
+        loop {
+            match zip.read(&mut buf).unwrap() {
+                0 => return data,
+                n => data.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    /// Reads the archive back from the end of central directory record, checking that every
+    /// offset and size in it is consistent, and returns the name & content of each entry
+    fn read_archive(data: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let eocd = data.len() - END_OF_CENTRAL_DIR_SIZE as usize;
+        assert_eq!(u32_at(data, eocd), END_OF_CENTRAL_DIR_SIGNATURE);
+        let num_entries = u16_at(data, eocd + 10) as usize;
+        assert_eq!(u16_at(data, eocd + 8), num_entries as u16);
+        let central_dir_size = u32_at(data, eocd + 12) as usize;
+        let central_dir_offset = u32_at(data, eocd + 16) as usize;
+        assert_eq!(central_dir_offset + central_dir_size, eocd);
+
+        let mut files = Vec::new();
+        let mut pos = central_dir_offset;
+        // Each entry should start right where the last one ended
+        let mut expected_header_offset = 0;
+        for _ in 0..num_entries {
+            assert_eq!(u32_at(data, pos), CENTRAL_HEADER_SIGNATURE);
+            let crc = u32_at(data, pos + 16);
+            let size = u32_at(data, pos + 20) as usize;
+            let name_len = u16_at(data, pos + 28) as usize;
+            let header_offset = u32_at(data, pos + 42) as usize;
+            let name = &data[pos + 46..pos + 46 + name_len];
+            pos += CENTRAL_HEADER_SIZE as usize + name_len;
+
+            let local = header_offset;
+            assert_eq!(local, expected_header_offset);
+            assert_eq!(u32_at(data, local), LOCAL_HEADER_SIGNATURE);
+            assert_eq!(u16_at(data, local + 6), ENTRY_FLAGS);
+            assert_eq!(u16_at(data, local + 26) as usize, name_len);
+            let content_start = local + LOCAL_HEADER_SIZE as usize + name_len;
+            assert_eq!(
+                &data[local + LOCAL_HEADER_SIZE as usize..content_start],
+                name
+            );
+
+            let content = &data[content_start..content_start + size];
+            let mut actual_crc = Crc32::new();
+            actual_crc.update(content);
+            assert_eq!(actual_crc.finish(), crc);
+
+            let descriptor = content_start + size;
+            assert_eq!(u32_at(data, descriptor), DATA_DESCRIPTOR_SIGNATURE);
+            assert_eq!(u32_at(data, descriptor + 4), crc);
+            assert_eq!(u32_at(data, descriptor + 8) as usize, size);
+            assert_eq!(u32_at(data, descriptor + 12) as usize, size);
+            expected_header_offset = descriptor + DATA_DESCRIPTOR_SIZE as usize;
+
+            let name = String::from_utf8(name.to_vec()).unwrap();
+            files.push((name, content.to_vec()));
+        }
+
+        assert_eq!(expected_header_offset, central_dir_offset);
+        assert_eq!(pos, eocd);
+        files
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        let mut crc = Crc32::new();
+        crc.update(b"123456789");
+        assert_eq!(crc.finish(), 0xCBF43926);
+
+        // Split across updates
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xCBF43926);
+
+        assert_eq!(Crc32::new().finish(), 0);
+    }
+
+    #[test]
+    fn empty_archive() {
+        let data = read_all(Vec::new());
+
+        // Just the end of central directory record, with everything zero
+        assert_eq!(data.len(), END_OF_CENTRAL_DIR_SIZE as usize);
+        assert_eq!(u32_at(&data, 0), END_OF_CENTRAL_DIR_SIGNATURE);
+        assert!(data[4..].iter().all(|&b| b == 0));
+        assert!(read_archive(&data).is_empty());
+    }
+
+    #[test]
+    fn single_entry_offsets() {
+        let data = read_all(write_entries("single", &[("a.jpg", b"hello")]));
+
+        let name_len = "a.jpg".len() as u64;
+        let central_dir_offset = LOCAL_HEADER_SIZE + name_len + 5 + DATA_DESCRIPTOR_SIZE;
+        let eocd_offset = central_dir_offset + CENTRAL_HEADER_SIZE + name_len;
+        assert_eq!(data.len() as u64, eocd_offset + END_OF_CENTRAL_DIR_SIZE);
+
+        let eocd = eocd_offset as usize;
+        assert_eq!(u16_at(&data, eocd + 10), 1);
+        assert_eq!(u32_at(&data, eocd + 16) as u64, central_dir_offset);
+        assert_eq!(u32_at(&data, central_dir_offset as usize + 42), 0);
+
+        // 13:27:46 on Aug 7, 2021
+        let central = central_dir_offset as usize;
+        assert_eq!(u16_at(&data, central + 12), (13 << 11) | (27 << 5) | 23);
+        assert_eq!(u16_at(&data, central + 14), (41 << 9) | (8 << 5) | 7);
+
+        let files = read_archive(&data);
+        assert_eq!(files, [("a.jpg".to_owned(), b"hello".to_vec())]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let big: Vec<u8> = (0..100_000_u32).map(|i| (i * 7) as u8).collect();
+        let files: &[(&str, &[u8])] = &[
+            ("first.jpg", b"some image data"),
+            ("empty.txt", b""),
+            ("photo – ünicode.png", &big),
+        ];
+
+        let data = read_all(write_entries("round-trip", files));
+
+        let read = read_archive(&data);
+        assert_eq!(read.len(), files.len());
+        for ((name, content), (expected_name, expected_content)) in read.iter().zip(files) {
+            assert_eq!(name, expected_name);
+            assert_eq!(content.as_slice(), *expected_content);
+        }
+    }
+}
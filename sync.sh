@@ -80,15 +80,25 @@ cmd_output="$($actual_cmd | indent "$INDENT_STR" | tee /dev/tty)"
 
 echo ':: Done'
 
+# Changes to just the default flex-grid settings don't need the photos to be re-processed
+FLEXGRID_CONFIG="content/photos/default-flex-grid-config.json"
+
 updates=""
-if grep -q "^${INDENT_STR}content/photos/." <(echo "$cmd_output"); then
+if grep -v "^${INDENT_STR}${FLEXGRID_CONFIG}$" <(echo "$cmd_output") \
+        | grep -q "^${INDENT_STR}content/photos/."; then
     updates="photos"
+elif grep -q "^${INDENT_STR}${FLEXGRID_CONFIG}$" <(echo "$cmd_output"); then
+    updates="config"
 fi
 
 if grep -qE "^${INDENT_STR}(deleting |)content/blog-posts/." <(echo "$cmd_output"); then
     updates="$updates blog"
 fi
 
+if grep -qE "^${INDENT_STR}(deleting |)static/." <(echo "$cmd_output"); then
+    updates="$updates static"
+fi
+
 if [[ ! -z "$updates" ]]; then
 
     echo ":: Send update signal '$updates'..."